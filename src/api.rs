@@ -1,42 +1,49 @@
-use crate::models::{DailyStat, Pipeline};
+use crate::db::{duration_percentiles, DurationGroup};
+use crate::models::{
+    DurationBucketCount, DurationTrendPoint, FlakyExample, Granularity, JobFilter, Pipeline, PipelineCursor,
+    PipelineFilter, PipelinePage, PipelineSort, RecoveryStat, SortOrder,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
 use crate::state::AppState;
 use axum::{
     extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
-use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use chrono::TimeZone;
 
-#[derive(Deserialize, Clone, Debug)]
-pub struct PipelineFilter {
-    project_name: Option<String>,
-    ref_name: Option<String>,
-    exclude_projects: Option<String>,
-    status: Option<String>,
-    from_ts: Option<i64>,
-    to_ts: Option<i64>,
-}
+/// Page size of `/api/pipelines` when `limit` is not given, and the largest one accepted.
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct ProjectStat {
-    pub project_name: String,
-    pub count: i64,
-    pub avg_duration: f64,
-    pub last_status: String,
+/// Flaky commits listed per project and ref by `/api/stats/flaky`.
+const FLAKY_EXAMPLES: usize = 3;
+
+/// Response header carrying the cursor of the next `/api/pipelines` page.
+const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TrendQuery {
+    granularity: Option<Granularity>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct SummaryStat {
-    pub total_count: i64,
-    pub avg_duration: f64,
-    pub success_rate: f64,
+#[derive(Deserialize, Clone, Debug)]
+pub struct PipelinePageQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<PipelineSort>,
+    order: Option<SortOrder>,
 }
 
 #[derive(Serialize)]
 pub struct PipelineResponse {
     pub id: i64,
+    /// GitLab instance; unset once the instance is no longer configured.
+    pub instance: Option<String>,
     pub project_id: i64,
     pub project_name: String,
     pub project_full_path: String,
@@ -45,9 +52,62 @@ pub struct PipelineResponse {
     pub user_name: String,
     pub status: String,
     pub created_at: String,
+    pub started_at: Option<String>,
     pub finished_at: Option<String>,
     pub duration: Option<i64>,
+    pub queued_duration: Option<i64>,
     pub web_url: Option<String>,
+    pub source: Option<String>,
+    pub coverage: Option<f64>,
+}
+
+/// One page of `/api/pipelines`, returned instead of the bare array once the caller pages
+/// with `limit` or `cursor`.
+#[derive(Serialize)]
+pub struct PipelineListResponse {
+    pub items: Vec<PipelineResponse>,
+    /// `cursor` of the next page; unset on the last one.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct JobResponse {
+    pub id: i64,
+    pub instance: Option<String>,
+    pub pipeline_id: i64,
+    pub project_id: i64,
+    pub project_full_path: String,
+    pub ref_name: String,
+    pub name: String,
+    pub stage: String,
+    pub status: String,
+    pub runner_id: Option<i64>,
+    pub runner_description: Option<String>,
+    pub created_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub duration: Option<f64>,
+    pub queued_duration: Option<f64>,
+    pub retried: bool,
+    pub allow_failure: bool,
+    pub web_url: Option<String>,
+}
+
+/// A stored project, or one only known from its pipelines (with no `state`).
+#[derive(Serialize)]
+pub struct ProjectResponse {
+    pub id: i64,
+    pub instance: Option<String>,
+    pub name: String,
+    pub full_path: String,
+    pub default_branch: Option<String>,
+    pub visibility: Option<String>,
+    pub archived: bool,
+    pub topics: Vec<String>,
+    /// `active`, `archived`, `deleted` or `unmonitored`.
+    pub state: Option<String>,
+    /// Path before the last rename.
+    pub previous_full_path: Option<String>,
 }
 
 pub fn app_router(state: AppState) -> Router {
@@ -55,48 +115,39 @@ pub fn app_router(state: AppState) -> Router {
         .route("/api/pipelines", get(list_pipelines))
         .route("/api/refresh_daily_stats", post(trigger_refresh_daily_stats))
         .route("/api/stats/trend", get(get_stats_trend))
+        .route("/api/stats/duration_trend", get(get_duration_trend))
         .route("/api/stats/projects", get(get_project_stats))
         .route("/api/stats/summary", get(get_summary_stats))
+        .route("/api/stats/jobs", get(get_job_stats))
+        .route("/api/stats/flaky", get(get_flaky_stats))
+        .route("/api/stats/recovery", get(get_recovery_stats))
+        .route("/api/stats/test_trend", get(get_test_trend))
+        .route("/api/stats/test_suites", get(get_test_suite_stats))
+        .route("/api/stats/coverage", get(get_coverage_trend))
+        .route("/api/dora", get(get_dora))
+        .route("/api/merge_requests", get(get_merge_request_stats))
+        .route("/api/jobs", get(list_jobs))
+        .route("/api/runners", get(get_runners))
         .route("/api/projects", get(list_projects))
         .route("/api/refs", get(list_refs))
+        .route("/metrics", get(get_metrics))
+        .route("/webhooks/gitlab", post(crate::webhook::receive_gitlab_hook))
+        .route("/webhooks/gitlab/:instance", post(crate::webhook::receive_instance_hook))
         .with_state(state)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct StatusCount {
-    pub status: String,
-    pub count: i64,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct FailProject {
-    pub project_name: String,
-    pub count: i64,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct BucketCount {
-    pub bucket: String,
-    pub count: i64,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct ProjectHealth {
-    pub project_name: String,
-    pub success_rate: f64,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FailedJobLog {
-    pub time: String,
-    pub level: String,
-    pub project: String,
-    pub message: String,
-    pub pipeline_id: i64,
+async fn get_metrics(State(state): State<AppState>) -> Response {
+    match crate::metrics::render(&state).await {
+        Ok(body) => ([(header::CONTENT_TYPE, crate::metrics::CONTENT_TYPE)], body).into_response(),
+        Err(e) => {
+            tracing::error!("metrics rendering failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
 
 async fn trigger_refresh_daily_stats(State(state): State<AppState>) -> Json<&'static str> {
-    match crate::db::backfill_daily_stats(&state.db).await {
+    match state.db.rebuild_rollups(crate::retention::pipelines_floor(&state.config), None).await {
         Ok(_) => Json("daily_stats backfill triggered/completed"),
         Err(e) => {
             tracing::error!("daily_stats backfill failed: {}", e);
@@ -108,544 +159,594 @@ async fn trigger_refresh_daily_stats(State(state): State<AppState>) -> Json<&'st
 async fn get_project_stats(
     State(state): State<AppState>,
     Query(filter): Query<PipelineFilter>,
-) -> Json<Vec<ProjectStat>> {
-    let use_fast_path = filter.ref_name.as_deref().unwrap_or("All") == "All";
+) -> Response {
+    cached_json(&state, cache_key("projects", &filter), async {
+        let mut stats = state.db.project_stats(&filter).await?;
+        let buckets = group_duration_buckets(state.db.duration_buckets(&filter, DurationGroup::Project).await?);
+        for s in &mut stats {
+            let p = duration_percentiles(buckets.get(&s.project_full_path).map(Vec::as_slice).unwrap_or_default());
+            (s.p50_duration, s.p90_duration, s.p95_duration, s.p99_duration) = (p.p50, p.p90, p.p95, p.p99);
+        }
+        Ok(stats)
+    }).await
+}
 
-    // Build a cache key from filters
-    let key = format!("projects:{:?}:{:?}:{:?}:{:?}:{:?}",
-        filter.project_name.as_deref().unwrap_or("All"),
-        filter.ref_name.as_deref().unwrap_or("All"),
-        filter.exclude_projects.as_deref().unwrap_or(""),
-        filter.from_ts,
-        filter.to_ts,
-    );
+async fn get_summary_stats(
+    State(state): State<AppState>,
+    Query(filter): Query<PipelineFilter>,
+) -> Response {
+    cached_json(&state, cache_key("summary", &filter), async {
+        let mut stats = state.db.summary_stats(&filter).await?;
+        let buckets = group_duration_buckets(state.db.duration_buckets(&filter, DurationGroup::All).await?);
+        let p = duration_percentiles(buckets.get("").map(Vec::as_slice).unwrap_or_default());
+        (stats.p50_duration, stats.p90_duration, stats.p95_duration, stats.p99_duration) = (p.p50, p.p90, p.p95, p.p99);
+        Ok(stats)
+    }).await
+}
 
-    // Attempt to get cached value
-    if let Some(cached) = state.cache.get(&key) {
-        if let Ok(v) = serde_json::from_value::<Vec<ProjectStat>>(cached.clone()) {
-            return Json(v);
+async fn list_pipelines(
+    State(state): State<AppState>,
+    Query(filter): Query<PipelineFilter>,
+    Query(query): Query<PipelinePageQuery>,
+) -> Response {
+    let after = match query.cursor.as_deref().map(str::parse::<PipelineCursor>).transpose() {
+        Ok(after) => after,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let page = PipelinePage {
+        sort: query.sort.unwrap_or_default(),
+        order: query.order.unwrap_or_default(),
+        limit: query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE),
+        after,
+    };
+
+    let mut pipelines = match state.db.list_pipelines(&filter, &page).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("list_pipelines query failed: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
-    }
+    };
 
-    let mut query_builder = if use_fast_path {
-        sqlx::QueryBuilder::new(
-            r#"
-            SELECT 
-                project_full_path as project_name, 
-                project_full_path,
-                SUM(count) as count, 
-                    COALESCE(CAST(SUM(total_duration) AS REAL) / NULLIF(SUM(count_with_duration), 0), 0) as avg_duration,
-                (SELECT status FROM pipelines p2 WHERE p2.project_full_path = daily_stats.project_full_path ORDER BY created_at DESC LIMIT 1) as last_status
-            FROM daily_stats 
-            WHERE 1=1
-            "#
-        )
+    // list_pipelines returns one row past the page when more follow
+    let next_cursor = if pipelines.len() as i64 > page.limit {
+        pipelines.truncate(page.limit as usize);
+        pipelines.last().map(|p| page.cursor_after(p).to_string())
     } else {
-        sqlx::QueryBuilder::new(
-            r#"
-            SELECT 
-                project_name, 
-                project_full_path,
-                COUNT(*) as count, 
-                AVG(duration) as avg_duration,
-                (SELECT status FROM pipelines p2 WHERE p2.project_full_path = pipelines.project_full_path ORDER BY created_at DESC LIMIT 1) as last_status
-            FROM pipelines 
-            WHERE 1=1
-            "#
-        )
+        None
     };
 
-    if let Some(p) = &filter.project_name {
-            if p != "All" && !p.is_empty() {
-            if p.contains(',') {
-                let projects: Vec<&str> = p.split(',').map(|s| s.trim()).collect();
-                if !projects.is_empty() {
-                    query_builder.push(" AND project_full_path IN (");
-                    let mut separated = query_builder.separated(", ");
-                    for proj in projects {
-                        separated.push_bind(proj);
-                    }
-                    separated.push_unseparated(") ");
-                }
-            } else {
-                query_builder.push(" AND project_full_path = ");
-                query_builder.push_bind(p);
-            }
-        }
-    }
-    
-    if !use_fast_path {
-        if let Some(r) = &filter.ref_name {
-            if r != "All" && !r.is_empty() {
-                if r.contains(',') {
-                    let refs: Vec<&str> = r.split(',').map(|s| s.trim()).collect();
-                    if !refs.is_empty() {
-                        query_builder.push(" AND ref_name IN (");
-                        let mut separated = query_builder.separated(", ");
-                        for rv in refs {
-                            separated.push_bind(rv);
-                        }
-                        separated.push_unseparated(") ");
-                    }
-                } else {
-                    query_builder.push(" AND ref_name = ");
-                    query_builder.push_bind(r);
-                }
-            }
-        }
-    }
-    
-    if let Some(ex) = &filter.exclude_projects {
-        if !ex.is_empty() {
-            let projects: Vec<&str> = ex.split(',').collect();
-            if !projects.is_empty() {
-                query_builder.push(" AND project_full_path NOT IN (");
-                let mut separated = query_builder.separated(", ");
-                for p in projects {
-                    separated.push_bind(p);
-                }
-                separated.push_unseparated(") ");
-            }
-        }
-    }
+    let items: Vec<PipelineResponse> = pipelines.into_iter().map(|p| {
+        let created = chrono::Utc
+            .timestamp_opt(p.created_at, 0)
+            .single()
+            .map(|dt| dt.to_rfc3339())
+            .unwrap_or_default();
+        let started = p.started_at.and_then(|ts| chrono::Utc.timestamp_opt(ts, 0).single().map(|dt| dt.to_rfc3339()));
+        let finished = p.finished_at.and_then(|ts| chrono::Utc.timestamp_opt(ts, 0).single().map(|dt| dt.to_rfc3339()));
 
-    if use_fast_path {
-        if let Some(ts) = filter.from_ts {
-            query_builder.push(" AND date >= date(");
-            query_builder.push_bind(ts);
-            query_builder.push(", 'unixepoch')");
-        }
-        if let Some(ts) = filter.to_ts {
-            query_builder.push(" AND date <= date(");
-            query_builder.push_bind(ts);
-            query_builder.push(", 'unixepoch')");
+        PipelineResponse {
+            id: p.id,
+            instance: state.instance_name(p.id),
+            project_id: p.project_id,
+            project_name: p.project_name,
+            project_full_path: p.project_full_path,
+            ref_name: p.ref_name,
+            sha: p.sha,
+            user_name: p.user_name,
+            status: p.status.clone(),
+            created_at: created,
+            started_at: started,
+            finished_at: finished,
+            duration: p.duration,
+            queued_duration: p.queued_duration,
+            web_url: p.web_url,
+            source: p.source,
+            coverage: p.coverage,
         }
+    }).collect();
+
+    // Callers that page get the cursor in the body too; the bare array stays the default
+    let body = if query.limit.is_some() || query.cursor.is_some() {
+        Json(PipelineListResponse { items, next_cursor: next_cursor.clone() }).into_response()
     } else {
-        if let Some(ts) = filter.from_ts {
-            query_builder.push(" AND created_at >= ");
-            query_builder.push_bind(ts);
-        }
-        if let Some(ts) = filter.to_ts {
-            query_builder.push(" AND created_at <= ");
-            query_builder.push_bind(ts);
-        }
+        Json(items).into_response()
+    };
+    match next_cursor {
+        Some(cursor) => ([(NEXT_CURSOR_HEADER, cursor)], body).into_response(),
+        None => body,
     }
+}
 
-    query_builder.push(" GROUP BY project_full_path ORDER BY avg_duration ASC");
-
-    let query = query_builder.build_query_as::<ProjectStat>();
-    let stats = query.fetch_all(&state.db).await.unwrap_or_default();
+async fn get_stats_trend(
+    State(state): State<AppState>,
+    Query(filter): Query<PipelineFilter>,
+    Query(trend): Query<TrendQuery>,
+) -> Response {
+    let granularity = trend.granularity.unwrap_or_default();
+    let now = chrono::Utc::now().timestamp();
+    let end_ts = filter.to_ts.unwrap_or(now);
+    let mut start_ts = if granularity == Granularity::Hour {
+        filter.from_ts.unwrap_or(now - 86400)
+    } else {
+        filter.from_ts.unwrap_or(now - 30 * 86400)
+    };
 
-    // insert into cache
-    if let Ok(val) = serde_json::to_value(&stats) {
-        let _ = state.cache.insert(key, val);
+    // Day and coarser buckets: if time range is less than 1 day, default to showing 1 week
+    if granularity != Granularity::Hour && end_ts - start_ts < 86400 {
+        start_ts = end_ts - 7 * 86400;
     }
 
-    Json(stats)
-}
+    let use_fast_path = filter.ref_name.as_deref().unwrap_or("All") == "All";
 
+    let key = format!("trend:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}",
+        if use_fast_path { "fast" } else { "slow" },
+        granularity,
+        filter.instance.as_deref().unwrap_or("All"),
+        filter.project_name.as_deref().unwrap_or("All"),
+        filter.ref_name.as_deref().unwrap_or("All"),
+        filter.source.as_deref().unwrap_or("All"),
+        filter.exclude_projects.as_deref().unwrap_or(""),
+        start_ts,
+        end_ts,
+    );
 
-async fn get_summary_stats(
+    cached_json(&state, key, state.db.stats_trend(&filter, start_ts, end_ts, granularity)).await
+}
+
+async fn get_duration_trend(
     State(state): State<AppState>,
-    Query(filter): Query<PipelineFilter>,
-) -> Json<SummaryStat> {
-    let use_fast_path = filter.ref_name.as_deref().unwrap_or("All") == "All";
+    Query(mut filter): Query<PipelineFilter>,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    filter.to_ts = Some(filter.to_ts.unwrap_or(now));
+    filter.from_ts = Some(filter.from_ts.unwrap_or(now - 30 * 86400));
+
+    cached_json(&state, cache_key("duration_trend", &filter), async {
+        let buckets = state.db.duration_buckets(&filter, DurationGroup::Day).await?;
+        // Newest day first, like /api/stats/trend
+        let days: BTreeMap<String, Vec<(i64, i64)>> = group_duration_buckets(buckets).into_iter().collect();
+        let points: Vec<DurationTrendPoint> = days.into_iter().rev().map(|(date, buckets)| {
+            let p = duration_percentiles(&buckets);
+            DurationTrendPoint {
+                date,
+                count: buckets.iter().map(|&(_, count)| count).sum(),
+                p50_duration: p.p50,
+                p90_duration: p.p90,
+                p95_duration: p.p95,
+                p99_duration: p.p99,
+            }
+        }).collect();
+        Ok(points)
+    }).await
+}
 
-    let key = format!("summary:{:?}:{:?}:{:?}:{:?}:{:?}",
+/// `(bucket, count)` pairs per `DurationBucketCount::key`.
+fn group_duration_buckets(rows: Vec<DurationBucketCount>) -> HashMap<String, Vec<(i64, i64)>> {
+    let mut grouped: HashMap<String, Vec<(i64, i64)>> = HashMap::new();
+    for row in rows {
+        grouped.entry(row.key).or_default().push((row.bucket, row.count));
+    }
+    grouped
+}
+
+/// Cache key of a stats endpoint `name` for the pipeline filters.
+fn cache_key(name: &str, filter: &PipelineFilter) -> String {
+    format!("{}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}",
+        name,
+        filter.instance.as_deref().unwrap_or("All"),
         filter.project_name.as_deref().unwrap_or("All"),
         filter.ref_name.as_deref().unwrap_or("All"),
+        filter.source.as_deref().unwrap_or("All"),
         filter.exclude_projects.as_deref().unwrap_or(""),
         filter.from_ts,
         filter.to_ts,
-    );
+    )
+}
 
+/// Serve the JSON of `compute` through the response cache under `key`. A failure is logged
+/// and answered with 500, and is not cached.
+async fn cached_json<T, F>(state: &AppState, key: String, compute: F) -> Response
+where
+    T: Serialize + DeserializeOwned,
+    F: Future<Output = anyhow::Result<T>>,
+{
     if let Some(cached) = state.cache.get(&key) {
-        if let Ok(v) = serde_json::from_value::<SummaryStat>(cached.clone()) {
-            return Json(v);
+        if let Ok(v) = serde_json::from_value::<T>(cached) {
+            return Json(v).into_response();
         }
     }
 
-    let mut query_builder = if use_fast_path {
-        sqlx::QueryBuilder::new(
-            r#"
-            SELECT 
-                SUM(count) as total_count, 
-                COALESCE(CAST(SUM(total_duration) AS REAL) / NULLIF(SUM(count_with_duration), 0), 0) as avg_duration,
-                COALESCE(SUM(CASE WHEN status = 'success' THEN count ELSE 0 END) * 100.0 / SUM(count), 0) as success_rate
-            FROM daily_stats 
-            WHERE 1=1
-            "#
-        )
-    } else {
-        sqlx::QueryBuilder::new(
-            r#"
-            SELECT 
-                COUNT(*) as total_count, 
-                COALESCE(AVG(duration), 0) as avg_duration,
-                COALESCE(SUM(CASE WHEN status = 'success' THEN 1 ELSE 0 END) * 100.0 / COUNT(*), 0) as success_rate
-            FROM pipelines 
-            WHERE 1=1
-            "#
-        )
-    };
-
-    if let Some(p) = &filter.project_name {
-        if p != "All" && !p.is_empty() {
-            if p.contains(',') {
-                let projects: Vec<&str> = p.split(',').map(|s| s.trim()).collect();
-                if !projects.is_empty() {
-                    query_builder.push(" AND project_full_path IN (");
-                    let mut separated = query_builder.separated(", ");
-                    for proj in projects {
-                        separated.push_bind(proj);
-                    }
-                    separated.push_unseparated(") ");
-                }
-            } else {
-                query_builder.push(" AND project_full_path = ");
-                query_builder.push_bind(p);
+    match compute.await {
+        Ok(v) => {
+            if let Ok(val) = serde_json::to_value(&v) {
+                state.cache.insert(key, val).await;
             }
+            Json(v).into_response()
         }
-    }
-    
-    if !use_fast_path {
-        if let Some(r) = &filter.ref_name {
-            if r != "All" && !r.is_empty() {
-                if r.contains(',') {
-                    let refs: Vec<&str> = r.split(',').map(|s| s.trim()).collect();
-                    if !refs.is_empty() {
-                        query_builder.push(" AND ref_name IN (");
-                        let mut separated = query_builder.separated(", ");
-                        for rv in refs {
-                            separated.push_bind(rv);
-                        }
-                        separated.push_unseparated(") ");
-                    }
-                } else {
-                    query_builder.push(" AND ref_name = ");
-                    query_builder.push_bind(r);
-                }
-            }
-        }
-    }
-    
-    if let Some(ex) = &filter.exclude_projects {
-        if !ex.is_empty() {
-            let projects: Vec<&str> = ex.split(',').collect();
-            if !projects.is_empty() {
-                query_builder.push(" AND project_full_path NOT IN (");
-                let mut separated = query_builder.separated(", ");
-                for p in projects {
-                    separated.push_bind(p);
-                }
-                separated.push_unseparated(") ");
-            }
+        Err(e) => {
+            tracing::error!("{} query failed: {}", key, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
+}
 
-    if use_fast_path {
-        if let Some(ts) = filter.from_ts {
-            query_builder.push(" AND date >= date(");
-            query_builder.push_bind(ts);
-            query_builder.push(", 'unixepoch')");
-        }
-        if let Some(ts) = filter.to_ts {
-            query_builder.push(" AND date <= date(");
-            query_builder.push_bind(ts);
-            query_builder.push(", 'unixepoch')");
+async fn list_projects(State(state): State<AppState>) -> Json<Vec<ProjectResponse>> {
+    let stored = match state.db.list_projects((0, i64::MAX)).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("list_projects query failed: {}", e);
+            Vec::new()
         }
-    } else {
-        if let Some(ts) = filter.from_ts {
-            query_builder.push(" AND created_at >= ");
-            query_builder.push_bind(ts);
+    };
+    let pipeline_only = match state.db.list_pipeline_projects().await {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("list_pipeline_projects query failed: {}", e);
+            Vec::new()
         }
-        if let Some(ts) = filter.to_ts {
-            query_builder.push(" AND created_at <= ");
-            query_builder.push_bind(ts);
+    };
+
+    let mut response: Vec<ProjectResponse> = stored.into_iter()
+        .map(|p| ProjectResponse {
+            id: p.id,
+            instance: state.instance_name(p.id),
+            name: p.name,
+            full_path: p.full_path,
+            default_branch: p.default_branch,
+            visibility: p.visibility,
+            archived: p.archived,
+            topics: p.topics.split(',').filter(|t| !t.is_empty()).map(str::to_string).collect(),
+            state: Some(p.state),
+            previous_full_path: p.previous_full_path,
+        })
+        .chain(pipeline_only.into_iter().map(|(id, name, full_path)| ProjectResponse {
+            id,
+            instance: state.instance_name(id),
+            name,
+            full_path,
+            default_branch: None,
+            visibility: None,
+            archived: false,
+            topics: Vec::new(),
+            state: None,
+            previous_full_path: None,
+        }))
+        .collect();
+    response.sort_by(|a, b| a.full_path.cmp(&b.full_path).then(a.id.cmp(&b.id)));
+    Json(response)
+}
+
+async fn list_refs(State(state): State<AppState>) -> Json<Vec<String>> {
+    let refs = state.db.list_refs().await.unwrap_or_default();
+    Json(refs)
+}
+
+async fn list_jobs(
+    State(state): State<AppState>,
+    Query(filter): Query<JobFilter>,
+) -> Json<Vec<JobResponse>> {
+    let jobs = match state.db.list_jobs(&filter).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("list_jobs query failed: {}", e);
+            Vec::new()
         }
-    }
+    };
 
-    let query = query_builder.build_query_as::<SummaryStat>();
-    let stats = query.fetch_one(&state.db).await.unwrap_or(SummaryStat {
-        total_count: 0,
-        avg_duration: 0.0,
-        success_rate: 0.0,
-    });
+    let to_rfc3339 = |ts: i64| chrono::Utc.timestamp_opt(ts, 0).single().map(|dt| dt.to_rfc3339());
+    let response = jobs.into_iter().map(|j| JobResponse {
+        id: j.id,
+        instance: state.instance_name(j.id),
+        pipeline_id: j.pipeline_id,
+        project_id: j.project_id,
+        project_full_path: j.project_full_path,
+        ref_name: j.ref_name,
+        name: j.name,
+        stage: j.stage,
+        status: j.status,
+        runner_id: j.runner_id,
+        runner_description: j.runner_description,
+        created_at: to_rfc3339(j.created_at).unwrap_or_default(),
+        started_at: j.started_at.and_then(to_rfc3339),
+        finished_at: j.finished_at.and_then(to_rfc3339),
+        duration: j.duration,
+        queued_duration: j.queued_duration,
+        retried: j.retried,
+        allow_failure: j.allow_failure,
+        web_url: j.web_url,
+    }).collect();
 
-    if let Ok(val) = serde_json::to_value(&stats) {
-        let _ = state.cache.insert(key, val);
-    }
+    Json(response)
+}
+
+async fn get_job_stats(
+    State(state): State<AppState>,
+    Query(filter): Query<JobFilter>,
+) -> Response {
+    let key = format!("jobs:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}",
+        filter.instance.as_deref().unwrap_or("All"),
+        filter.project_name.as_deref().unwrap_or("All"),
+        filter.ref_name.as_deref().unwrap_or("All"),
+        filter.exclude_projects.as_deref().unwrap_or(""),
+        filter.pipeline_id,
+        filter.name,
+        filter.stage,
+        filter.status,
+        filter.from_ts,
+        filter.to_ts,
+    );
 
-    Json(stats)
+    cached_json(&state, key, state.db.job_stats(&filter)).await
 }
 
+async fn get_runners(
+    State(state): State<AppState>,
+    Query(mut filter): Query<JobFilter>,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    filter.to_ts = Some(filter.to_ts.unwrap_or(now));
+    filter.from_ts = Some(filter.from_ts.unwrap_or(now - 30 * 86400));
 
-async fn list_pipelines(
+    let key = format!("runners:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}",
+        filter.instance.as_deref().unwrap_or("All"),
+        filter.project_name.as_deref().unwrap_or("All"),
+        filter.ref_name.as_deref().unwrap_or("All"),
+        filter.exclude_projects.as_deref().unwrap_or(""),
+        filter.name,
+        filter.stage,
+        filter.status,
+        filter.from_ts,
+        filter.to_ts,
+    );
+
+    cached_json(&state, key, async {
+        let mut runners = state.db.list_runners().await?;
+        runners.retain(|r| crate::instance::matches(&state.instances, r.id, filter.instance.as_deref()));
+        let job_stats = state.db.runner_job_stats(&filter).await?;
+        Ok(crate::runners::report(&state.instances, runners, job_stats, filter.from_ts.unwrap_or(0), filter.to_ts.unwrap_or(now)))
+    }).await
+}
+
+async fn get_flaky_stats(
     State(state): State<AppState>,
-    Query(filter): Query<PipelineFilter>,
-) -> Json<Vec<PipelineResponse>> {
-    // timestamp now not needed here
-
-    let pipelines = {
-        let mut query_builder = sqlx::QueryBuilder::new("SELECT * FROM pipelines WHERE 1=1");
-
-        if let Some(p) = &filter.project_name {
-            if p != "All" && !p.is_empty() {
-                if p.contains(',') {
-                    let projects: Vec<&str> = p.split(',').map(|s| s.trim()).collect();
-                    if !projects.is_empty() {
-                        query_builder.push(" AND project_full_path IN (");
-                        let mut separated = query_builder.separated(", ");
-                        for proj in projects {
-                            separated.push_bind(proj);
-                        }
-                        separated.push_unseparated(") ");
-                    }
-                } else {
-                    query_builder.push(" AND project_full_path = ");
-                    query_builder.push_bind(p);
-                }
-            }
-        }
-        if let Some(r) = &filter.ref_name {
-            if r != "All" && !r.is_empty() {
-                if r.contains(',') {
-                    let refs: Vec<&str> = r.split(',').map(|s| s.trim()).collect();
-                    if !refs.is_empty() {
-                        query_builder.push(" AND ref_name IN (");
-                        let mut separated = query_builder.separated(", ");
-                        for rv in refs {
-                            separated.push_bind(rv);
-                        }
-                        separated.push_unseparated(") ");
-                    }
-                } else {
-                    query_builder.push(" AND ref_name = ");
-                    query_builder.push_bind(r);
-                }
-            }
-        }
-        if let Some(ex) = &filter.exclude_projects {
-            if !ex.is_empty() {
-                let projects: Vec<&str> = ex.split(',').collect();
-                if !projects.is_empty() {
-                    query_builder.push(" AND project_full_path NOT IN (");
-                    let mut separated = query_builder.separated(", ");
-                    for p in projects {
-                        separated.push_bind(p);
-                    }
-                    separated.push_unseparated(") ");
+    Query(mut filter): Query<PipelineFilter>,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    filter.to_ts = Some(filter.to_ts.unwrap_or(now));
+    filter.from_ts = Some(filter.from_ts.unwrap_or(now - 30 * 86400));
+
+    cached_json(&state, cache_key("flaky", &filter), async {
+        let mut stats = state.db.flaky_stats(&filter).await?;
+
+        // Newest flaky commits first, each with its latest failed and successful pipeline
+        let mut examples: HashMap<(i64, String), Vec<FlakyExample>> = HashMap::new();
+        for p in state.db.flaky_pipelines(&filter).await? {
+            let commits = examples.entry((p.project_id, p.ref_name)).or_default();
+            let example = match commits.iter().position(|e| e.sha == p.sha) {
+                Some(i) => &mut commits[i],
+                None if commits.len() < FLAKY_EXAMPLES => {
+                    commits.push(FlakyExample { sha: p.sha, failed_web_url: None, success_web_url: None });
+                    commits.last_mut().unwrap()
                 }
+                None => continue,
+            };
+            let url = if p.status == "failed" { &mut example.failed_web_url } else { &mut example.success_web_url };
+            if url.is_none() {
+                *url = p.web_url;
             }
         }
-
-        if let Some(s) = &filter.status {
-            query_builder.push(" AND status = ");
-            query_builder.push_bind(s);
+        for stat in &mut stats {
+            stat.examples = examples.remove(&(stat.project_id, stat.ref_name.clone())).unwrap_or_default();
         }
-        
-        let is_running_query = filter.status.as_deref() == Some("running");
-        if !is_running_query {
-            if let Some(ts) = filter.from_ts {
-                query_builder.push(" AND created_at >= ");
-                query_builder.push_bind(ts);
-            }
-            if let Some(ts) = filter.to_ts {
-                query_builder.push(" AND created_at <= ");
-                query_builder.push_bind(ts);
-            }
-        }
-
-        query_builder.push(" ORDER BY created_at DESC LIMIT 100");
+        Ok(stats)
+    }).await
+}
 
-        let query = query_builder.build_query_as::<Pipeline>();
-        match query.fetch_all(&state.db).await {
-            Ok(rows) => rows,
-            Err(e) => {
-                tracing::error!("list_pipelines query failed: {}", e);
-                Vec::new()
-            }
-        }
-    };
+async fn get_merge_request_stats(
+    State(state): State<AppState>,
+    Query(mut filter): Query<PipelineFilter>,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    filter.to_ts = Some(filter.to_ts.unwrap_or(now));
+    filter.from_ts = Some(filter.from_ts.unwrap_or(now - 30 * 86400));
 
-    let response: Vec<PipelineResponse> = pipelines.into_iter().map(|p| {
-        let created = chrono::Utc
-            .timestamp_opt(p.created_at, 0)
-            .single()
-            .map(|dt| dt.to_rfc3339())
-            .unwrap_or_default();
-        let finished = p.finished_at.and_then(|ts| chrono::Utc.timestamp_opt(ts, 0).single().map(|dt| dt.to_rfc3339()));
+    cached_json(&state, cache_key("merge_requests", &filter), state.db.merge_request_stats(&filter)).await
+}
 
-        PipelineResponse {
-            id: p.id,
-            project_id: p.project_id,
-            project_name: p.project_name,
-            project_full_path: p.project_full_path,
-            ref_name: p.ref_name,
-            sha: p.sha,
-            user_name: p.user_name,
-            status: p.status.clone(),
-            created_at: created,
-            finished_at: finished,
-            duration: p.duration,
-            web_url: p.web_url,
-        }
-    }).collect();
+async fn get_test_trend(
+    State(state): State<AppState>,
+    Query(mut filter): Query<PipelineFilter>,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    filter.to_ts = Some(filter.to_ts.unwrap_or(now));
+    filter.from_ts = Some(filter.from_ts.unwrap_or(now - 30 * 86400));
 
-    Json(response)
+    cached_json(&state, cache_key("test_trend", &filter), state.db.test_trend(&filter)).await
 }
 
+async fn get_test_suite_stats(
+    State(state): State<AppState>,
+    Query(mut filter): Query<PipelineFilter>,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    filter.to_ts = Some(filter.to_ts.unwrap_or(now));
+    filter.from_ts = Some(filter.from_ts.unwrap_or(now - 30 * 86400));
 
+    cached_json(&state, cache_key("test_suites", &filter), state.db.test_suite_stats(&filter)).await
+}
 
-async fn get_stats_trend(
+async fn get_coverage_trend(
     State(state): State<AppState>,
-    Query(filter): Query<PipelineFilter>,
-) -> Json<Vec<DailyStat>> {
+    Query(mut filter): Query<PipelineFilter>,
+) -> Response {
     let now = chrono::Utc::now().timestamp();
-    let end_ts = filter.to_ts.unwrap_or(now);
-    let mut start_ts = filter.from_ts.unwrap_or(now - 30 * 86400);
+    filter.to_ts = Some(filter.to_ts.unwrap_or(now));
+    filter.from_ts = Some(filter.from_ts.unwrap_or(now - 30 * 86400));
 
-    // If time range is less than 1 day, default to showing 1 week
-    if end_ts - start_ts < 86400 {
-        start_ts = end_ts - 7 * 86400;
-    }
-
-    // If ref filter is present, we must use pipelines table (slow path)
-    // Otherwise use daily_stats (fast path)
-    let use_fast_path = filter.ref_name.as_deref().unwrap_or("All") == "All";
+    cached_json(&state, cache_key("coverage", &filter), state.db.coverage_trend(&filter)).await
+}
 
-    let mut query_builder = if use_fast_path {
-        let mut qb = sqlx::QueryBuilder::new(
-            r#"
-            SELECT 
-                date,
-                status,
-                SUM(count) as count
-            FROM daily_stats
-            WHERE date >= date(
-            "#
-        );
-        qb.push_bind(start_ts);
-        qb.push(", 'unixepoch') AND date <= date(");
-        qb.push_bind(end_ts);
-        qb.push(", 'unixepoch')");
-        qb
-    } else {
-        let mut qb = sqlx::QueryBuilder::new(
-            r#"
-            SELECT 
-                date(created_at, 'unixepoch') as date,
-                status,
-                COUNT(*) as count
-            FROM pipelines
-            WHERE created_at >= 
-            "#
-        );
-        qb.push_bind(start_ts);
-        qb.push(" AND created_at <= ");
-        qb.push_bind(end_ts);
-        qb
-    };
+async fn get_dora(
+    State(state): State<AppState>,
+    Query(mut filter): Query<PipelineFilter>,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    filter.to_ts = Some(filter.to_ts.unwrap_or(now));
+    filter.from_ts = Some(filter.from_ts.unwrap_or(now - 30 * 86400));
+
+    cached_json(&state, cache_key("dora", &filter), async {
+        let deployments = state.db.list_deployments(&filter).await?;
+        // Validated when the config was loaded
+        let environments = state.config.dora.as_ref()
+            .and_then(|d| d.production_environments.as_deref())
+            .and_then(|re| regex::Regex::new(re).ok());
+        Ok(crate::dora::report(
+            deployments,
+            &state.config.monitor_groups(),
+            environments.as_ref(),
+            filter.from_ts.unwrap_or(0),
+            filter.to_ts.unwrap_or(now),
+        ))
+    }).await
+}
 
-    if let Some(p) = &filter.project_name {
-        if p != "All" && !p.is_empty() {
-            if p.contains(',') {
-                let projects: Vec<&str> = p.split(',').map(|s| s.trim()).collect();
-                if !projects.is_empty() {
-                    query_builder.push(" AND project_full_path IN (");
-                    let mut separated = query_builder.separated(", ");
-                    for proj in projects {
-                        separated.push_bind(proj);
-                    }
-                    separated.push_unseparated(") ");
-                }
-            } else {
-                query_builder.push(" AND project_full_path = ");
-                query_builder.push_bind(p);
-            }
-        }
-    }
-    
-    if !use_fast_path {
-        if let Some(r) = &filter.ref_name {
-            if r != "All" && !r.is_empty() {
-                if r.contains(',') {
-                    let refs: Vec<&str> = r.split(',').map(|s| s.trim()).collect();
-                    if !refs.is_empty() {
-                        query_builder.push(" AND ref_name IN (");
-                        let mut separated = query_builder.separated(", ");
-                        for rv in refs {
-                            separated.push_bind(rv);
-                        }
-                        separated.push_unseparated(") ");
-                    }
-                } else {
-                    query_builder.push(" AND ref_name = ");
-                    query_builder.push_bind(r);
+async fn get_recovery_stats(
+    State(state): State<AppState>,
+    Query(mut filter): Query<PipelineFilter>,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    filter.to_ts = Some(filter.to_ts.unwrap_or(now));
+    filter.from_ts = Some(filter.from_ts.unwrap_or(now - 30 * 86400));
+
+    // The ref is fixed per project, so a ref filter does not apply
+    filter.ref_name = None;
+
+    cached_json(&state, cache_key("recovery", &filter), async {
+        // Default branches and current paths come from the last project discovery, or the stored
+        // projects until the first one after a start completed
+        let projects = state.monitored_projects.read().unwrap().clone();
+        let branches: HashMap<i64, (String, String)> = if projects.is_empty() {
+            state.db.list_projects((0, i64::MAX)).await?
+                .into_iter()
+                .filter(|p| p.state == crate::projects::ACTIVE)
+                .filter_map(|p| Some((p.id, (p.default_branch?, p.full_path))))
+                .collect()
+        } else {
+            projects.iter()
+                .filter_map(|p| Some((p.id as i64, (p.default_branch.clone()?, p.path_with_namespace.clone()))))
+                .collect()
+        };
+        let mut refs: Vec<String> = branches.values().map(|(branch, _)| branch.clone()).collect();
+        refs.sort();
+        refs.dedup();
+
+        let pipelines = state.db.pipeline_outcomes(&filter, &refs).await?;
+        // Keyed by the current path so the pipelines from before a rename stay on the same branch
+        let mut by_project: BTreeMap<String, Vec<Pipeline>> = BTreeMap::new();
+        for p in pipelines {
+            if let Some((branch, path)) = branches.get(&p.project_id) {
+                if *branch == p.ref_name {
+                    by_project.entry(path.clone()).or_default().push(p);
                 }
             }
         }
-    }
 
-    if let Some(ex) = &filter.exclude_projects {
-        if !ex.is_empty() {
-            let projects: Vec<&str> = ex.split(',').collect();
-            if !projects.is_empty() {
-                query_builder.push(" AND project_full_path NOT IN (");
-                let mut separated = query_builder.separated(", ");
-                for p in projects {
-                    separated.push_bind(p);
-                }
-                separated.push_unseparated(") ");
+        let end = filter.to_ts.unwrap_or(now).min(now);
+        let mut stats: Vec<RecoveryStat> = by_project.into_iter()
+            .map(|(project_name, pipelines)| recovery_stat(project_name, &pipelines, end))
+            .collect();
+        // Red branches first, then the slowest to recover
+        stats.sort_by(|a, b| b.current_red_streak.cmp(&a.current_red_streak)
+            .then(b.mttr.unwrap_or(0.0).total_cmp(&a.mttr.unwrap_or(0.0))));
+        Ok(stats)
+    }).await
+}
+
+/// Red intervals of one branch from its failed and successful `pipelines`, oldest first;
+/// an interval still open is measured up to `end`.
+fn recovery_stat(project_name: String, pipelines: &[Pipeline], end: i64) -> RecoveryStat {
+    let default_branch = pipelines.first().map(|p| p.ref_name.clone()).unwrap_or_default();
+    let mut outages = Vec::new();
+    let mut red_since: Option<i64> = None;
+    let mut failed_count = 0;
+    for p in pipelines {
+        let at = p.finished_at.unwrap_or(p.created_at);
+        if p.status == "failed" {
+            if red_since.is_none() {
+                red_since = Some(at);
+                failed_count = 0;
             }
+            failed_count += 1;
+        } else if let Some(since) = red_since.take() {
+            outages.push((at - since).max(0));
         }
     }
 
-    if use_fast_path {
-        query_builder.push(" GROUP BY date, status ORDER BY date DESC");
-    } else {
-        query_builder.push(" GROUP BY 1, 2 ORDER BY 1 DESC");
+    let current_red_streak = red_since.map(|since| (end - since).max(0)).unwrap_or(0);
+    RecoveryStat {
+        project_name,
+        default_branch,
+        recovery_count: outages.len() as i64,
+        mttr: (!outages.is_empty()).then(|| outages.iter().sum::<i64>() as f64 / outages.len() as f64),
+        longest_outage: outages.iter().copied().chain(red_since.map(|_| current_red_streak)).max(),
+        red_since,
+        current_red_streak,
+        current_failed_count: if red_since.is_some() { failed_count } else { 0 },
     }
+}
 
-    let key = format!("trend:{:?}:{:?}:{:?}:{:?}:{:?}:{:?}",
-        if use_fast_path { "fast" } else { "slow" },
-        filter.project_name.as_deref().unwrap_or("All"),
-        filter.ref_name.as_deref().unwrap_or("All"),
-        filter.exclude_projects.as_deref().unwrap_or(""),
-        start_ts,
-        end_ts,
-    );
-
-    if let Some(cached) = state.cache.get(&key) {
-        if let Ok(v) = serde_json::from_value::<Vec<DailyStat>>(cached.clone()) {
-            return Json(v);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(status: &str, created_at: i64, finished_at: Option<i64>) -> Pipeline {
+        Pipeline {
+            id: created_at,
+            project_id: 7,
+            project_name: "app".to_string(),
+            project_full_path: "grp/app".to_string(),
+            ref_name: "main".to_string(),
+            sha: "abc".to_string(),
+            user_name: String::new(),
+            status: status.to_string(),
+            created_at,
+            started_at: None,
+            finished_at,
+            duration: None,
+            queued_duration: None,
+            web_url: None,
+            source: None,
+            merge_request_iid: None,
+            coverage: None,
         }
     }
 
-    let query = query_builder.build_query_as::<DailyStat>();
-    let stats = query.fetch_all(&state.db).await.unwrap_or_default();
-
-    if let Ok(val) = serde_json::to_value(&stats) {
-        let _ = state.cache.insert(key, val);
+    #[test]
+    fn recovery_stat_measures_closed_and_open_red_intervals() {
+        let pipelines = [
+            pipeline("success", 50, Some(100)),
+            pipeline("failed", 150, Some(200)),
+            pipeline("failed", 250, Some(300)),
+            pipeline("success", 550, Some(600)),
+            // Unfinished pipelines count from their creation
+            pipeline("failed", 700, None),
+            pipeline("failed", 750, Some(800)),
+        ];
+        let stat = recovery_stat("grp/app".to_string(), &pipelines, 1_000);
+        assert_eq!((stat.project_name.as_str(), stat.default_branch.as_str()), ("grp/app", "main"));
+        assert_eq!((stat.recovery_count, stat.mttr, stat.longest_outage), (1, Some(400.0), Some(400)));
+        assert_eq!((stat.red_since, stat.current_red_streak, stat.current_failed_count), (Some(700), 300, 2));
     }
 
-    Json(stats)
-}
+    #[test]
+    fn recovery_stat_of_a_green_branch() {
+        let pipelines = [pipeline("failed", 50, Some(100)), pipeline("success", 150, Some(160)), pipeline("success", 250, Some(300))];
+        let stat = recovery_stat("grp/app".to_string(), &pipelines, 1_000);
+        assert_eq!((stat.recovery_count, stat.mttr, stat.longest_outage), (1, Some(60.0), Some(60)));
+        assert_eq!((stat.red_since, stat.current_red_streak, stat.current_failed_count), (None, 0, 0));
 
-async fn list_projects(State(state): State<AppState>) -> Json<Vec<String>> {
-    let projects = sqlx::query_scalar("SELECT DISTINCT project_full_path FROM pipelines ORDER BY project_full_path")
-        .fetch_all(&state.db)
-        .await
-        .unwrap_or_default();
-    let mut names = projects;
-    names.sort();
-    Json(names)
+        let stat = recovery_stat("grp/app".to_string(), &[], 1_000);
+        assert_eq!((stat.recovery_count, stat.mttr, stat.longest_outage, stat.red_since), (0, None, None, None));
+    }
 }
-
-async fn list_refs(State(state): State<AppState>) -> Json<Vec<String>> {
-    let refs: Vec<String> = sqlx::query_scalar("SELECT DISTINCT ref_name FROM pipelines ORDER BY ref_name")
-        .fetch_all(&state.db)
-        .await
-        .unwrap_or_default();
-    Json(refs)
-}
\ No newline at end of file
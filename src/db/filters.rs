@@ -0,0 +1,249 @@
+//! Filter clauses shared by the SQL backends; placeholders come from the `QueryBuilder`.
+
+use super::{local_date, local_hour};
+use crate::instance::NAMESPACE_SHIFT;
+use crate::models::{JobFilter, PipelineFilter, PipelinePage, SortOrder};
+use chrono_tz::Tz;
+use sqlx::{Database, Encode, QueryBuilder, Type};
+
+/// `column = value`, or `column IN (...)` for a comma separated list; "All" or empty matches everything.
+pub fn push_list_filter<'args, DB>(qb: &mut QueryBuilder<'args, DB>, column: &str, value: Option<&str>)
+where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    let Some(v) = value else { return };
+    if v == "All" || v.is_empty() {
+        return;
+    }
+    if v.contains(',') {
+        qb.push(format!(" AND {} IN (", column));
+        let mut separated = qb.separated(", ");
+        for item in v.split(',').map(|s| s.trim()) {
+            separated.push_bind(item.to_string());
+        }
+        separated.push_unseparated(") ");
+    } else {
+        qb.push(format!(" AND {} = ", column));
+        qb.push_bind(v.to_string());
+    }
+}
+
+/// `column NOT IN (...)` for a comma separated list of excluded projects.
+pub fn push_exclude_filter<'args, DB>(qb: &mut QueryBuilder<'args, DB>, column: &str, value: Option<&str>)
+where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    let Some(v) = value else { return };
+    if v.is_empty() {
+        return;
+    }
+    qb.push(format!(" AND {} NOT IN (", column));
+    let mut separated = qb.separated(", ");
+    for item in v.split(',') {
+        separated.push_bind(item.to_string());
+    }
+    separated.push_unseparated(") ");
+}
+
+/// Keep rows whose stored GitLab id in `id_column` belongs to one of the instances named in a
+/// comma separated list; "All" or empty matches everything.
+pub fn push_instance_filter<'args, DB>(qb: &mut QueryBuilder<'args, DB>, id_column: &str, value: Option<&str>)
+where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    let Some(v) = value else { return };
+    if v == "All" || v.is_empty() {
+        return;
+    }
+    qb.push(format!(" AND {} / {} IN (SELECT namespace FROM instances WHERE 1=1", id_column, 1i64 << NAMESPACE_SHIFT));
+    push_list_filter(qb, "name", Some(v));
+    qb.push(")");
+}
+
+/// Stored project ids of the projects matched by a `full_path` condition appended to it.
+pub const PROJECT_IDS: &str = "SELECT id FROM projects WHERE 1=1";
+
+/// Stored pipeline ids of the projects matched by a `full_path` condition appended to it.
+pub const PIPELINE_IDS: &str = "SELECT pipelines.id FROM pipelines JOIN projects ON projects.id = pipelines.project_id WHERE 1=1";
+
+/// Project inclusion (`include`) and exclusion (`exclude`) by comma separated paths, matching
+/// both the path stored with each row and the current path of its project in `projects`, so a
+/// renamed project keeps its whole history under either path. `key_column` is matched against
+/// `keys`, which is `PROJECT_IDS` for a project id column or `PIPELINE_IDS` for a pipeline id.
+pub fn push_project_filter<'args, DB>(
+    qb: &mut QueryBuilder<'args, DB>,
+    key_column: &str,
+    keys: &str,
+    path_column: &str,
+    include: Option<&str>,
+    exclude: Option<&str>,
+) where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    if let Some(v) = include.filter(|v| *v != "All" && !v.is_empty()) {
+        qb.push(" AND ((1=1");
+        push_list_filter(qb, path_column, Some(v));
+        qb.push(format!(") OR {} IN ({}", key_column, keys));
+        push_list_filter(qb, "projects.full_path", Some(v));
+        qb.push("))");
+    }
+    if let Some(v) = exclude.filter(|v| !v.is_empty()) {
+        push_exclude_filter(qb, path_column, Some(v));
+        qb.push(format!(" AND {} NOT IN ({} AND projects.full_path IN (", key_column, keys));
+        let mut separated = qb.separated(", ");
+        for item in v.split(',') {
+            separated.push_bind(item.to_string());
+        }
+        separated.push_unseparated("))");
+    }
+}
+
+/// Bound a unix timestamp column by `from_ts` / `to_ts`.
+pub fn push_ts_range<'args, DB>(qb: &mut QueryBuilder<'args, DB>, column: &str, from_ts: Option<i64>, to_ts: Option<i64>)
+where
+    DB: Database,
+    i64: Encode<'args, DB> + Type<DB>,
+{
+    if let Some(ts) = from_ts {
+        qb.push(format!(" AND {} >= ", column));
+        qb.push_bind(ts);
+    }
+    if let Some(ts) = to_ts {
+        qb.push(format!(" AND {} <= ", column));
+        qb.push_bind(ts);
+    }
+}
+
+/// Bound the `daily_stats.date` column by the days (in `tz`) containing `from_ts` / `to_ts`.
+pub fn push_date_range<'args, DB>(qb: &mut QueryBuilder<'args, DB>, tz: Tz, from_ts: Option<i64>, to_ts: Option<i64>)
+where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    if let Some(ts) = from_ts {
+        qb.push(" AND date >= ");
+        qb.push_bind(local_date(ts, tz));
+    }
+    if let Some(ts) = to_ts {
+        qb.push(" AND date <= ");
+        qb.push_bind(local_date(ts, tz));
+    }
+}
+
+/// Bound the `hourly_stats.hour` column by the hours (in `tz`) containing `from_ts` / `to_ts`.
+pub fn push_hour_range<'args, DB>(qb: &mut QueryBuilder<'args, DB>, tz: Tz, from_ts: Option<i64>, to_ts: Option<i64>)
+where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    if let Some(ts) = from_ts {
+        qb.push(" AND hour >= ");
+        qb.push_bind(local_hour(ts, tz));
+    }
+    if let Some(ts) = to_ts {
+        qb.push(" AND hour <= ");
+        qb.push_bind(local_hour(ts, tz));
+    }
+}
+
+/// Instance, project, ref, source and exclusion conditions of a `PipelineFilter` against `pipelines`.
+pub fn push_pipeline_filter<'args, DB>(qb: &mut QueryBuilder<'args, DB>, filter: &PipelineFilter)
+where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    push_instance_filter(qb, "id", filter.instance.as_deref());
+    // `project_id` is ambiguous in the queries joining pipelines with other tables; `id` is not
+    push_project_filter(qb, "id", PIPELINE_IDS, "project_full_path", filter.project_name.as_deref(), filter.exclude_projects.as_deref());
+    push_list_filter(qb, "ref_name", filter.ref_name.as_deref());
+    push_list_filter(qb, "source", filter.source.as_deref());
+}
+
+/// Subquery of failed and successful pipeline counts per project id, ref and commit, for the
+/// pipelines matching `filter`; a commit with both is flaky.
+pub fn push_commit_outcomes<'args, DB>(qb: &mut QueryBuilder<'args, DB>, filter: &PipelineFilter)
+where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+    i64: Encode<'args, DB> + Type<DB>,
+{
+    qb.push(
+        "(SELECT project_id, MAX(project_full_path) as project_full_path, ref_name, sha, \
+         SUM(CASE WHEN status = 'failed' THEN 1 ELSE 0 END) as failed, \
+         SUM(CASE WHEN status = 'success' THEN 1 ELSE 0 END) as succeeded \
+         FROM pipelines WHERE status IN ('failed', 'success')",
+    );
+    push_pipeline_filter(qb, filter);
+    push_ts_range(qb, "created_at", filter.from_ts, filter.to_ts);
+    qb.push(" GROUP BY project_id, ref_name, sha)");
+}
+
+/// Keyset condition, ordering and limit of a `list_pipelines` page; fetches one extra row
+/// so the caller can tell whether another page follows.
+pub fn push_pipeline_page<'args, DB>(qb: &mut QueryBuilder<'args, DB>, page: &PipelinePage)
+where
+    DB: Database,
+    i64: Encode<'args, DB> + Type<DB>,
+{
+    let expr = page.sort_expr();
+    let (cmp, dir) = match page.order {
+        SortOrder::Asc => (">", "ASC"),
+        SortOrder::Desc => ("<", "DESC"),
+    };
+    if let Some(after) = page.after {
+        qb.push(format!(" AND ({} {} ", expr, cmp));
+        qb.push_bind(after.value);
+        qb.push(format!(" OR ({} = ", expr));
+        qb.push_bind(after.value);
+        qb.push(format!(" AND id {} ", cmp));
+        qb.push_bind(after.id);
+        qb.push("))");
+    }
+    qb.push(format!(" ORDER BY {} {}, id {} LIMIT ", expr, dir, dir));
+    qb.push_bind(page.limit + 1);
+}
+
+/// Instance, project, source and exclusion conditions of a `PipelineFilter` against the rollups, which have no ref.
+/// Only `daily_stats` has a source, so `hourly_stats` and `daily_duration_buckets` are read without one.
+pub fn push_daily_stats_filter<'args, DB>(qb: &mut QueryBuilder<'args, DB>, filter: &PipelineFilter)
+where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+{
+    push_instance_filter(qb, "project_id", filter.instance.as_deref());
+    push_project_filter(qb, "project_id", PROJECT_IDS, "project_full_path", filter.project_name.as_deref(), filter.exclude_projects.as_deref());
+    push_list_filter(qb, "source", filter.source.as_deref());
+}
+
+/// Push the `JobFilter` conditions; expects `jobs j` joined with `pipelines p`.
+pub fn push_job_filters<'args, DB>(qb: &mut QueryBuilder<'args, DB>, filter: &JobFilter)
+where
+    DB: Database,
+    String: Encode<'args, DB> + Type<DB>,
+    i64: Encode<'args, DB> + Type<DB>,
+{
+    push_instance_filter(qb, "p.id", filter.instance.as_deref());
+    push_project_filter(qb, "p.project_id", PROJECT_IDS, "p.project_full_path", filter.project_name.as_deref(), filter.exclude_projects.as_deref());
+    push_list_filter(qb, "p.ref_name", filter.ref_name.as_deref());
+    if let Some(id) = filter.pipeline_id {
+        qb.push(" AND j.pipeline_id = ");
+        qb.push_bind(id);
+    }
+    if let Some(n) = &filter.name {
+        qb.push(" AND j.name = ");
+        qb.push_bind(n.clone());
+    }
+    if let Some(st) = &filter.stage {
+        qb.push(" AND j.stage = ");
+        qb.push_bind(st.clone());
+    }
+    if let Some(s) = &filter.status {
+        qb.push(" AND j.status = ");
+        qb.push_bind(s.clone());
+    }
+    push_ts_range(qb, "j.created_at", filter.from_ts, filter.to_ts);
+}
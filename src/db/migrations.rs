@@ -0,0 +1,949 @@
+use anyhow::{bail, Result};
+use sqlx::{Executor, Pool, Postgres, Sqlite};
+use tracing::info;
+
+/// A forward-only schema change, applied once and recorded in `schema_version`.
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+    /// Query returning a row when a database created before `schema_version` existed
+    /// already contains this change; only needed for statements that are not idempotent.
+    pub legacy_check: Option<&'static str>,
+}
+
+pub const SQLITE: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial pipelines, poll_state and daily_stats schema",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS pipelines (
+            id INTEGER PRIMARY KEY,
+            project_id INTEGER NOT NULL,
+            project_name TEXT NOT NULL,
+            project_full_path TEXT NOT NULL,
+            ref_name TEXT NOT NULL,
+            user_name TEXT,
+            sha TEXT,
+            status TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            finished_at INTEGER,
+            duration INTEGER,
+            web_url TEXT,
+            UNIQUE(id)
+        );
+        CREATE TABLE IF NOT EXISTS poll_state (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            last_poll_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS daily_stats (
+            date TEXT NOT NULL,
+            project_id INTEGER NOT NULL,
+            project_name TEXT NOT NULL,
+            project_full_path TEXT NOT NULL,
+            status TEXT NOT NULL,
+            count INTEGER DEFAULT 0,
+            total_duration INTEGER DEFAULT 0,
+            PRIMARY KEY (date, project_id, status)
+        );
+        CREATE INDEX IF NOT EXISTS idx_query ON pipelines(project_name, status, created_at);
+        CREATE INDEX IF NOT EXISTS idx_status_created ON pipelines(status, created_at DESC);
+        CREATE INDEX IF NOT EXISTS idx_project_created ON pipelines(project_name, created_at DESC);
+        CREATE INDEX IF NOT EXISTS idx_watermark ON pipelines(finished_at);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 2,
+        description: "daily_stats.count_with_duration",
+        sql: "ALTER TABLE daily_stats ADD COLUMN count_with_duration INTEGER DEFAULT 0;",
+        legacy_check: Some("SELECT 1 FROM pragma_table_info('daily_stats') WHERE name = 'count_with_duration'"),
+    },
+    Migration {
+        version: 3,
+        description: "jobs table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS jobs (
+            id INTEGER PRIMARY KEY,
+            pipeline_id INTEGER NOT NULL REFERENCES pipelines(id) ON DELETE CASCADE,
+            project_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            stage TEXT NOT NULL,
+            status TEXT NOT NULL,
+            runner_id INTEGER,
+            runner_description TEXT,
+            created_at INTEGER NOT NULL,
+            started_at INTEGER,
+            finished_at INTEGER,
+            duration REAL,
+            queued_duration REAL,
+            retried INTEGER NOT NULL DEFAULT 0,
+            allow_failure INTEGER NOT NULL DEFAULT 0,
+            web_url TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_jobs_pipeline ON jobs(pipeline_id);
+        CREATE INDEX IF NOT EXISTS idx_jobs_project_created ON jobs(project_id, created_at DESC);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 4,
+        description: "per-scope poll watermarks",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS poll_watermarks (
+            scope TEXT NOT NULL,
+            scope_key TEXT NOT NULL,
+            last_poll_at INTEGER NOT NULL,
+            PRIMARY KEY (scope, scope_key)
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 5,
+        description: "pipelines.created_at index for retention pruning",
+        sql: "CREATE INDEX IF NOT EXISTS idx_created ON pipelines(created_at);",
+        legacy_check: None,
+    },
+    Migration {
+        version: 6,
+        description: "hourly_stats rollup, seeded from pipelines",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS hourly_stats (
+            hour TEXT NOT NULL,
+            project_id INTEGER NOT NULL,
+            project_name TEXT NOT NULL,
+            project_full_path TEXT NOT NULL,
+            status TEXT NOT NULL,
+            count INTEGER DEFAULT 0,
+            total_duration INTEGER DEFAULT 0,
+            count_with_duration INTEGER DEFAULT 0,
+            PRIMARY KEY (hour, project_id, status)
+        );
+        INSERT INTO hourly_stats (hour, project_id, project_name, project_full_path, status, count, total_duration, count_with_duration)
+        SELECT strftime('%Y-%m-%d %H:00', created_at, 'unixepoch'),
+               project_id,
+               MAX(project_name),
+               MAX(project_full_path),
+               status,
+               COUNT(*),
+               COALESCE(SUM(duration), 0),
+               COUNT(duration)
+        FROM pipelines
+        GROUP BY 1, project_id, status
+        ON CONFLICT(hour, project_id, status) DO NOTHING;
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 7,
+        description: "settings key/value table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 8,
+        description: "daily_duration_buckets rollup, seeded from pipelines",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS daily_duration_buckets (
+            date TEXT NOT NULL,
+            project_id INTEGER NOT NULL,
+            project_name TEXT NOT NULL,
+            project_full_path TEXT NOT NULL,
+            bucket INTEGER NOT NULL,
+            count INTEGER DEFAULT 0,
+            PRIMARY KEY (date, project_id, bucket)
+        );
+        INSERT INTO daily_duration_buckets (date, project_id, project_name, project_full_path, bucket, count)
+        SELECT date(created_at, 'unixepoch'),
+               project_id,
+               MAX(project_name),
+               MAX(project_full_path),
+               CASE WHEN duration <= 100 THEN duration
+                    WHEN duration <= 1000 THEN (duration + 9) / 10 * 10
+                    WHEN duration <= 10000 THEN (duration + 99) / 100 * 100
+                    WHEN duration <= 100000 THEN (duration + 999) / 1000 * 1000
+                    WHEN duration <= 1000000 THEN (duration + 9999) / 10000 * 10000
+                    ELSE (duration + 99999) / 100000 * 100000 END,
+               COUNT(*)
+        FROM pipelines
+        WHERE duration IS NOT NULL
+        GROUP BY 1, project_id, 5
+        ON CONFLICT(date, project_id, bucket) DO NOTHING;
+        -- The seed is bucketed by UTC dates; have startup rebuild the rollups in the reporting timezone
+        UPDATE settings SET value = 'UTC' WHERE key = 'rollup_timezone';
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 9,
+        description: "pipeline start and queue times, daily_stats queue totals",
+        sql: r#"
+        ALTER TABLE pipelines ADD COLUMN started_at INTEGER;
+        ALTER TABLE pipelines ADD COLUMN queued_duration INTEGER;
+        ALTER TABLE daily_stats ADD COLUMN total_queued_duration INTEGER DEFAULT 0;
+        ALTER TABLE daily_stats ADD COLUMN count_with_queued_duration INTEGER DEFAULT 0;
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 10,
+        description: "environments and deployments tables",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS environments (
+            id INTEGER PRIMARY KEY,
+            project_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            tier TEXT,
+            state TEXT
+        );
+        CREATE TABLE IF NOT EXISTS deployments (
+            id INTEGER PRIMARY KEY,
+            project_id INTEGER NOT NULL,
+            project_full_path TEXT NOT NULL,
+            environment_id INTEGER NOT NULL,
+            environment_name TEXT NOT NULL,
+            ref_name TEXT NOT NULL,
+            sha TEXT NOT NULL,
+            status TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            finished_at INTEGER,
+            commit_created_at INTEGER,
+            pipeline_id INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS idx_deployments_created ON deployments(created_at);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 11,
+        description: "pipeline source, daily_stats grouped by source",
+        sql: r#"
+        ALTER TABLE pipelines ADD COLUMN source TEXT;
+        CREATE TABLE daily_stats_by_source (
+            date TEXT NOT NULL,
+            project_id INTEGER NOT NULL,
+            project_name TEXT NOT NULL,
+            project_full_path TEXT NOT NULL,
+            status TEXT NOT NULL,
+            source TEXT NOT NULL DEFAULT '',
+            count INTEGER DEFAULT 0,
+            total_duration INTEGER DEFAULT 0,
+            count_with_duration INTEGER DEFAULT 0,
+            total_queued_duration INTEGER DEFAULT 0,
+            count_with_queued_duration INTEGER DEFAULT 0,
+            PRIMARY KEY (date, project_id, status, source)
+        );
+        -- SQLite cannot change a primary key in place; existing rows have no known source
+        INSERT INTO daily_stats_by_source (date, project_id, project_name, project_full_path, status, count, total_duration, count_with_duration, total_queued_duration, count_with_queued_duration)
+        SELECT date, project_id, project_name, project_full_path, status, count, total_duration, count_with_duration, total_queued_duration, count_with_queued_duration
+        FROM daily_stats;
+        DROP TABLE daily_stats;
+        ALTER TABLE daily_stats_by_source RENAME TO daily_stats;
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 12,
+        description: "pipeline merge requests and merge_requests table",
+        sql: r#"
+        ALTER TABLE pipelines ADD COLUMN merge_request_iid INTEGER;
+        CREATE INDEX IF NOT EXISTS idx_pipelines_merge_request ON pipelines(project_id, merge_request_iid);
+        CREATE TABLE IF NOT EXISTS merge_requests (
+            project_id INTEGER NOT NULL,
+            iid INTEGER NOT NULL,
+            title TEXT NOT NULL,
+            state TEXT,
+            source_branch TEXT,
+            target_branch TEXT,
+            web_url TEXT,
+            created_at INTEGER,
+            merged_at INTEGER,
+            PRIMARY KEY (project_id, iid)
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 13,
+        description: "test_suites table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS test_suites (
+            pipeline_id INTEGER NOT NULL REFERENCES pipelines(id) ON DELETE CASCADE,
+            project_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            total_time REAL NOT NULL DEFAULT 0,
+            total_count INTEGER NOT NULL DEFAULT 0,
+            success_count INTEGER NOT NULL DEFAULT 0,
+            failed_count INTEGER NOT NULL DEFAULT 0,
+            skipped_count INTEGER NOT NULL DEFAULT 0,
+            error_count INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (pipeline_id, name)
+        );
+        CREATE INDEX IF NOT EXISTS idx_test_suites_project ON test_suites(project_id, name);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 14,
+        description: "pipeline coverage",
+        sql: "ALTER TABLE pipelines ADD COLUMN coverage REAL;",
+        legacy_check: None,
+    },
+    Migration {
+        version: 15,
+        description: "runners table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS runners (
+            id INTEGER PRIMARY KEY,
+            description TEXT,
+            runner_type TEXT,
+            status TEXT,
+            paused INTEGER NOT NULL DEFAULT 0,
+            executor TEXT,
+            tags TEXT NOT NULL DEFAULT '',
+            contacted_at INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS idx_jobs_runner_created ON jobs(runner_id, created_at);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 16,
+        description: "instances table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS instances (
+            url TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            namespace INTEGER NOT NULL UNIQUE
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 17,
+        description: "projects table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS projects (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            full_path TEXT NOT NULL,
+            default_branch TEXT,
+            state TEXT NOT NULL DEFAULT 'active',
+            previous_full_path TEXT,
+            first_seen_at INTEGER NOT NULL,
+            last_seen_at INTEGER NOT NULL,
+            state_changed_at INTEGER NOT NULL
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 18,
+        description: "project metadata",
+        sql: r#"
+        ALTER TABLE projects ADD COLUMN visibility TEXT;
+        ALTER TABLE projects ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE projects ADD COLUMN topics TEXT NOT NULL DEFAULT '';
+        UPDATE projects SET archived = 1 WHERE state = 'archived';
+        CREATE INDEX IF NOT EXISTS idx_projects_full_path ON projects(full_path);
+        CREATE INDEX IF NOT EXISTS idx_pipelines_project_created ON pipelines(project_id, created_at DESC);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 19,
+        description: "pipeline_totals of pruned pipelines",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS pipeline_totals (
+            project_full_path TEXT NOT NULL,
+            ref_name TEXT NOT NULL,
+            status TEXT NOT NULL,
+            bucket INTEGER NOT NULL,
+            count INTEGER NOT NULL DEFAULT 0,
+            duration_count INTEGER NOT NULL DEFAULT 0,
+            duration_sum INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (project_full_path, ref_name, status, bucket)
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 20,
+        description: "pipeline_totals of every pipeline, counted when it first finishes",
+        sql: r#"
+        ALTER TABLE pipelines ADD COLUMN counted INTEGER NOT NULL DEFAULT 0;
+        -- Until now pipeline_totals only held pruned pipelines; add the stored finished ones
+        INSERT INTO pipeline_totals (project_full_path, ref_name, status, bucket, count, duration_count, duration_sum)
+        SELECT project_full_path, ref_name, status,
+               CASE WHEN d <= 100 THEN d
+                    WHEN d <= 1000 THEN (d + 9) / 10 * 10
+                    WHEN d <= 10000 THEN (d + 99) / 100 * 100
+                    WHEN d <= 100000 THEN (d + 999) / 1000 * 1000
+                    WHEN d <= 1000000 THEN (d + 9999) / 10000 * 10000
+                    ELSE (d + 99999) / 100000 * 100000 END,
+               COUNT(*), COUNT(duration), SUM(d)
+        FROM (SELECT project_full_path, ref_name, status, duration, COALESCE(duration, 0) AS d FROM pipelines
+              WHERE status IN ('success', 'failed', 'canceled', 'skipped')) t
+        WHERE 1=1
+        GROUP BY 1, 2, 3, 4
+        ON CONFLICT (project_full_path, ref_name, status, bucket) DO UPDATE SET
+            count = pipeline_totals.count + excluded.count,
+            duration_count = pipeline_totals.duration_count + excluded.duration_count,
+            duration_sum = pipeline_totals.duration_sum + excluded.duration_sum;
+        UPDATE pipelines SET counted = 1 WHERE status IN ('success', 'failed', 'canceled', 'skipped');
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 21,
+        description: "pipeline_totals per instance",
+        sql: r#"
+        CREATE TABLE pipeline_totals_new (
+            namespace INTEGER NOT NULL DEFAULT 0,
+            project_full_path TEXT NOT NULL,
+            ref_name TEXT NOT NULL,
+            status TEXT NOT NULL,
+            bucket INTEGER NOT NULL,
+            count INTEGER NOT NULL DEFAULT 0,
+            duration_count INTEGER NOT NULL DEFAULT 0,
+            duration_sum INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (namespace, project_full_path, ref_name, status, bucket)
+        );
+        INSERT INTO pipeline_totals_new (project_full_path, ref_name, status, bucket, count, duration_count, duration_sum)
+        SELECT project_full_path, ref_name, status, bucket, count, duration_count, duration_sum FROM pipeline_totals;
+        -- Totals kept no instance so far: leave them with the first one, moving the stored
+        -- pipelines of the others to their own
+        CREATE TABLE pipeline_totals_moved AS
+        SELECT id / 1099511627776 AS namespace, project_full_path, ref_name, status,
+               CASE WHEN d <= 100 THEN d
+                    WHEN d <= 1000 THEN (d + 9) / 10 * 10
+                    WHEN d <= 10000 THEN (d + 99) / 100 * 100
+                    WHEN d <= 100000 THEN (d + 999) / 1000 * 1000
+                    WHEN d <= 1000000 THEN (d + 9999) / 10000 * 10000
+                    ELSE (d + 99999) / 100000 * 100000 END AS bucket,
+               COUNT(*) AS count, COUNT(duration) AS duration_count, SUM(d) AS duration_sum
+        FROM (SELECT id, project_full_path, ref_name, status, duration, COALESCE(duration, 0) AS d FROM pipelines
+              WHERE counted = 1 AND id >= 1099511627776) t
+        GROUP BY 1, 2, 3, 4, 5;
+        INSERT INTO pipeline_totals_new (namespace, project_full_path, ref_name, status, bucket, count, duration_count, duration_sum)
+        SELECT 0, project_full_path, ref_name, status, bucket, -count, -duration_count, -duration_sum FROM pipeline_totals_moved
+        WHERE 1=1
+        ON CONFLICT (namespace, project_full_path, ref_name, status, bucket) DO UPDATE SET
+            count = pipeline_totals_new.count + excluded.count,
+            duration_count = pipeline_totals_new.duration_count + excluded.duration_count,
+            duration_sum = pipeline_totals_new.duration_sum + excluded.duration_sum;
+        DELETE FROM pipeline_totals_new WHERE count <= 0;
+        INSERT INTO pipeline_totals_new (namespace, project_full_path, ref_name, status, bucket, count, duration_count, duration_sum)
+        SELECT namespace, project_full_path, ref_name, status, bucket, count, duration_count, duration_sum FROM pipeline_totals_moved;
+        DROP TABLE pipeline_totals_moved;
+        DROP TABLE pipeline_totals;
+        ALTER TABLE pipeline_totals_new RENAME TO pipeline_totals;
+        "#,
+        legacy_check: None,
+    },
+];
+
+/// The same schema versions for PostgreSQL, which never predates `schema_version`.
+pub const POSTGRES: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial pipelines, poll_state and daily_stats schema",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS pipelines (
+            id BIGINT PRIMARY KEY,
+            project_id BIGINT NOT NULL,
+            project_name TEXT NOT NULL,
+            project_full_path TEXT NOT NULL,
+            ref_name TEXT NOT NULL,
+            user_name TEXT,
+            sha TEXT,
+            status TEXT NOT NULL,
+            created_at BIGINT NOT NULL,
+            finished_at BIGINT,
+            duration BIGINT,
+            web_url TEXT
+        );
+        CREATE TABLE IF NOT EXISTS poll_state (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            last_poll_at BIGINT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS daily_stats (
+            date TEXT NOT NULL,
+            project_id BIGINT NOT NULL,
+            project_name TEXT NOT NULL,
+            project_full_path TEXT NOT NULL,
+            status TEXT NOT NULL,
+            count BIGINT DEFAULT 0,
+            total_duration BIGINT DEFAULT 0,
+            PRIMARY KEY (date, project_id, status)
+        );
+        CREATE INDEX IF NOT EXISTS idx_query ON pipelines(project_name, status, created_at);
+        CREATE INDEX IF NOT EXISTS idx_status_created ON pipelines(status, created_at DESC);
+        CREATE INDEX IF NOT EXISTS idx_project_created ON pipelines(project_name, created_at DESC);
+        CREATE INDEX IF NOT EXISTS idx_watermark ON pipelines(finished_at);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 2,
+        description: "daily_stats.count_with_duration",
+        sql: "ALTER TABLE daily_stats ADD COLUMN IF NOT EXISTS count_with_duration BIGINT DEFAULT 0;",
+        legacy_check: None,
+    },
+    Migration {
+        version: 3,
+        description: "jobs table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS jobs (
+            id BIGINT PRIMARY KEY,
+            pipeline_id BIGINT NOT NULL REFERENCES pipelines(id) ON DELETE CASCADE,
+            project_id BIGINT NOT NULL,
+            name TEXT NOT NULL,
+            stage TEXT NOT NULL,
+            status TEXT NOT NULL,
+            runner_id BIGINT,
+            runner_description TEXT,
+            created_at BIGINT NOT NULL,
+            started_at BIGINT,
+            finished_at BIGINT,
+            duration DOUBLE PRECISION,
+            queued_duration DOUBLE PRECISION,
+            retried BOOLEAN NOT NULL DEFAULT FALSE,
+            allow_failure BOOLEAN NOT NULL DEFAULT FALSE,
+            web_url TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_jobs_pipeline ON jobs(pipeline_id);
+        CREATE INDEX IF NOT EXISTS idx_jobs_project_created ON jobs(project_id, created_at DESC);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 4,
+        description: "per-scope poll watermarks",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS poll_watermarks (
+            scope TEXT NOT NULL,
+            scope_key TEXT NOT NULL,
+            last_poll_at BIGINT NOT NULL,
+            PRIMARY KEY (scope, scope_key)
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 5,
+        description: "pipelines.created_at index for retention pruning",
+        sql: "CREATE INDEX IF NOT EXISTS idx_created ON pipelines(created_at);",
+        legacy_check: None,
+    },
+    Migration {
+        version: 6,
+        description: "hourly_stats rollup, seeded from pipelines",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS hourly_stats (
+            hour TEXT NOT NULL,
+            project_id BIGINT NOT NULL,
+            project_name TEXT NOT NULL,
+            project_full_path TEXT NOT NULL,
+            status TEXT NOT NULL,
+            count BIGINT DEFAULT 0,
+            total_duration BIGINT DEFAULT 0,
+            count_with_duration BIGINT DEFAULT 0,
+            PRIMARY KEY (hour, project_id, status)
+        );
+        INSERT INTO hourly_stats (hour, project_id, project_name, project_full_path, status, count, total_duration, count_with_duration)
+        SELECT to_char(to_timestamp(created_at) AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:00'),
+               project_id,
+               MAX(project_name),
+               MAX(project_full_path),
+               status,
+               COUNT(*),
+               COALESCE(SUM(duration), 0),
+               COUNT(duration)
+        FROM pipelines
+        GROUP BY 1, project_id, status
+        ON CONFLICT(hour, project_id, status) DO NOTHING;
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 7,
+        description: "settings key/value table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 8,
+        description: "daily_duration_buckets rollup, seeded from pipelines",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS daily_duration_buckets (
+            date TEXT NOT NULL,
+            project_id BIGINT NOT NULL,
+            project_name TEXT NOT NULL,
+            project_full_path TEXT NOT NULL,
+            bucket BIGINT NOT NULL,
+            count BIGINT DEFAULT 0,
+            PRIMARY KEY (date, project_id, bucket)
+        );
+        INSERT INTO daily_duration_buckets (date, project_id, project_name, project_full_path, bucket, count)
+        SELECT to_char(to_timestamp(created_at) AT TIME ZONE 'UTC', 'YYYY-MM-DD'),
+               project_id,
+               MAX(project_name),
+               MAX(project_full_path),
+               CASE WHEN duration <= 100 THEN duration
+                    WHEN duration <= 1000 THEN (duration + 9) / 10 * 10
+                    WHEN duration <= 10000 THEN (duration + 99) / 100 * 100
+                    WHEN duration <= 100000 THEN (duration + 999) / 1000 * 1000
+                    WHEN duration <= 1000000 THEN (duration + 9999) / 10000 * 10000
+                    ELSE (duration + 99999) / 100000 * 100000 END,
+               COUNT(*)
+        FROM pipelines
+        WHERE duration IS NOT NULL
+        GROUP BY 1, project_id, 5
+        ON CONFLICT(date, project_id, bucket) DO NOTHING;
+        -- The seed is bucketed by UTC dates; have startup rebuild the rollups in the reporting timezone
+        UPDATE settings SET value = 'UTC' WHERE key = 'rollup_timezone';
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 9,
+        description: "pipeline start and queue times, daily_stats queue totals",
+        sql: r#"
+        ALTER TABLE pipelines ADD COLUMN IF NOT EXISTS started_at BIGINT;
+        ALTER TABLE pipelines ADD COLUMN IF NOT EXISTS queued_duration BIGINT;
+        ALTER TABLE daily_stats ADD COLUMN IF NOT EXISTS total_queued_duration BIGINT DEFAULT 0;
+        ALTER TABLE daily_stats ADD COLUMN IF NOT EXISTS count_with_queued_duration BIGINT DEFAULT 0;
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 10,
+        description: "environments and deployments tables",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS environments (
+            id BIGINT PRIMARY KEY,
+            project_id BIGINT NOT NULL,
+            name TEXT NOT NULL,
+            tier TEXT,
+            state TEXT
+        );
+        CREATE TABLE IF NOT EXISTS deployments (
+            id BIGINT PRIMARY KEY,
+            project_id BIGINT NOT NULL,
+            project_full_path TEXT NOT NULL,
+            environment_id BIGINT NOT NULL,
+            environment_name TEXT NOT NULL,
+            ref_name TEXT NOT NULL,
+            sha TEXT NOT NULL,
+            status TEXT NOT NULL,
+            created_at BIGINT NOT NULL,
+            finished_at BIGINT,
+            commit_created_at BIGINT,
+            pipeline_id BIGINT
+        );
+        CREATE INDEX IF NOT EXISTS idx_deployments_created ON deployments(created_at);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 11,
+        description: "pipeline source, daily_stats grouped by source",
+        sql: r#"
+        ALTER TABLE pipelines ADD COLUMN IF NOT EXISTS source TEXT;
+        ALTER TABLE daily_stats ADD COLUMN IF NOT EXISTS source TEXT NOT NULL DEFAULT '';
+        ALTER TABLE daily_stats DROP CONSTRAINT IF EXISTS daily_stats_pkey;
+        ALTER TABLE daily_stats ADD PRIMARY KEY (date, project_id, status, source);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 12,
+        description: "pipeline merge requests and merge_requests table",
+        sql: r#"
+        ALTER TABLE pipelines ADD COLUMN IF NOT EXISTS merge_request_iid BIGINT;
+        CREATE INDEX IF NOT EXISTS idx_pipelines_merge_request ON pipelines(project_id, merge_request_iid);
+        CREATE TABLE IF NOT EXISTS merge_requests (
+            project_id BIGINT NOT NULL,
+            iid BIGINT NOT NULL,
+            title TEXT NOT NULL,
+            state TEXT,
+            source_branch TEXT,
+            target_branch TEXT,
+            web_url TEXT,
+            created_at BIGINT,
+            merged_at BIGINT,
+            PRIMARY KEY (project_id, iid)
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 13,
+        description: "test_suites table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS test_suites (
+            pipeline_id BIGINT NOT NULL REFERENCES pipelines(id) ON DELETE CASCADE,
+            project_id BIGINT NOT NULL,
+            name TEXT NOT NULL,
+            total_time DOUBLE PRECISION NOT NULL DEFAULT 0,
+            total_count BIGINT NOT NULL DEFAULT 0,
+            success_count BIGINT NOT NULL DEFAULT 0,
+            failed_count BIGINT NOT NULL DEFAULT 0,
+            skipped_count BIGINT NOT NULL DEFAULT 0,
+            error_count BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (pipeline_id, name)
+        );
+        CREATE INDEX IF NOT EXISTS idx_test_suites_project ON test_suites(project_id, name);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 14,
+        description: "pipeline coverage",
+        sql: "ALTER TABLE pipelines ADD COLUMN IF NOT EXISTS coverage DOUBLE PRECISION;",
+        legacy_check: None,
+    },
+    Migration {
+        version: 15,
+        description: "runners table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS runners (
+            id BIGINT PRIMARY KEY,
+            description TEXT,
+            runner_type TEXT,
+            status TEXT,
+            paused BOOLEAN NOT NULL DEFAULT FALSE,
+            executor TEXT,
+            tags TEXT NOT NULL DEFAULT '',
+            contacted_at BIGINT
+        );
+        CREATE INDEX IF NOT EXISTS idx_jobs_runner_created ON jobs(runner_id, created_at);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 16,
+        description: "instances table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS instances (
+            url TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            namespace BIGINT NOT NULL UNIQUE
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 17,
+        description: "projects table",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS projects (
+            id BIGINT PRIMARY KEY,
+            name TEXT NOT NULL,
+            full_path TEXT NOT NULL,
+            default_branch TEXT,
+            state TEXT NOT NULL DEFAULT 'active',
+            previous_full_path TEXT,
+            first_seen_at BIGINT NOT NULL,
+            last_seen_at BIGINT NOT NULL,
+            state_changed_at BIGINT NOT NULL
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 18,
+        description: "project metadata",
+        sql: r#"
+        ALTER TABLE projects ADD COLUMN visibility TEXT;
+        ALTER TABLE projects ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
+        ALTER TABLE projects ADD COLUMN topics TEXT NOT NULL DEFAULT '';
+        UPDATE projects SET archived = TRUE WHERE state = 'archived';
+        CREATE INDEX IF NOT EXISTS idx_projects_full_path ON projects(full_path);
+        CREATE INDEX IF NOT EXISTS idx_pipelines_project_created ON pipelines(project_id, created_at DESC);
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 19,
+        description: "pipeline_totals of pruned pipelines",
+        sql: r#"
+        CREATE TABLE IF NOT EXISTS pipeline_totals (
+            project_full_path TEXT NOT NULL,
+            ref_name TEXT NOT NULL,
+            status TEXT NOT NULL,
+            bucket BIGINT NOT NULL,
+            count BIGINT NOT NULL DEFAULT 0,
+            duration_count BIGINT NOT NULL DEFAULT 0,
+            duration_sum BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (project_full_path, ref_name, status, bucket)
+        );
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 20,
+        description: "pipeline_totals of every pipeline, counted when it first finishes",
+        sql: r#"
+        ALTER TABLE pipelines ADD COLUMN counted BOOLEAN NOT NULL DEFAULT FALSE;
+        -- Until now pipeline_totals only held pruned pipelines; add the stored finished ones
+        INSERT INTO pipeline_totals (project_full_path, ref_name, status, bucket, count, duration_count, duration_sum)
+        SELECT project_full_path, ref_name, status,
+               CASE WHEN d <= 100 THEN d
+                    WHEN d <= 1000 THEN (d + 9) / 10 * 10
+                    WHEN d <= 10000 THEN (d + 99) / 100 * 100
+                    WHEN d <= 100000 THEN (d + 999) / 1000 * 1000
+                    WHEN d <= 1000000 THEN (d + 9999) / 10000 * 10000
+                    ELSE (d + 99999) / 100000 * 100000 END,
+               COUNT(*), COUNT(duration), SUM(d)
+        FROM (SELECT project_full_path, ref_name, status, duration, COALESCE(duration, 0) AS d FROM pipelines
+              WHERE status IN ('success', 'failed', 'canceled', 'skipped')) t
+        WHERE 1=1
+        GROUP BY 1, 2, 3, 4
+        ON CONFLICT (project_full_path, ref_name, status, bucket) DO UPDATE SET
+            count = pipeline_totals.count + excluded.count,
+            duration_count = pipeline_totals.duration_count + excluded.duration_count,
+            duration_sum = pipeline_totals.duration_sum + excluded.duration_sum;
+        UPDATE pipelines SET counted = TRUE WHERE status IN ('success', 'failed', 'canceled', 'skipped');
+        "#,
+        legacy_check: None,
+    },
+    Migration {
+        version: 21,
+        description: "pipeline_totals per instance",
+        sql: r#"
+        ALTER TABLE pipeline_totals RENAME TO pipeline_totals_new;
+        ALTER TABLE pipeline_totals_new ADD COLUMN namespace BIGINT NOT NULL DEFAULT 0;
+        ALTER TABLE pipeline_totals_new DROP CONSTRAINT pipeline_totals_pkey;
+        ALTER TABLE pipeline_totals_new ADD PRIMARY KEY (namespace, project_full_path, ref_name, status, bucket);
+        -- Totals kept no instance so far: leave them with the first one, moving the stored
+        -- pipelines of the others to their own
+        CREATE TABLE pipeline_totals_moved AS
+        SELECT id / 1099511627776 AS namespace, project_full_path, ref_name, status,
+               CASE WHEN d <= 100 THEN d
+                    WHEN d <= 1000 THEN (d + 9) / 10 * 10
+                    WHEN d <= 10000 THEN (d + 99) / 100 * 100
+                    WHEN d <= 100000 THEN (d + 999) / 1000 * 1000
+                    WHEN d <= 1000000 THEN (d + 9999) / 10000 * 10000
+                    ELSE (d + 99999) / 100000 * 100000 END AS bucket,
+               COUNT(*) AS count, COUNT(duration) AS duration_count, SUM(d) AS duration_sum
+        FROM (SELECT id, project_full_path, ref_name, status, duration, COALESCE(duration, 0) AS d FROM pipelines
+              WHERE counted AND id >= 1099511627776) t
+        GROUP BY 1, 2, 3, 4, 5;
+        INSERT INTO pipeline_totals_new (namespace, project_full_path, ref_name, status, bucket, count, duration_count, duration_sum)
+        SELECT 0, project_full_path, ref_name, status, bucket, -count, -duration_count, -duration_sum FROM pipeline_totals_moved
+        WHERE 1=1
+        ON CONFLICT (namespace, project_full_path, ref_name, status, bucket) DO UPDATE SET
+            count = pipeline_totals_new.count + excluded.count,
+            duration_count = pipeline_totals_new.duration_count + excluded.duration_count,
+            duration_sum = pipeline_totals_new.duration_sum + excluded.duration_sum;
+        DELETE FROM pipeline_totals_new WHERE count <= 0;
+        INSERT INTO pipeline_totals_new (namespace, project_full_path, ref_name, status, bucket, count, duration_count, duration_sum)
+        SELECT namespace, project_full_path, ref_name, status, bucket, count, duration_count, duration_sum FROM pipeline_totals_moved;
+        DROP TABLE pipeline_totals_moved;
+        ALTER TABLE pipeline_totals_new RENAME TO pipeline_totals;
+        ALTER TABLE pipeline_totals RENAME CONSTRAINT pipeline_totals_new_pkey TO pipeline_totals_pkey;
+        "#,
+        legacy_check: None,
+    },
+];
+
+/// Highest schema version this binary knows how to use.
+fn latest_version(migrations: &[Migration]) -> i64 {
+    migrations.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Apply all pending migrations in order, each in its own transaction.
+/// Refuses to touch a database written by a newer release.
+pub async fn run_sqlite(pool: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER PRIMARY KEY, description TEXT NOT NULL, applied_at INTEGER NOT NULL)",
+    )
+    .execute(pool)
+    .await?;
+
+    let current: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    let latest = latest_version(SQLITE);
+    if current > latest {
+        bail!(
+            "database schema version {} is newer than the latest version {} supported by this build; refusing to start",
+            current,
+            latest
+        );
+    }
+
+    for m in SQLITE.iter().filter(|m| m.version > current) {
+        let mut tx = pool.begin().await?;
+
+        let already_present = match m.legacy_check {
+            Some(check) => sqlx::query_scalar::<_, i64>(check).fetch_optional(&mut *tx).await?.is_some(),
+            None => false,
+        };
+        if already_present {
+            info!("Schema migration {} ({}) already present; recording it", m.version, m.description);
+        } else {
+            info!("Applying schema migration {}: {}", m.version, m.description);
+            sqlx::query(m.sql).execute(&mut *tx).await?;
+        }
+
+        sqlx::query("INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?)")
+            .bind(m.version)
+            .bind(m.description)
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+/// PostgreSQL counterpart of `run_sqlite`; migrations run through the simple
+/// query protocol so one migration may hold several statements.
+pub async fn run_postgres(pool: &Pool<Postgres>) -> Result<()> {
+    pool.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (version BIGINT PRIMARY KEY, description TEXT NOT NULL, applied_at BIGINT NOT NULL)",
+    )
+    .await?;
+
+    let current: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+        .fetch_one(pool)
+        .await?;
+    let latest = latest_version(POSTGRES);
+    if current > latest {
+        bail!(
+            "database schema version {} is newer than the latest version {} supported by this build; refusing to start",
+            current,
+            latest
+        );
+    }
+
+    for m in POSTGRES.iter().filter(|m| m.version > current) {
+        let mut tx = pool.begin().await?;
+        info!("Applying schema migration {}: {}", m.version, m.description);
+        (&mut *tx).execute(m.sql).await?;
+        sqlx::query("INSERT INTO schema_version (version, description, applied_at) VALUES ($1, $2, $3)")
+            .bind(m.version)
+            .bind(m.description)
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,588 @@
+mod filters;
+mod migrations;
+mod postgres;
+mod sql;
+mod sqlite;
+
+use crate::config::{DatabaseConfig, GitLabConfig};
+use crate::models::{
+    CoverageTrendPoint, DailyStat, Deployment, DurationBucketCount, DurationPercentiles, Environment, FlakyStat,
+    Granularity, Job, JobFilter, JobRow, JobStat, LabeledCount, LabeledHistogram, LabeledLastStatus, MergeRequest,
+    MergeRequestStat, Pipeline, PipelineFilter, PipelinePage, Project, ProjectStat, Runner, RunnerJobStat, SummaryStat,
+    TestSuite, TestSuiteStat, TestTrendPoint,
+};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use chrono::{Offset, TimeZone};
+use chrono_tz::Tz;
+use std::sync::Arc;
+
+pub use postgres::PostgresStorage;
+pub use sqlite::SqliteStorage;
+
+/// Watermark scope for a monitored group, keyed by its full path.
+pub const SCOPE_GROUP: &str = "group";
+
+/// Watermark scope for a project monitored on its own, keyed by its full path.
+pub const SCOPE_PROJECT: &str = "project";
+
+/// Watermark scope for the deployments of a project, keyed by its id.
+pub const SCOPE_DEPLOYMENTS: &str = "deployments";
+
+/// `settings` key holding the timezone the rollups are currently bucketed in.
+pub const SETTING_ROLLUP_TIMEZONE: &str = "rollup_timezone";
+/// `settings` key holding the first `daily_stats.date` bucketed in `rollup_timezone`; earlier
+/// days lost their raw pipelines to retention and keep the timezone they were written in.
+pub const SETTING_ROLLUP_TIMEZONE_FROM: &str = "rollup_timezone_from";
+
+/// What `duration_buckets` groups its counts by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DurationGroup {
+    All,
+    /// Keyed by the project's current path, which both paths of `project_stats` report.
+    Project,
+    Day,
+}
+
+/// Everything the exporter reads from or writes to its database.
+///
+/// Implemented once in `sql::SqlStorage`; the backends only supply their SQL `Dialect`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Pipelines stored with an id in `[first, end)`.
+    async fn pipeline_count(&self, id_range: (i64, i64)) -> Result<i64>;
+    async fn daily_stats_count(&self) -> Result<i64>;
+
+    async fn get_last_poll(&self) -> Result<Option<i64>>;
+    async fn set_last_poll(&self, ts: i64) -> Result<()>;
+    async fn get_watermark(&self, scope: &str, key: &str) -> Result<Option<i64>>;
+    async fn set_watermark(&self, scope: &str, key: &str, ts: i64) -> Result<()>;
+    async fn get_setting(&self, key: &str) -> Result<Option<String>>;
+    async fn set_setting(&self, key: &str, value: &str) -> Result<()>;
+    /// Seed per-group watermarks from the legacy single-row `poll_state` table.
+    /// Groups that already have a watermark keep it.
+    async fn migrate_poll_state(&self, groups: &[String]) -> Result<()>;
+    /// Id namespace of each GitLab instance, in order. Instances are known by URL, so renaming
+    /// one keeps its data; URLs seen for the first time get the next free namespace, starting at 0.
+    async fn register_instances(&self, instances: &[GitLabConfig]) -> Result<Vec<i64>>;
+
+    /// Upsert a pipeline and keep its `daily_stats`, `hourly_stats` and `daily_duration_buckets`
+    /// rows in step, atomically. A pipeline not stored yet and created before `floor` is
+    /// skipped: retention may have pruned it, leaving it counted in the rollups and totals.
+    async fn upsert_pipeline(&self, p: &Pipeline, floor: i64) -> Result<()>;
+    async fn pipeline_exists(&self, id: i64) -> Result<bool>;
+    async fn project_has_pipelines(&self, project_id: i64) -> Result<bool>;
+    /// `(id, project_id)` of pipelines with an id in `[first, end)` whose user name is still unknown.
+    async fn pipelines_missing_user(&self, id_range: (i64, i64), limit: i64) -> Result<Vec<(i64, i64)>>;
+    async fn set_pipeline_user(&self, id: i64, user_name: &str) -> Result<()>;
+    /// `(id, project_id)` of finished pipelines created since `since` that have no jobs stored,
+    /// walking by id from `after_id` up to `before_id`.
+    async fn pipelines_without_jobs(&self, after_id: i64, before_id: i64, since: i64, limit: i64) -> Result<Vec<(i64, i64)>>;
+    /// Upsert jobs in one transaction and mark older attempts of the same job as retried.
+    async fn upsert_jobs(&self, jobs: &[Job]) -> Result<()>;
+    /// Replace the test suites stored for `pipeline_id`.
+    async fn replace_test_suites(&self, pipeline_id: i64, suites: &[TestSuite]) -> Result<()>;
+    async fn upsert_environments(&self, environments: &[Environment]) -> Result<()>;
+    async fn upsert_deployments(&self, deployments: &[Deployment]) -> Result<()>;
+    /// Replace the stored runners with `runners`.
+    async fn replace_runners(&self, runners: &[Runner]) -> Result<()>;
+    /// Upsert merge requests; a known merge time, and the state that goes with it, is never cleared.
+    async fn upsert_merge_requests(&self, merge_requests: &[MergeRequest]) -> Result<()>;
+    /// Upsert projects; the time a project was first seen is kept.
+    async fn upsert_projects(&self, projects: &[Project]) -> Result<()>;
+    /// Stored projects with an id in `[first, end)`.
+    async fn list_projects(&self, id_range: (i64, i64)) -> Result<Vec<Project>>;
+
+    /// Replace the `daily_stats`, `hourly_stats` and `daily_duration_buckets` rows from the day
+    /// of `since` onwards with ones rebuilt from the stored pipelines. Days before `since` may
+    /// have lost raw rows to retention and keep their rollups.
+    ///
+    /// With `previous_tz`, the rollups were bucketed in that timezone and `since` is one of its
+    /// midnights: pipelines up to the next midnight of the reporting timezone stay bucketed in
+    /// `previous_tz`, so that no stored pipeline is dropped or counted twice at the switch.
+    async fn rebuild_rollups(&self, since: i64, previous_tz: Option<Tz>) -> Result<()>;
+
+    /// Delete up to `limit` pipelines created before `before`, with their jobs. Returns the
+    /// number of pipelines deleted.
+    async fn prune_pipelines(&self, before: i64, limit: i64) -> Result<u64>;
+    /// Delete up to `limit` `daily_stats` rows dated before `before` (`YYYY-MM-DD`).
+    async fn prune_daily_stats(&self, before: &str, limit: i64) -> Result<u64>;
+    /// Delete up to `limit` `hourly_stats` rows before `before` (`YYYY-MM-DD HH:00`).
+    async fn prune_hourly_stats(&self, before: &str, limit: i64) -> Result<u64>;
+    /// Delete up to `limit` `daily_duration_buckets` rows dated before `before` (`YYYY-MM-DD`).
+    async fn prune_duration_buckets(&self, before: &str, limit: i64) -> Result<u64>;
+
+    /// One page of pipelines matching `filter`, plus the first row of the next page if there is one.
+    async fn list_pipelines(&self, filter: &PipelineFilter, page: &PipelinePage) -> Result<Vec<Pipeline>>;
+    async fn project_stats(&self, filter: &PipelineFilter) -> Result<Vec<ProjectStat>>;
+    async fn summary_stats(&self, filter: &PipelineFilter) -> Result<SummaryStat>;
+    /// Counts of pipelines with a duration per `duration_bucket`, keyed by project path,
+    /// local date or `""` as `group` asks; feed each key's buckets to `duration_percentiles`.
+    async fn duration_buckets(&self, filter: &PipelineFilter, group: DurationGroup) -> Result<Vec<DurationBucketCount>>;
+    /// Pipeline counts per status and time bucket between `start_ts` and `end_ts`.
+    async fn stats_trend(&self, filter: &PipelineFilter, start_ts: i64, end_ts: i64, granularity: Granularity) -> Result<Vec<DailyStat>>;
+    /// Id, name and path of the projects with pipelines but no `projects` row, such as ones
+    /// polled before projects were stored or from an instance no longer configured.
+    async fn list_pipeline_projects(&self) -> Result<Vec<(i64, String, String)>>;
+    async fn list_refs(&self) -> Result<Vec<String>>;
+    async fn list_jobs(&self, filter: &JobFilter) -> Result<Vec<JobRow>>;
+    async fn job_stats(&self, filter: &JobFilter) -> Result<Vec<JobStat>>;
+    async fn list_runners(&self) -> Result<Vec<Runner>>;
+    /// Per runner, the jobs matching `filter` it picked up.
+    async fn runner_job_stats(&self, filter: &JobFilter) -> Result<Vec<RunnerJobStat>>;
+    /// Per project and ref, the commits whose pipelines both failed and succeeded, most flaky first.
+    async fn flaky_stats(&self, filter: &PipelineFilter) -> Result<Vec<FlakyStat>>;
+    /// Failed and successful pipelines of the flaky commits counted by `flaky_stats`, newest first.
+    async fn flaky_pipelines(&self, filter: &PipelineFilter) -> Result<Vec<Pipeline>>;
+    /// Failed and successful pipelines on any of `refs` in the filter window, oldest first.
+    async fn pipeline_outcomes(&self, filter: &PipelineFilter, refs: &[String]) -> Result<Vec<Pipeline>>;
+    /// Deployments created in the filter window with their environment tier and the current
+    /// path of their project, oldest first.
+    async fn list_deployments(&self, filter: &PipelineFilter) -> Result<Vec<Deployment>>;
+    /// Per merge request, the pipelines it ran in the filter window, most recently active first.
+    async fn merge_request_stats(&self, filter: &PipelineFilter) -> Result<Vec<MergeRequestStat>>;
+    /// Test counts per local date of the pipelines with a test report, newest first.
+    async fn test_trend(&self, filter: &PipelineFilter) -> Result<Vec<TestTrendPoint>>;
+    /// Per project and test suite, its runs and failures in the filter window, most failing first.
+    async fn test_suite_stats(&self, filter: &PipelineFilter) -> Result<Vec<TestSuiteStat>>;
+    /// Coverage per project, ref and local date of the successful pipelines that report one.
+    async fn coverage_trend(&self, filter: &PipelineFilter) -> Result<Vec<CoverageTrendPoint>>;
+
+    /// Finished pipeline counts from `pipeline_totals` grouped by the given columns or
+    /// `instance`, the name of the pipelines' instance. Each pipeline is counted once, when it
+    /// first finishes, and pruning keeps the totals, so the counts never go down.
+    async fn metric_pipeline_counts(&self, columns: &[&str]) -> Result<Vec<LabeledCount>>;
+    /// Duration histograms (cumulative counts per upper bound) of the finished pipelines in
+    /// `pipeline_totals` grouped like `metric_pipeline_counts`. Durations are compared by their `duration_bucket`.
+    async fn metric_duration_histograms(&self, columns: &[&str], buckets: &[f64]) -> Result<Vec<LabeledHistogram>>;
+    /// Status and creation time of the most recent pipeline per group of the given `pipelines`
+    /// columns or `instance`.
+    async fn metric_last_pipelines(&self, columns: &[&str]) -> Result<Vec<LabeledLastStatus>>;
+}
+
+/// Connect to the configured database, apply pending migrations and seed `poll_state`.
+/// Rollups are bucketed by the calendar of `tz`.
+pub async fn init_db(config: &DatabaseConfig, tz: Tz) -> Result<Arc<dyn Storage>> {
+    let url = config.url();
+    let max_connections = config.max_connections.unwrap_or(5);
+
+    let storage: Arc<dyn Storage> = if url.starts_with("sqlite:") {
+        Arc::new(SqliteStorage::connect(url, max_connections, tz).await?)
+    } else if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        Arc::new(PostgresStorage::connect(url, max_connections, tz).await?)
+    } else {
+        bail!("unsupported database url '{}': expected sqlite: or postgres://", url);
+    };
+
+    let current_time = chrono::Utc::now().timestamp();
+    if storage.get_last_poll().await?.is_none() {
+        storage.set_last_poll(current_time).await?;
+    }
+    Ok(storage)
+}
+
+/// One signed adjustment of the `daily_stats` and `hourly_stats` rows covering `created_at`.
+/// `hourly_stats` has no queue totals and ignores those fields.
+struct RollupDelta {
+    created_at: i64,
+    status: String,
+    /// Pipeline source, `''` when unknown.
+    source: String,
+    count: i64,
+    total_duration: i64,
+    count_with_duration: i64,
+    total_queued_duration: i64,
+    count_with_queued_duration: i64,
+}
+
+impl RollupDelta {
+    /// `sign` (1 or -1) times one pipeline with the given durations.
+    fn pipeline(created_at: i64, status: String, source: String, sign: i64, duration: Option<i64>, queued_duration: Option<i64>) -> Self {
+        RollupDelta {
+            created_at,
+            status,
+            source,
+            count: sign,
+            total_duration: sign * duration.unwrap_or(0),
+            count_with_duration: sign * duration.is_some() as i64,
+            total_queued_duration: sign * queued_duration.unwrap_or(0),
+            count_with_queued_duration: sign * queued_duration.is_some() as i64,
+        }
+    }
+}
+
+/// `daily_stats.date` key of a unix timestamp in `tz`, e.g. `2024-05-01`.
+pub fn local_date(ts: i64, tz: Tz) -> String {
+    tz.timestamp_opt(ts, 0).single()
+        .map(|dt| dt.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "1970-01-01".to_string())
+}
+
+/// `hourly_stats.hour` key of a unix timestamp in `tz`, e.g. `2024-05-01 13:00`.
+pub fn local_hour(ts: i64, tz: Tz) -> String {
+    tz.timestamp_opt(ts, 0).single()
+        .map(|dt| dt.format("%Y-%m-%d %H:00").to_string())
+        .unwrap_or_else(|| "1970-01-01 00:00".to_string())
+}
+
+/// Unix timestamp of the first instant of the calendar day containing `ts` in `tz`.
+pub fn local_midnight(ts: i64, tz: Tz) -> i64 {
+    tz.timestamp_opt(ts, 0).single()
+        .and_then(|dt| dt.date_naive().and_hms_opt(0, 0, 0))
+        .and_then(|midnight| tz.from_local_datetime(&midnight).earliest())
+        .map(|dt| dt.timestamp())
+        .unwrap_or(ts)
+}
+
+/// Unix timestamp of the first local midnight in `tz` at or after `ts`.
+pub fn next_local_midnight(ts: i64, tz: Tz) -> i64 {
+    let midnight = local_midnight(ts, tz);
+    if midnight >= ts {
+        midnight
+    } else {
+        // No day is longer than 25 hours, so this lands within the next one
+        local_midnight(midnight + 26 * 3600, tz)
+    }
+}
+
+fn utc_offset(ts: i64, tz: Tz) -> i32 {
+    tz.timestamp_opt(ts, 0).single()
+        .map(|dt| dt.offset().fix().local_minus_utc())
+        .unwrap_or(0)
+}
+
+/// SQL expression shifting the unix timestamp `column` into local time of `tz`, exact for
+/// values between `from` and `to`. For databases without a timezone database: the offset
+/// changes of `tz` are found by probing daily and bisecting, then spelled out as a CASE.
+fn local_epoch_sql(column: &str, tz: Tz, from: i64, to: i64) -> String {
+    let mut segments = vec![(from, utc_offset(from, tz))];
+    let mut ts = from;
+    while ts < to {
+        let next = (ts + 86400).min(to);
+        let (_, current) = segments[segments.len() - 1];
+        if utc_offset(next, tz) != current {
+            let (mut lo, mut hi) = (ts, next);
+            while hi - lo > 1 {
+                let mid = lo + (hi - lo) / 2;
+                if utc_offset(mid, tz) == current { lo = mid } else { hi = mid }
+            }
+            segments.push((hi, utc_offset(hi, tz)));
+        }
+        ts = next;
+    }
+
+    if segments.len() == 1 {
+        return format!("({} + {})", column, segments[0].1);
+    }
+    let mut sql = format!("({} + CASE", column);
+    for pair in segments.windows(2) {
+        sql.push_str(&format!(" WHEN {} < {} THEN {}", column, pair[1].0, pair[0].1));
+    }
+    sql.push_str(&format!(" ELSE {} END)", segments[segments.len() - 1].1));
+    sql
+}
+
+/// `daily_duration_buckets.bucket` of a duration: its upper bound in seconds. Buckets are one
+/// second wide up to 100s and then keep two significant digits, so none is wider than 10%.
+/// `duration_bucket_sql` must compute the same value.
+pub fn duration_bucket(duration: i64) -> i64 {
+    let step = bucket_step(duration);
+    (duration + step - 1) / step * step
+}
+
+/// Width of the bucket ending at (or containing) `duration`.
+fn bucket_step(duration: i64) -> i64 {
+    match duration {
+        ..=100 => 1,
+        101..=1_000 => 10,
+        1_001..=10_000 => 100,
+        10_001..=100_000 => 1_000,
+        100_001..=1_000_000 => 10_000,
+        _ => 100_000,
+    }
+}
+
+/// SQL expression for `duration_bucket` of the integer `column`.
+fn duration_bucket_sql(column: &str) -> String {
+    format!(
+        "CASE WHEN {c} <= 100 THEN {c} \
+         WHEN {c} <= 1000 THEN ({c} + 9) / 10 * 10 \
+         WHEN {c} <= 10000 THEN ({c} + 99) / 100 * 100 \
+         WHEN {c} <= 100000 THEN ({c} + 999) / 1000 * 1000 \
+         WHEN {c} <= 1000000 THEN ({c} + 9999) / 10000 * 10000 \
+         ELSE ({c} + 99999) / 100000 * 100000 END",
+        c = column
+    )
+}
+
+/// SQL expression for the name of the instance with the id namespace `namespace`, `""` for
+/// one that was never registered.
+fn instance_name_sql(namespace: &str) -> String {
+    format!("COALESCE((SELECT name FROM instances WHERE instances.namespace = {}), '')", namespace)
+}
+
+/// SQL expression for the current `column` (`full_path` or `name`) of the project in
+/// `id_column`, so rows of a renamed project, grouped by project id, stay one row under its
+/// current path. Falls back to the `fallback` expression (e.g. `MAX(project_full_path)` in
+/// grouped queries) for projects never discovered.
+fn project_column_sql(column: &str, id_column: &str, fallback: &str) -> String {
+    format!("COALESCE((SELECT {} FROM projects WHERE projects.id = {}), {})", column, id_column, fallback)
+}
+
+/// Estimate duration percentiles from `(bucket, count)` pairs, interpolating linearly inside
+/// the bucket that holds each rank. All zeros when there are no durations.
+pub fn duration_percentiles(buckets: &[(i64, i64)]) -> DurationPercentiles {
+    let mut buckets: Vec<(i64, i64)> = buckets.iter().copied().filter(|&(_, count)| count > 0).collect();
+    buckets.sort_unstable();
+    let total: i64 = buckets.iter().map(|&(_, count)| count).sum();
+
+    let percentile = |q: f64| -> f64 {
+        let rank = q * total as f64;
+        let mut below = 0;
+        for &(upper, count) in &buckets {
+            if ((below + count) as f64) >= rank {
+                let step = bucket_step(upper);
+                if step == 1 {
+                    return upper as f64;
+                }
+                let fraction = (rank - below as f64) / count as f64;
+                return (upper - step) as f64 + step as f64 * fraction;
+            }
+            below += count;
+        }
+        buckets.last().map(|&(upper, _)| upper as f64).unwrap_or(0.0)
+    };
+
+    DurationPercentiles {
+        p50: percentile(0.50),
+        p90: percentile(0.90),
+        p95: percentile(0.95),
+        p99: percentile(0.99),
+    }
+}
+
+/// Signed `daily_duration_buckets` adjustments `(bucket, count)` when `p` is upserted over
+/// `existing`; like the upsert, a known duration is only ever replaced, never cleared.
+fn duration_bucket_deltas(existing: Option<&StoredPipeline>, p: &Pipeline) -> Vec<(i64, i64)> {
+    let old = existing.and_then(|e| e.duration);
+    let new = p.duration.or(old);
+    if old == new {
+        return Vec::new();
+    }
+    old.map(|d| (duration_bucket(d), -1)).into_iter()
+        .chain(new.map(|d| (duration_bucket(d), 1)))
+        .collect()
+}
+
+/// The stored fields of a pipeline that the rollups depend on.
+struct StoredPipeline {
+    status: String,
+    counted: bool,
+    source: Option<String>,
+    duration: Option<i64>,
+    queued_duration: Option<i64>,
+    created_at: i64,
+    finished_at: Option<i64>,
+}
+
+/// Statuses a pipeline finishes in. A retried pipeline leaves them to run again, so
+/// `pipeline_totals` counts it once, in the first of them it is stored with.
+const FINISHED_STATUSES: &[&str] = &["success", "failed", "canceled", "skipped"];
+
+/// Status and duration to add `p` to `pipeline_totals` with, when upserting it over
+/// `existing` first stores it in a finished status. Mirrors the upsert like `rollup_deltas`.
+fn first_finish(existing: Option<&StoredPipeline>, p: &Pipeline) -> Option<(String, Option<i64>)> {
+    let (status, duration) = match existing {
+        None => (&p.status, p.duration),
+        Some(old) if old.counted => return None,
+        Some(old) => {
+            let status = if p.finished_at.is_none() && old.finished_at.is_some() { &old.status } else { &p.status };
+            (status, p.duration.or(old.duration))
+        }
+    };
+    FINISHED_STATUSES.contains(&status.as_str()).then(|| (status.clone(), duration))
+}
+
+/// Work out how the rollups change when `p` is upserted over `existing`.
+/// Mirrors the upsert itself: a finished row keeps its status against an
+/// unfinished update, and a known duration, queue time or source is never cleared.
+fn rollup_deltas(existing: Option<StoredPipeline>, p: &Pipeline) -> Vec<RollupDelta> {
+    let Some(old) = existing else {
+        // New pipeline: count it with its durations under its date/status/source
+        return vec![RollupDelta::pipeline(p.created_at, p.status.clone(), p.source.clone().unwrap_or_default(), 1, p.duration, p.queued_duration)];
+    };
+
+    let new_status = if p.finished_at.is_none() && old.finished_at.is_some() { old.status.clone() } else { p.status.clone() };
+    let new_duration = p.duration.or(old.duration);
+    let new_queued = p.queued_duration.or(old.queued_duration);
+    let old_source = old.source.unwrap_or_default();
+    let new_source = p.source.clone().unwrap_or_else(|| old_source.clone());
+    let created_at = old.created_at;
+
+    if old.status == new_status && old_source == new_source {
+        // Same status and source: only the duration bookkeeping can change
+        if new_duration == old.duration && new_queued == old.queued_duration {
+            return Vec::new();
+        }
+        let removed = RollupDelta::pipeline(created_at, old.status, old_source, -1, old.duration, old.queued_duration);
+        let added = RollupDelta::pipeline(created_at, new_status, new_source, 1, new_duration, new_queued);
+        vec![RollupDelta {
+            count: 0,
+            total_duration: removed.total_duration + added.total_duration,
+            count_with_duration: removed.count_with_duration + added.count_with_duration,
+            total_queued_duration: removed.total_queued_duration + added.total_queued_duration,
+            count_with_queued_duration: removed.count_with_queued_duration + added.count_with_queued_duration,
+            ..added
+        }]
+    } else {
+        // Status or source changed: move the pipeline from the old row to the new one
+        vec![
+            RollupDelta::pipeline(created_at, old.status, old_source, -1, old.duration, old.queued_duration),
+            RollupDelta::pipeline(created_at, new_status, new_source, 1, new_duration, new_queued),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(status: &str, duration: Option<i64>, finished_at: Option<i64>) -> Pipeline {
+        Pipeline {
+            id: 1,
+            project_id: 7,
+            project_name: "app".to_string(),
+            project_full_path: "grp/app".to_string(),
+            ref_name: "main".to_string(),
+            sha: "abc".to_string(),
+            user_name: String::new(),
+            status: status.to_string(),
+            created_at: 1_000,
+            started_at: None,
+            finished_at,
+            duration,
+            queued_duration: None,
+            web_url: None,
+            source: Some("push".to_string()),
+            merge_request_iid: None,
+            coverage: None,
+        }
+    }
+
+    fn stored(status: &str, duration: Option<i64>, finished_at: Option<i64>) -> StoredPipeline {
+        StoredPipeline {
+            status: status.to_string(),
+            counted: false,
+            source: Some("push".to_string()),
+            duration,
+            queued_duration: None,
+            created_at: 500,
+            finished_at,
+        }
+    }
+
+    #[test]
+    fn duration_bucket_keeps_two_significant_digits() {
+        assert_eq!(duration_bucket(0), 0);
+        assert_eq!(duration_bucket(100), 100);
+        assert_eq!(duration_bucket(101), 110);
+        assert_eq!(duration_bucket(110), 110);
+        assert_eq!(duration_bucket(111), 120);
+        assert_eq!(duration_bucket(1_000), 1_000);
+        assert_eq!(duration_bucket(1_001), 1_100);
+        assert_eq!(duration_bucket(12_345), 13_000);
+        assert_eq!(duration_bucket(2_000_001), 2_100_000);
+    }
+
+    #[test]
+    fn duration_percentiles_interpolate_within_buckets() {
+        let p = duration_percentiles(&[(200, 10)]);
+        assert_eq!((p.p50, p.p90, p.p95), (195.0, 199.0, 199.5));
+        assert!((p.p99 - 199.9).abs() < 1e-9);
+
+        // One-second buckets are exact; empty buckets and input order do not matter
+        let p = duration_percentiles(&[(300, 0), (20, 1), (10, 1)]);
+        assert_eq!((p.p50, p.p90), (10.0, 20.0));
+    }
+
+    #[test]
+    fn duration_percentiles_are_zero_without_durations() {
+        let p = duration_percentiles(&[]);
+        assert_eq!((p.p50, p.p90, p.p95, p.p99), (0.0, 0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn rollup_deltas_count_a_new_pipeline() {
+        let deltas = rollup_deltas(None, &pipeline("success", Some(60), Some(1_060)));
+        assert_eq!(deltas.len(), 1);
+        let d = &deltas[0];
+        assert_eq!((d.created_at, d.status.as_str(), d.source.as_str()), (1_000, "success", "push"));
+        assert_eq!((d.count, d.total_duration, d.count_with_duration), (1, 60, 1));
+        assert_eq!((d.total_queued_duration, d.count_with_queued_duration), (0, 0));
+    }
+
+    #[test]
+    fn rollup_deltas_move_a_finished_pipeline_to_its_status() {
+        let deltas = rollup_deltas(Some(stored("running", None, None)), &pipeline("success", Some(60), Some(1_060)));
+        assert_eq!(deltas.len(), 2);
+        // Keyed by the stored creation time
+        assert!(deltas.iter().all(|d| d.created_at == 500));
+        assert_eq!((deltas[0].status.as_str(), deltas[0].count, deltas[0].count_with_duration), ("running", -1, 0));
+        assert_eq!((deltas[1].status.as_str(), deltas[1].count, deltas[1].total_duration), ("success", 1, 60));
+    }
+
+    #[test]
+    fn rollup_deltas_ignore_a_stale_unfinished_update() {
+        let deltas = rollup_deltas(Some(stored("success", Some(60), Some(560))), &pipeline("running", None, None));
+        assert!(deltas.is_empty());
+    }
+
+    #[test]
+    fn rollup_deltas_add_a_late_duration_without_recounting() {
+        let deltas = rollup_deltas(Some(stored("success", None, Some(560))), &pipeline("success", Some(60), Some(560)));
+        assert_eq!(deltas.len(), 1);
+        let d = &deltas[0];
+        assert_eq!((d.status.as_str(), d.count, d.total_duration, d.count_with_duration), ("success", 0, 60, 1));
+    }
+
+    #[test]
+    fn first_finish_counts_a_retried_pipeline_once() {
+        assert_eq!(first_finish(None, &pipeline("running", None, None)), None);
+        let failed = stored("failed", Some(60), Some(560));
+        assert_eq!(first_finish(Some(&failed), &pipeline("failed", Some(60), Some(560))), Some(("failed".to_string(), Some(60))));
+
+        let counted = StoredPipeline { counted: true, ..failed };
+        assert_eq!(first_finish(Some(&counted), &pipeline("running", None, None)), None);
+        assert_eq!(first_finish(Some(&counted), &pipeline("success", Some(120), Some(900))), None);
+    }
+
+    #[test]
+    fn next_local_midnight_rounds_up_to_a_day_start() {
+        let tz: Tz = "Europe/Berlin".parse().unwrap();
+        // 2024-03-31 is 23 hours long in Berlin
+        let midnight = 1711839600; // 2024-03-31 00:00 CET
+        assert_eq!(next_local_midnight(midnight, tz), midnight);
+        assert_eq!(next_local_midnight(midnight + 1, tz), midnight + 23 * 3600);
+        assert_eq!(next_local_midnight(midnight - 1, tz), midnight);
+    }
+
+    #[test]
+    fn local_epoch_sql_is_a_constant_offset_without_transitions() {
+        assert_eq!(local_epoch_sql("created_at", Tz::UTC, 1_709_251_200, 1_714_435_200), "(created_at + 0)");
+        assert_eq!(local_epoch_sql("created_at", Tz::Asia__Shanghai, 1_709_251_200, 1_714_435_200), "(created_at + 28800)");
+    }
+
+    #[test]
+    fn local_epoch_sql_splits_at_dst_transitions() {
+        // Berlin switches to CEST at 2024-03-31 01:00 UTC
+        assert_eq!(
+            local_epoch_sql("created_at", Tz::Europe__Berlin, 1_709_251_200, 1_714_435_200),
+            "(created_at + CASE WHEN created_at < 1711846800 THEN 3600 ELSE 7200 END)"
+        );
+        // ... and back to CET at 2024-10-27 01:00 UTC
+        assert_eq!(
+            local_epoch_sql("created_at", Tz::Europe__Berlin, 1_709_251_200, 1_732_924_800),
+            "(created_at + CASE WHEN created_at < 1711846800 THEN 3600 WHEN created_at < 1729990800 THEN 7200 ELSE 3600 END)"
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use super::sql::{Dialect, SqlStorage};
+use super::migrations;
+use crate::models::Granularity;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono_tz::Tz;
+use sqlx::{FromRow, Pool, Postgres, QueryBuilder};
+
+pub type PostgresStorage = SqlStorage<Postgres>;
+
+#[async_trait]
+impl Dialect for Postgres {
+    const FOR_UPDATE: &'static str = " FOR UPDATE";
+
+    async fn migrate(pool: &Pool<Self>) -> Result<()> {
+        migrations::run_postgres(pool).await
+    }
+
+    fn date_bucket(granularity: Granularity) -> &'static str {
+        match granularity {
+            Granularity::Week => "to_char(date_trunc('week', date::date), 'YYYY-MM-DD')",
+            Granularity::Month => "substr(date, 1, 7)",
+            Granularity::Hour | Granularity::Day => "date",
+        }
+    }
+
+    fn created_at_bucket(granularity: Granularity, column: &str, tz: Tz, _from: i64, _to: i64) -> String {
+        let local = format!("to_timestamp({}) AT TIME ZONE '{}'", column, tz.name());
+        match granularity {
+            Granularity::Hour => format!("to_char({}, 'YYYY-MM-DD HH24:00')", local),
+            Granularity::Day => format!("to_char({}, 'YYYY-MM-DD')", local),
+            Granularity::Week => format!("to_char(date_trunc('week', {}), 'YYYY-MM-DD')", local),
+            Granularity::Month => format!("to_char({}, 'YYYY-MM')", local),
+        }
+    }
+
+    async fn fetch_rows(mut qb: QueryBuilder<'_, Self>, pool: &Pool<Self>) -> sqlx::Result<Vec<Self::Row>> {
+        qb.build().fetch_all(pool).await
+    }
+
+    async fn fetch_all<T>(mut qb: QueryBuilder<'_, Self>, pool: &Pool<Self>) -> sqlx::Result<Vec<T>>
+    where
+        T: for<'r> FromRow<'r, Self::Row> + Send + Unpin,
+    {
+        qb.build_query_as::<T>().fetch_all(pool).await
+    }
+
+    async fn fetch_one<T>(mut qb: QueryBuilder<'_, Self>, pool: &Pool<Self>) -> sqlx::Result<T>
+    where
+        T: for<'r> FromRow<'r, Self::Row> + Send + Unpin,
+    {
+        qb.build_query_as::<T>().fetch_one(pool).await
+    }
+
+    fn rows_affected(result: &Self::QueryResult) -> u64 {
+        result.rows_affected()
+    }
+
+    async fn execute(mut qb: QueryBuilder<'_, Self>, conn: &mut Self::Connection) -> sqlx::Result<u64> {
+        Ok(qb.build().execute(conn).await?.rows_affected())
+    }
+}
@@ -0,0 +1,1359 @@
+use super::filters::{push_commit_outcomes, push_daily_stats_filter, push_date_range, push_hour_range, push_instance_filter, push_job_filters, push_list_filter, push_pipeline_filter, push_pipeline_page, push_project_filter, push_ts_range, PROJECT_IDS};
+use super::{
+    duration_bucket_deltas, duration_bucket_sql, local_date, local_hour, local_midnight, next_local_midnight, first_finish, instance_name_sql, project_column_sql, rollup_deltas,
+    duration_bucket, DurationGroup, Storage, StoredPipeline, SCOPE_GROUP,
+};
+use crate::config::GitLabConfig;
+use crate::instance::NAMESPACE_SHIFT;
+use crate::models::{
+    CoverageTrendPoint, DailyStat, Deployment, DurationBucketCount, Environment, FlakyStat, Granularity, Job, JobFilter,
+    JobRow, JobStat, LabeledCount, LabeledHistogram, LabeledLastStatus, MergeRequest, MergeRequestStat, Pipeline,
+    PipelineFilter, PipelinePage, Project, ProjectStat, Runner, RunnerJobStat, SummaryStat, TestSuite, TestSuiteStat,
+    TestTrendPoint,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono_tz::Tz;
+use sqlx::database::HasArguments;
+use sqlx::pool::PoolOptions;
+use sqlx::query::Query;
+use sqlx::{ColumnIndex, Database, Decode, Encode, Executor, FromRow, IntoArguments, Pool, QueryBuilder, Row, Type};
+
+/// What the SQL shared by the backends cannot spell the same way in SQLite and PostgreSQL.
+///
+/// Fixed statements use `$1`-style placeholders, which both accept. Statements assembled
+/// with a `QueryBuilder` run through the `fetch_*` and `execute` methods, as its arguments
+/// can only be handed to the driver where the database type is known.
+#[async_trait]
+pub trait Dialect: Database {
+    /// Suffix of a `SELECT` that locks the rows it reads until the transaction ends.
+    const FOR_UPDATE: &'static str;
+
+    /// Apply the pending schema migrations.
+    async fn migrate(pool: &Pool<Self>) -> Result<()>;
+
+    /// Trend bucket label computed from `daily_stats.date`; weeks start on Monday.
+    fn date_bucket(granularity: Granularity) -> &'static str;
+
+    /// Trend bucket label of the unix timestamp `column` in local time of `tz`. Only values
+    /// between `from` and `to` are guaranteed to be bucketed exactly.
+    fn created_at_bucket(granularity: Granularity, column: &str, tz: Tz, from: i64, to: i64) -> String;
+
+    async fn fetch_rows(qb: QueryBuilder<'_, Self>, pool: &Pool<Self>) -> sqlx::Result<Vec<Self::Row>>;
+
+    async fn fetch_all<T>(qb: QueryBuilder<'_, Self>, pool: &Pool<Self>) -> sqlx::Result<Vec<T>>
+    where
+        T: for<'r> FromRow<'r, Self::Row> + Send + Unpin;
+
+    async fn fetch_one<T>(qb: QueryBuilder<'_, Self>, pool: &Pool<Self>) -> sqlx::Result<T>
+    where
+        T: for<'r> FromRow<'r, Self::Row> + Send + Unpin;
+
+    /// Rows changed by a statement; each driver's result type has its own `rows_affected`.
+    fn rows_affected(result: &Self::QueryResult) -> u64;
+
+    /// Run the statement on `conn`, returning the number of rows affected.
+    async fn execute(qb: QueryBuilder<'_, Self>, conn: &mut Self::Connection) -> sqlx::Result<u64>;
+}
+
+/// `Storage` on a SQL database, written once for every `Dialect`.
+pub struct SqlStorage<DB: Database> {
+    pool: Pool<DB>,
+    /// Timezone whose calendar buckets the rollups.
+    tz: Tz,
+}
+
+impl<DB: Dialect> SqlStorage<DB> {
+    pub async fn connect(url: &str, max_connections: u32, tz: Tz) -> Result<Self> {
+        let pool = PoolOptions::<DB>::new()
+            .max_connections(max_connections)
+            .connect(url).await?;
+
+        DB::migrate(&pool).await?;
+        Ok(Self { pool, tz })
+    }
+}
+
+// The bounds spell out what the statements below bind and decode
+#[async_trait]
+impl<DB> Storage for SqlStorage<DB>
+where
+    DB: Dialect,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> i64: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> f64: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> bool: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> String: Encode<'q, DB> + Decode<'q, DB> + Type<DB>,
+    for<'q> &'q str: Encode<'q, DB> + Type<DB> + ColumnIndex<DB::Row>,
+    for<'q> Option<i64>: Encode<'q, DB>,
+    for<'q> Option<f64>: Encode<'q, DB>,
+    for<'q> Option<String>: Encode<'q, DB>,
+    usize: ColumnIndex<DB::Row>,
+{
+    async fn pipeline_count(&self, id_range: (i64, i64)) -> Result<i64> {
+        Ok(sqlx::query_scalar("SELECT COUNT(*) FROM pipelines WHERE id >= $1 AND id < $2")
+            .bind(id_range.0)
+            .bind(id_range.1)
+            .fetch_one(&self.pool).await?)
+    }
+
+    async fn daily_stats_count(&self) -> Result<i64> {
+        Ok(sqlx::query_scalar("SELECT COUNT(*) FROM daily_stats").fetch_one(&self.pool).await?)
+    }
+
+    async fn get_last_poll(&self) -> Result<Option<i64>> {
+        let row: Option<i64> = sqlx::query_scalar("SELECT last_poll_at FROM poll_state WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
+    async fn set_last_poll(&self, ts: i64) -> Result<()> {
+        sqlx::query("INSERT INTO poll_state (id, last_poll_at) VALUES (1, $1) ON CONFLICT(id) DO UPDATE SET last_poll_at = excluded.last_poll_at")
+            .bind(ts)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_watermark(&self, scope: &str, key: &str) -> Result<Option<i64>> {
+        let row: Option<i64> = sqlx::query_scalar("SELECT last_poll_at FROM poll_watermarks WHERE scope = $1 AND scope_key = $2")
+            .bind(scope)
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
+    async fn set_watermark(&self, scope: &str, key: &str, ts: i64) -> Result<()> {
+        sqlx::query("INSERT INTO poll_watermarks (scope, scope_key, last_poll_at) VALUES ($1, $2, $3) ON CONFLICT(scope, scope_key) DO UPDATE SET last_poll_at = excluded.last_poll_at")
+            .bind(scope)
+            .bind(key)
+            .bind(ts)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let row: Option<String> = sqlx::query_scalar("SELECT value FROM settings WHERE key = $1")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row)
+    }
+
+    async fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        sqlx::query("INSERT INTO settings (key, value) VALUES ($1, $2) ON CONFLICT(key) DO UPDATE SET value = excluded.value")
+            .bind(key)
+            .bind(value)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn migrate_poll_state(&self, groups: &[String]) -> Result<()> {
+        let Some(last_poll) = self.get_last_poll().await? else { return Ok(()) };
+        for group in groups {
+            sqlx::query("INSERT INTO poll_watermarks (scope, scope_key, last_poll_at) VALUES ($1, $2, $3) ON CONFLICT(scope, scope_key) DO NOTHING")
+                .bind(SCOPE_GROUP)
+                .bind(group)
+                .bind(last_poll)
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn register_instances(&self, instances: &[GitLabConfig]) -> Result<Vec<i64>> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut namespaces = Vec::with_capacity(instances.len());
+        for g in instances {
+            let url = g.url.trim_end_matches('/');
+            let known: Option<i64> = sqlx::query_scalar("SELECT namespace FROM instances WHERE url = $1")
+                .bind(url)
+                .fetch_optional(&mut *tx)
+                .await?;
+            let namespace = match known {
+                Some(ns) => {
+                    sqlx::query("UPDATE instances SET name = $1 WHERE url = $2")
+                        .bind(g.name())
+                        .bind(url)
+                        .execute(&mut *tx)
+                        .await?;
+                    ns
+                }
+                None => {
+                    let next: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(namespace) + 1, 0) FROM instances")
+                        .fetch_one(&mut *tx)
+                        .await?;
+                    sqlx::query("INSERT INTO instances (url, name, namespace) VALUES ($1, $2, $3)")
+                        .bind(url)
+                        .bind(g.name())
+                        .bind(next)
+                        .execute(&mut *tx)
+                        .await?;
+                    next
+                }
+            };
+            namespaces.push(namespace);
+        }
+
+        tx.commit().await?;
+        Ok(namespaces)
+    }
+
+    async fn upsert_pipeline(&self, p: &Pipeline, floor: i64) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        // Claim a new pipeline with a plain insert, so only the writer whose insert lands takes
+        // the "new" path; being a write, it also takes SQLite's write lock before anything is read
+        let inserted = p.created_at >= floor
+            && bind_pipeline(sqlx::query(&format!("INSERT INTO pipelines {} ON CONFLICT(id) DO NOTHING RETURNING id", PIPELINE_VALUES)), p)
+                .fetch_optional(&mut *tx).await?
+                .is_some();
+        let existing = if inserted {
+            None
+        } else {
+            // Lock the row so concurrent webhook and poller updates apply their deltas in turn
+            let existing = sqlx::query_as::<_, (String, bool, Option<String>, Option<i64>, Option<i64>, i64, Option<i64>)>(&format!(
+                "SELECT status, counted, source, duration, queued_duration, created_at, finished_at FROM pipelines WHERE id = $1{}",
+                DB::FOR_UPDATE
+            )).bind(p.id).fetch_optional(&mut *tx).await?
+            .map(|(status, counted, source, duration, queued_duration, created_at, finished_at)| StoredPipeline { status, counted, source, duration, queued_duration, created_at, finished_at });
+            // Not stored and created before the floor: nothing to update
+            let Some(existing) = existing else { return Ok(()) };
+
+            bind_pipeline(sqlx::query(&format!(
+                r#"
+                INSERT INTO pipelines {}
+                ON CONFLICT(id) DO UPDATE SET
+                    status = CASE
+                        WHEN excluded.finished_at IS NULL AND pipelines.finished_at IS NOT NULL THEN pipelines.status
+                        ELSE excluded.status
+                    END,
+                    finished_at = COALESCE(excluded.finished_at, pipelines.finished_at),
+                    sha = excluded.sha,
+                    duration = COALESCE(excluded.duration, pipelines.duration),
+                    started_at = COALESCE(excluded.started_at, pipelines.started_at),
+                    queued_duration = COALESCE(excluded.queued_duration, pipelines.queued_duration),
+                    source = COALESCE(excluded.source, pipelines.source),
+                    merge_request_iid = COALESCE(excluded.merge_request_iid, pipelines.merge_request_iid),
+                    coverage = COALESCE(excluded.coverage, pipelines.coverage),
+                    web_url = COALESCE(excluded.web_url, pipelines.web_url),
+                    user_name = COALESCE(excluded.user_name, pipelines.user_name)
+                "#,
+                PIPELINE_VALUES
+            )), p)
+            .execute(&mut *tx).await?;
+            Some(existing)
+        };
+
+        // /metrics counts a pipeline when it first finishes, so a retry cannot take it back
+        if let Some((status, duration)) = first_finish(existing.as_ref(), p) {
+            sqlx::query(
+                "INSERT INTO pipeline_totals (namespace, project_full_path, ref_name, status, bucket, count, duration_count, duration_sum) VALUES ($1, $2, $3, $4, $5, 1, $6, $7) \
+                 ON CONFLICT (namespace, project_full_path, ref_name, status, bucket) DO UPDATE SET \
+                 count = pipeline_totals.count + 1, \
+                 duration_count = pipeline_totals.duration_count + excluded.duration_count, \
+                 duration_sum = pipeline_totals.duration_sum + excluded.duration_sum",
+            )
+            .bind(p.id >> NAMESPACE_SHIFT)
+            .bind(&p.project_full_path)
+            .bind(&p.ref_name)
+            .bind(status)
+            .bind(duration_bucket(duration.unwrap_or(0)))
+            .bind(duration.is_some() as i64)
+            .bind(duration.unwrap_or(0))
+            .execute(&mut *tx).await?;
+            sqlx::query("UPDATE pipelines SET counted = TRUE WHERE id = $1").bind(p.id).execute(&mut *tx).await?;
+        }
+
+        let created_at = existing.as_ref().map(|e| e.created_at).unwrap_or(p.created_at);
+        for (bucket, count) in duration_bucket_deltas(existing.as_ref(), p) {
+            sqlx::query("INSERT INTO daily_duration_buckets(date, project_id, project_name, project_full_path, bucket, count) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT(date, project_id, bucket) DO UPDATE SET count = daily_duration_buckets.count + excluded.count, project_full_path = excluded.project_full_path")
+                .bind(local_date(created_at, self.tz))
+                .bind(p.project_id)
+                .bind(&p.project_name)
+                .bind(&p.project_full_path)
+                .bind(bucket)
+                .bind(count)
+                .execute(&mut *tx).await?;
+        }
+
+        for d in rollup_deltas(existing, p) {
+            sqlx::query("INSERT INTO daily_stats(date, project_id, project_name, project_full_path, status, source, count, total_duration, count_with_duration, total_queued_duration, count_with_queued_duration) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) ON CONFLICT(date, project_id, status, source) DO UPDATE SET count = daily_stats.count + excluded.count, total_duration = daily_stats.total_duration + excluded.total_duration, count_with_duration = daily_stats.count_with_duration + excluded.count_with_duration, total_queued_duration = daily_stats.total_queued_duration + excluded.total_queued_duration, count_with_queued_duration = daily_stats.count_with_queued_duration + excluded.count_with_queued_duration, project_full_path = excluded.project_full_path")
+                .bind(local_date(d.created_at, self.tz))
+                .bind(p.project_id)
+                .bind(&p.project_name)
+                .bind(&p.project_full_path)
+                .bind(&d.status)
+                .bind(&d.source)
+                .bind(d.count)
+                .bind(d.total_duration)
+                .bind(d.count_with_duration)
+                .bind(d.total_queued_duration)
+                .bind(d.count_with_queued_duration)
+                .execute(&mut *tx).await?;
+            sqlx::query("INSERT INTO hourly_stats(hour, project_id, project_name, project_full_path, status, count, total_duration, count_with_duration) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT(hour, project_id, status) DO UPDATE SET count = hourly_stats.count + excluded.count, total_duration = hourly_stats.total_duration + excluded.total_duration, count_with_duration = hourly_stats.count_with_duration + excluded.count_with_duration, project_full_path = excluded.project_full_path")
+                .bind(local_hour(d.created_at, self.tz))
+                .bind(p.project_id)
+                .bind(&p.project_name)
+                .bind(&p.project_full_path)
+                .bind(&d.status)
+                .bind(d.count)
+                .bind(d.total_duration)
+                .bind(d.count_with_duration)
+                .execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn pipeline_exists(&self, id: i64) -> Result<bool> {
+        let row: Option<i64> = sqlx::query_scalar("SELECT id FROM pipelines WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.is_some())
+    }
+
+    async fn project_has_pipelines(&self, project_id: i64) -> Result<bool> {
+        let row: Option<i64> = sqlx::query_scalar("SELECT id FROM pipelines WHERE project_id = $1 LIMIT 1")
+            .bind(project_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.is_some())
+    }
+
+    async fn pipelines_missing_user(&self, id_range: (i64, i64), limit: i64) -> Result<Vec<(i64, i64)>> {
+        Ok(sqlx::query_as("SELECT id, project_id FROM pipelines WHERE id >= $1 AND id < $2 AND (user_name IS NULL OR user_name = '') LIMIT $3")
+            .bind(id_range.0)
+            .bind(id_range.1)
+            .bind(limit)
+            .fetch_all(&self.pool).await?)
+    }
+
+    async fn set_pipeline_user(&self, id: i64, user_name: &str) -> Result<()> {
+        sqlx::query("UPDATE pipelines SET user_name = $1 WHERE id = $2 AND (user_name IS NULL OR user_name = '')")
+            .bind(user_name)
+            .bind(id)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn pipelines_without_jobs(&self, after_id: i64, before_id: i64, since: i64, limit: i64) -> Result<Vec<(i64, i64)>> {
+        Ok(sqlx::query_as(
+            "SELECT id, project_id FROM pipelines p WHERE id > $1 AND id < $2 AND created_at >= $3 AND finished_at IS NOT NULL AND NOT EXISTS (SELECT 1 FROM jobs j WHERE j.pipeline_id = p.id) ORDER BY id LIMIT $4",
+        )
+        .bind(after_id)
+        .bind(before_id)
+        .bind(since)
+        .bind(limit)
+        .fetch_all(&self.pool).await?)
+    }
+
+    async fn upsert_jobs(&self, jobs: &[Job]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for j in jobs {
+            sqlx::query(
+                r#"
+                INSERT INTO jobs (id, pipeline_id, project_id, name, stage, status, runner_id, runner_description, created_at, started_at, finished_at, duration, queued_duration, retried, allow_failure, web_url)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+                ON CONFLICT(id) DO UPDATE SET
+                    status = excluded.status,
+                    runner_id = COALESCE(excluded.runner_id, jobs.runner_id),
+                    runner_description = COALESCE(excluded.runner_description, jobs.runner_description),
+                    started_at = COALESCE(excluded.started_at, jobs.started_at),
+                    finished_at = COALESCE(excluded.finished_at, jobs.finished_at),
+                    duration = COALESCE(excluded.duration, jobs.duration),
+                    queued_duration = COALESCE(excluded.queued_duration, jobs.queued_duration),
+                    retried = jobs.retried OR excluded.retried,
+                    allow_failure = excluded.allow_failure,
+                    web_url = COALESCE(excluded.web_url, jobs.web_url)
+                "#,
+            ).bind(j.id)
+            .bind(j.pipeline_id)
+            .bind(j.project_id)
+            .bind(&j.name)
+            .bind(&j.stage)
+            .bind(&j.status)
+            .bind(j.runner_id)
+            .bind(&j.runner_description)
+            .bind(j.created_at)
+            .bind(j.started_at)
+            .bind(j.finished_at)
+            .bind(j.duration)
+            .bind(j.queued_duration)
+            .bind(j.retried)
+            .bind(j.allow_failure)
+            .bind(&j.web_url)
+            .execute(&mut *tx).await?;
+        }
+
+        // A newer attempt of a job supersedes every older attempt with the same name
+        for j in jobs {
+            sqlx::query("UPDATE jobs SET retried = TRUE WHERE pipeline_id = $1 AND name = $2 AND id < $3 AND NOT retried")
+                .bind(j.pipeline_id)
+                .bind(&j.name)
+                .bind(j.id)
+                .execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn replace_test_suites(&self, pipeline_id: i64, suites: &[TestSuite]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM test_suites WHERE pipeline_id = $1")
+            .bind(pipeline_id)
+            .execute(&mut *tx).await?;
+        for t in suites {
+            sqlx::query(
+                r#"
+                INSERT INTO test_suites (pipeline_id, project_id, name, total_time, total_count, success_count, failed_count, skipped_count, error_count)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                "#,
+            ).bind(t.pipeline_id)
+            .bind(t.project_id)
+            .bind(&t.name)
+            .bind(t.total_time)
+            .bind(t.total_count)
+            .bind(t.success_count)
+            .bind(t.failed_count)
+            .bind(t.skipped_count)
+            .bind(t.error_count)
+            .execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn upsert_environments(&self, environments: &[Environment]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for e in environments {
+            sqlx::query(
+                r#"
+                INSERT INTO environments (id, project_id, name, tier, state)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT(id) DO UPDATE SET
+                    name = excluded.name,
+                    tier = COALESCE(excluded.tier, environments.tier),
+                    state = excluded.state
+                "#,
+            ).bind(e.id)
+            .bind(e.project_id)
+            .bind(&e.name)
+            .bind(&e.tier)
+            .bind(&e.state)
+            .execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn upsert_deployments(&self, deployments: &[Deployment]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for d in deployments {
+            sqlx::query(
+                r#"
+                INSERT INTO deployments (id, project_id, project_full_path, environment_id, environment_name, ref_name, sha, status, created_at, finished_at, commit_created_at, pipeline_id)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                ON CONFLICT(id) DO UPDATE SET
+                    project_full_path = excluded.project_full_path,
+                    environment_name = excluded.environment_name,
+                    status = excluded.status,
+                    finished_at = COALESCE(excluded.finished_at, deployments.finished_at),
+                    commit_created_at = COALESCE(excluded.commit_created_at, deployments.commit_created_at),
+                    pipeline_id = COALESCE(excluded.pipeline_id, deployments.pipeline_id)
+                "#,
+            ).bind(d.id)
+            .bind(d.project_id)
+            .bind(&d.project_full_path)
+            .bind(d.environment_id)
+            .bind(&d.environment_name)
+            .bind(&d.ref_name)
+            .bind(&d.sha)
+            .bind(&d.status)
+            .bind(d.created_at)
+            .bind(d.finished_at)
+            .bind(d.commit_created_at)
+            .bind(d.pipeline_id)
+            .execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn replace_runners(&self, runners: &[Runner]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM runners").execute(&mut *tx).await?;
+        for r in runners {
+            sqlx::query(
+                r#"
+                INSERT INTO runners (id, description, runner_type, status, paused, executor, tags, contacted_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                "#,
+            ).bind(r.id)
+            .bind(&r.description)
+            .bind(&r.runner_type)
+            .bind(&r.status)
+            .bind(r.paused)
+            .bind(&r.executor)
+            .bind(&r.tags)
+            .bind(r.contacted_at)
+            .execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn upsert_merge_requests(&self, merge_requests: &[MergeRequest]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for m in merge_requests {
+            sqlx::query(
+                r#"
+                INSERT INTO merge_requests (project_id, iid, title, state, source_branch, target_branch, web_url, created_at, merged_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                ON CONFLICT(project_id, iid) DO UPDATE SET
+                    title = excluded.title,
+                    state = CASE
+                        WHEN excluded.merged_at IS NULL AND merge_requests.merged_at IS NOT NULL THEN merge_requests.state
+                        ELSE COALESCE(excluded.state, merge_requests.state)
+                    END,
+                    source_branch = COALESCE(excluded.source_branch, merge_requests.source_branch),
+                    target_branch = COALESCE(excluded.target_branch, merge_requests.target_branch),
+                    web_url = COALESCE(excluded.web_url, merge_requests.web_url),
+                    created_at = COALESCE(excluded.created_at, merge_requests.created_at),
+                    merged_at = COALESCE(excluded.merged_at, merge_requests.merged_at)
+                "#,
+            ).bind(m.project_id)
+            .bind(m.iid)
+            .bind(&m.title)
+            .bind(&m.state)
+            .bind(&m.source_branch)
+            .bind(&m.target_branch)
+            .bind(&m.web_url)
+            .bind(m.created_at)
+            .bind(m.merged_at)
+            .execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn upsert_projects(&self, projects: &[Project]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for p in projects {
+            sqlx::query(
+                r#"
+                INSERT INTO projects (id, name, full_path, default_branch, visibility, archived, topics, state, previous_full_path, first_seen_at, last_seen_at, state_changed_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                ON CONFLICT(id) DO UPDATE SET
+                    name = excluded.name,
+                    full_path = excluded.full_path,
+                    default_branch = excluded.default_branch,
+                    visibility = excluded.visibility,
+                    archived = excluded.archived,
+                    topics = excluded.topics,
+                    state = excluded.state,
+                    previous_full_path = excluded.previous_full_path,
+                    last_seen_at = excluded.last_seen_at,
+                    state_changed_at = excluded.state_changed_at
+                "#,
+            ).bind(p.id)
+            .bind(&p.name)
+            .bind(&p.full_path)
+            .bind(&p.default_branch)
+            .bind(&p.visibility)
+            .bind(p.archived)
+            .bind(&p.topics)
+            .bind(&p.state)
+            .bind(&p.previous_full_path)
+            .bind(p.first_seen_at)
+            .bind(p.last_seen_at)
+            .bind(p.state_changed_at)
+            .execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn list_projects(&self, id_range: (i64, i64)) -> Result<Vec<Project>> {
+        Ok(sqlx::query_as::<_, Project>("SELECT * FROM projects WHERE id >= $1 AND id < $2 ORDER BY id")
+            .bind(id_range.0)
+            .bind(id_range.1)
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn rebuild_rollups(&self, since: i64, previous_tz: Option<Tz>) -> Result<()> {
+        // Aggregate pipelines into daily_stats and hourly_stats by their local date and hour
+        let (since, switch, since_tz) = match previous_tz {
+            Some(previous) => (since, next_local_midnight(since, self.tz), previous),
+            None => {
+                let since = local_midnight(since, self.tz);
+                (since, since, self.tz)
+            }
+        };
+        let mut ranges = Vec::new();
+        for (tz, from, to) in [(since_tz, since, switch), (self.tz, switch, i64::MAX)] {
+            if from >= to {
+                continue;
+            }
+            let (first, last): (Option<i64>, Option<i64>) = sqlx::query_as("SELECT MIN(created_at), MAX(created_at) FROM pipelines WHERE created_at >= $1 AND created_at < $2")
+                .bind(from)
+                .bind(to)
+                .fetch_one(&self.pool)
+                .await?;
+            let (first, last) = (first.unwrap_or(from), last.unwrap_or(from));
+            let date = DB::created_at_bucket(Granularity::Day, "created_at", tz, first, last);
+            let hour = DB::created_at_bucket(Granularity::Hour, "created_at", tz, first, last);
+            ranges.push((date, hour, from, to));
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        // Drop the buckets being rebuilt so rows keyed in a previous timezone do not linger
+        sqlx::query("DELETE FROM daily_stats WHERE date >= $1")
+            .bind(local_date(since, since_tz))
+            .execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM hourly_stats WHERE hour >= $1")
+            .bind(local_hour(since, since_tz))
+            .execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM daily_duration_buckets WHERE date >= $1")
+            .bind(local_date(since, since_tz))
+            .execute(&mut *tx).await?;
+
+        // The two timezones' buckets can share a key at the switch; add up rather than overwrite
+        for (date, hour, from, to) in ranges {
+            let q = format!(r#"
+            INSERT INTO daily_stats (date, project_id, project_name, project_full_path, status, source, count, total_duration, count_with_duration, total_queued_duration, count_with_queued_duration)
+            SELECT {date} as date,
+                   project_id,
+                   MAX(project_name),
+                   MAX(project_full_path),
+                   status,
+                   COALESCE(source, '') as source,
+                   COUNT(*) as count,
+                   COALESCE(SUM(duration), 0) as total_duration,
+                   COUNT(duration) as count_with_duration,
+                   COALESCE(SUM(queued_duration), 0) as total_queued_duration,
+                   COUNT(queued_duration) as count_with_queued_duration
+            FROM pipelines
+            WHERE created_at >= $1 AND created_at < $2
+            GROUP BY 1, project_id, status, COALESCE(source, '')
+            ON CONFLICT(date, project_id, status, source) DO UPDATE SET
+                count = daily_stats.count + excluded.count,
+                total_duration = daily_stats.total_duration + excluded.total_duration,
+                count_with_duration = daily_stats.count_with_duration + excluded.count_with_duration,
+                total_queued_duration = daily_stats.total_queued_duration + excluded.total_queued_duration,
+                count_with_queued_duration = daily_stats.count_with_queued_duration + excluded.count_with_queued_duration,
+                project_full_path = excluded.project_full_path
+            "#);
+
+            sqlx::query(&q).bind(from).bind(to).execute(&mut *tx).await?;
+
+            let q = format!(r#"
+            INSERT INTO hourly_stats (hour, project_id, project_name, project_full_path, status, count, total_duration, count_with_duration)
+            SELECT {hour} as hour,
+                   project_id,
+                   MAX(project_name),
+                   MAX(project_full_path),
+                   status,
+                   COUNT(*) as count,
+                   COALESCE(SUM(duration), 0) as total_duration,
+                   COUNT(duration) as count_with_duration
+            FROM pipelines
+            WHERE created_at >= $1 AND created_at < $2
+            GROUP BY 1, project_id, status
+            ON CONFLICT(hour, project_id, status) DO UPDATE SET
+                count = hourly_stats.count + excluded.count,
+                total_duration = hourly_stats.total_duration + excluded.total_duration,
+                count_with_duration = hourly_stats.count_with_duration + excluded.count_with_duration,
+                project_full_path = excluded.project_full_path
+            "#);
+
+            sqlx::query(&q).bind(from).bind(to).execute(&mut *tx).await?;
+
+            let q = format!(r#"
+            INSERT INTO daily_duration_buckets (date, project_id, project_name, project_full_path, bucket, count)
+            SELECT {date} as date,
+                   project_id,
+                   MAX(project_name),
+                   MAX(project_full_path),
+                   {bucket} as bucket,
+                   COUNT(*) as count
+            FROM pipelines
+            WHERE created_at >= $1 AND created_at < $2 AND duration IS NOT NULL
+            GROUP BY 1, project_id, 5
+            ON CONFLICT(date, project_id, bucket) DO UPDATE SET
+                count = daily_duration_buckets.count + excluded.count,
+                project_full_path = excluded.project_full_path
+            "#, bucket = duration_bucket_sql("duration"));
+
+            sqlx::query(&q).bind(from).bind(to).execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn prune_pipelines(&self, before: i64, limit: i64) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM pipelines WHERE created_at < $1 ORDER BY created_at LIMIT $2")
+            .bind(before)
+            .bind(limit)
+            .fetch_all(&mut *tx)
+            .await?;
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        // Jobs follow through ON DELETE CASCADE
+        let mut qb = QueryBuilder::new("DELETE FROM pipelines WHERE id IN (");
+        push_ids(&mut qb, &ids);
+        qb.push(")");
+        let deleted = DB::execute(qb, &mut *tx).await?;
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    async fn prune_daily_stats(&self, before: &str, limit: i64) -> Result<u64> {
+        let res = sqlx::query("DELETE FROM daily_stats WHERE (date, project_id, status, source) IN (SELECT date, project_id, status, source FROM daily_stats WHERE date < $1 ORDER BY date LIMIT $2)")
+            .bind(before)
+            .bind(limit)
+            .execute(&self.pool)
+            .await?;
+        Ok(DB::rows_affected(&res))
+    }
+
+    async fn prune_hourly_stats(&self, before: &str, limit: i64) -> Result<u64> {
+        let res = sqlx::query("DELETE FROM hourly_stats WHERE (hour, project_id, status) IN (SELECT hour, project_id, status FROM hourly_stats WHERE hour < $1 ORDER BY hour LIMIT $2)")
+            .bind(before)
+            .bind(limit)
+            .execute(&self.pool)
+            .await?;
+        Ok(DB::rows_affected(&res))
+    }
+
+    async fn prune_duration_buckets(&self, before: &str, limit: i64) -> Result<u64> {
+        let res = sqlx::query("DELETE FROM daily_duration_buckets WHERE (date, project_id, bucket) IN (SELECT date, project_id, bucket FROM daily_duration_buckets WHERE date < $1 ORDER BY date LIMIT $2)")
+            .bind(before)
+            .bind(limit)
+            .execute(&self.pool)
+            .await?;
+        Ok(DB::rows_affected(&res))
+    }
+
+    async fn list_pipelines(&self, filter: &PipelineFilter, page: &PipelinePage) -> Result<Vec<Pipeline>> {
+        let mut query_builder = QueryBuilder::new("SELECT * FROM pipelines WHERE 1=1");
+        push_pipeline_filter(&mut query_builder, filter);
+        if let Some(s) = &filter.status {
+            query_builder.push(" AND status = ");
+            query_builder.push_bind(s.clone());
+        }
+        if filter.status.as_deref() != Some("running") {
+            push_ts_range(&mut query_builder, "created_at", filter.from_ts, filter.to_ts);
+        }
+        push_pipeline_page(&mut query_builder, page);
+
+        Ok(DB::fetch_all::<Pipeline>(query_builder, &self.pool).await?)
+    }
+
+    async fn project_stats(&self, filter: &PipelineFilter) -> Result<Vec<ProjectStat>> {
+        let use_fast_path = filter.ref_name.as_deref().unwrap_or("All") == "All";
+
+        let mut query_builder = if use_fast_path {
+            let mut qb = QueryBuilder::new(format!(
+                r#"
+                SELECT
+                    {path} as project_name,
+                    {path} as project_full_path,
+                    CAST(SUM(count) AS BIGINT) as count,
+                    COALESCE(CAST(SUM(total_duration) AS DOUBLE PRECISION) / NULLIF(SUM(count_with_duration), 0), 0.0) as avg_duration,
+                    COALESCE(CAST(SUM(total_queued_duration) AS DOUBLE PRECISION) / NULLIF(SUM(count_with_queued_duration), 0), 0.0) as avg_queued_duration,
+                    COALESCE((SELECT status FROM pipelines p2 WHERE p2.project_id = daily_stats.project_id ORDER BY created_at DESC LIMIT 1), '') as last_status
+                FROM daily_stats
+                WHERE 1=1
+                "#,
+                path = project_column_sql("full_path", "daily_stats.project_id", "MAX(project_full_path)"),
+            ));
+            push_daily_stats_filter(&mut qb, filter);
+            push_date_range(&mut qb, self.tz, filter.from_ts, filter.to_ts);
+            qb
+        } else {
+            let mut qb = QueryBuilder::new(format!(
+                r#"
+                SELECT
+                    {name} as project_name,
+                    {path} as project_full_path,
+                    COUNT(*) as count,
+                    CAST(AVG(duration) AS DOUBLE PRECISION) as avg_duration,
+                    CAST(COALESCE(AVG(queued_duration), 0) AS DOUBLE PRECISION) as avg_queued_duration,
+                    (SELECT status FROM pipelines p2 WHERE p2.project_id = pipelines.project_id ORDER BY created_at DESC LIMIT 1) as last_status
+                FROM pipelines
+                WHERE 1=1
+                "#,
+                name = project_column_sql("name", "pipelines.project_id", "MAX(project_name)"),
+                path = project_column_sql("full_path", "pipelines.project_id", "MAX(project_full_path)"),
+            ));
+            push_pipeline_filter(&mut qb, filter);
+            push_ts_range(&mut qb, "created_at", filter.from_ts, filter.to_ts);
+            qb
+        };
+
+        query_builder.push(" GROUP BY project_id ORDER BY avg_duration ASC");
+
+        Ok(DB::fetch_all::<ProjectStat>(query_builder, &self.pool).await?)
+    }
+
+    async fn summary_stats(&self, filter: &PipelineFilter) -> Result<SummaryStat> {
+        let use_fast_path = filter.ref_name.as_deref().unwrap_or("All") == "All";
+
+        let query_builder = if use_fast_path {
+            let mut qb = QueryBuilder::new(
+                r#"
+                SELECT
+                    CAST(SUM(count) AS BIGINT) as total_count,
+                    COALESCE(CAST(SUM(total_duration) AS DOUBLE PRECISION) / NULLIF(SUM(count_with_duration), 0), 0.0) as avg_duration,
+                    COALESCE(CAST(SUM(total_queued_duration) AS DOUBLE PRECISION) / NULLIF(SUM(count_with_queued_duration), 0), 0.0) as avg_queued_duration,
+                    CAST(COALESCE(SUM(CASE WHEN status = 'success' THEN count ELSE 0 END) * 100.0 / NULLIF(SUM(count), 0), 0) AS DOUBLE PRECISION) as success_rate
+                FROM daily_stats
+                WHERE 1=1
+                "#
+            );
+            push_daily_stats_filter(&mut qb, filter);
+            push_date_range(&mut qb, self.tz, filter.from_ts, filter.to_ts);
+            qb
+        } else {
+            let mut qb = QueryBuilder::new(
+                r#"
+                SELECT
+                    COUNT(*) as total_count,
+                    CAST(COALESCE(AVG(duration), 0) AS DOUBLE PRECISION) as avg_duration,
+                    CAST(COALESCE(AVG(queued_duration), 0) AS DOUBLE PRECISION) as avg_queued_duration,
+                    CAST(COALESCE(SUM(CASE WHEN status = 'success' THEN 1 ELSE 0 END) * 100.0 / NULLIF(COUNT(*), 0), 0) AS DOUBLE PRECISION) as success_rate
+                FROM pipelines
+                WHERE 1=1
+                "#
+            );
+            push_pipeline_filter(&mut qb, filter);
+            push_ts_range(&mut qb, "created_at", filter.from_ts, filter.to_ts);
+            qb
+        };
+
+        Ok(DB::fetch_one::<SummaryStat>(query_builder, &self.pool).await?)
+    }
+
+    async fn duration_buckets(&self, filter: &PipelineFilter, group: DurationGroup) -> Result<Vec<DurationBucketCount>> {
+        // daily_duration_buckets has neither a ref nor a source
+        let use_fast_path = filter.ref_name.as_deref().unwrap_or("All") == "All"
+            && filter.source.as_deref().unwrap_or("All") == "All";
+
+        // The project key aggregates the stored paths of each project
+        let group_by = match group {
+            DurationGroup::Project => " GROUP BY project_id, 2",
+            _ => " GROUP BY 1, 2",
+        };
+        let query_builder = if use_fast_path {
+            let key = match group {
+                DurationGroup::All => "''".to_string(),
+                DurationGroup::Project => project_column_sql("full_path", "daily_duration_buckets.project_id", "MAX(project_full_path)"),
+                DurationGroup::Day => "date".to_string(),
+            };
+            let mut qb = QueryBuilder::new(format!("SELECT {} as key, bucket, CAST(SUM(count) AS BIGINT) as count FROM daily_duration_buckets WHERE 1=1", key));
+            push_daily_stats_filter(&mut qb, filter);
+            push_date_range(&mut qb, self.tz, filter.from_ts, filter.to_ts);
+            qb.push(group_by);
+            qb
+        } else {
+            let key = match group {
+                DurationGroup::All => "''".to_string(),
+                DurationGroup::Project => project_column_sql("full_path", "pipelines.project_id", "MAX(project_full_path)"),
+                DurationGroup::Day => {
+                    let to = filter.to_ts.unwrap_or_else(|| chrono::Utc::now().timestamp());
+                    DB::created_at_bucket(Granularity::Day, "created_at", self.tz, filter.from_ts.unwrap_or(0), to)
+                }
+            };
+            let mut qb = QueryBuilder::new(format!(
+                "SELECT {} as key, {} as bucket, COUNT(*) as count FROM pipelines WHERE duration IS NOT NULL",
+                key,
+                duration_bucket_sql("duration")
+            ));
+            push_pipeline_filter(&mut qb, filter);
+            push_ts_range(&mut qb, "created_at", filter.from_ts, filter.to_ts);
+            qb.push(group_by);
+            qb
+        };
+
+        Ok(DB::fetch_all::<DurationBucketCount>(query_builder, &self.pool).await?)
+    }
+
+    async fn stats_trend(&self, filter: &PipelineFilter, start_ts: i64, end_ts: i64, granularity: Granularity) -> Result<Vec<DailyStat>> {
+        let use_fast_path = filter.ref_name.as_deref().unwrap_or("All") == "All";
+        // hourly_stats has no source, so a source filter reads hours from pipelines
+        let by_source = filter.source.as_deref().unwrap_or("All") != "All";
+
+        let mut query_builder = if use_fast_path && granularity == Granularity::Hour && !by_source {
+            let mut qb = QueryBuilder::new(
+                r#"
+                SELECT
+                    hour as date,
+                    status,
+                    CAST(SUM(count) AS BIGINT) as count
+                FROM hourly_stats
+                WHERE 1=1
+                "#
+            );
+            push_hour_range(&mut qb, self.tz, Some(start_ts), Some(end_ts));
+            push_daily_stats_filter(&mut qb, filter);
+            qb
+        } else if use_fast_path && granularity != Granularity::Hour {
+            let mut qb = QueryBuilder::new(format!(
+                r#"
+                SELECT
+                    {} as date,
+                    status,
+                    CAST(SUM(count) AS BIGINT) as count
+                FROM daily_stats
+                WHERE 1=1
+                "#,
+                DB::date_bucket(granularity)
+            ));
+            push_date_range(&mut qb, self.tz, Some(start_ts), Some(end_ts));
+            push_daily_stats_filter(&mut qb, filter);
+            qb
+        } else {
+            let mut qb = QueryBuilder::new(format!(
+                r#"
+                SELECT
+                    {} as date,
+                    status,
+                    COUNT(*) as count
+                FROM pipelines
+                WHERE 1=1
+                "#,
+                DB::created_at_bucket(granularity, "created_at", self.tz, start_ts, end_ts)
+            ));
+            push_ts_range(&mut qb, "created_at", Some(start_ts), Some(end_ts));
+            push_pipeline_filter(&mut qb, filter);
+            qb
+        };
+        query_builder.push(" GROUP BY 1, 2 ORDER BY 1 DESC");
+
+        Ok(DB::fetch_all::<DailyStat>(query_builder, &self.pool).await?)
+    }
+
+    async fn list_pipeline_projects(&self) -> Result<Vec<(i64, String, String)>> {
+        Ok(sqlx::query_as(
+            r#"
+            SELECT project_id, MAX(project_name), MAX(project_full_path)
+            FROM pipelines
+            WHERE project_id NOT IN (SELECT id FROM projects)
+            GROUP BY project_id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn list_refs(&self) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar("SELECT DISTINCT ref_name FROM pipelines ORDER BY ref_name")
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn list_jobs(&self, filter: &JobFilter) -> Result<Vec<JobRow>> {
+        let mut query_builder = QueryBuilder::new(
+            r#"
+            SELECT j.id, j.pipeline_id, j.project_id, p.project_full_path, p.ref_name, j.name, j.stage, j.status,
+                   j.runner_id, j.runner_description, j.created_at, j.started_at, j.finished_at,
+                   j.duration, j.queued_duration, j.retried, j.allow_failure, j.web_url
+            FROM jobs j JOIN pipelines p ON p.id = j.pipeline_id
+            WHERE 1=1
+            "#
+        );
+        push_job_filters(&mut query_builder, filter);
+        query_builder.push(" ORDER BY j.created_at DESC, j.id DESC LIMIT 100");
+
+        Ok(DB::fetch_all::<JobRow>(query_builder, &self.pool).await?)
+    }
+
+    async fn job_stats(&self, filter: &JobFilter) -> Result<Vec<JobStat>> {
+        let mut query_builder = QueryBuilder::new(format!(
+            r#"
+            SELECT
+                {} as project_name,
+                j.stage,
+                j.name,
+                COUNT(*) as count,
+                SUM(CASE WHEN j.status = 'failed' THEN 1 ELSE 0 END) as failed_count,
+                SUM(CASE WHEN j.retried THEN 1 ELSE 0 END) as retried_count,
+                CAST(SUM(CASE WHEN j.status = 'failed' THEN 1 ELSE 0 END) * 100.0 / COUNT(*) AS DOUBLE PRECISION) as failure_rate,
+                COALESCE(AVG(j.duration), 0.0) as avg_duration,
+                COALESCE(MAX(j.duration), 0.0) as max_duration,
+                COALESCE(AVG(j.queued_duration), 0.0) as avg_queued_duration
+            FROM jobs j JOIN pipelines p ON p.id = j.pipeline_id
+            WHERE 1=1
+            "#,
+            project_column_sql("full_path", "p.project_id", "MAX(p.project_full_path)")
+        ));
+        push_job_filters(&mut query_builder, filter);
+        query_builder.push(" GROUP BY p.project_id, j.stage, j.name ORDER BY avg_duration DESC");
+
+        Ok(DB::fetch_all::<JobStat>(query_builder, &self.pool).await?)
+    }
+
+    async fn list_runners(&self) -> Result<Vec<Runner>> {
+        Ok(sqlx::query_as::<_, Runner>("SELECT * FROM runners ORDER BY id")
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn runner_job_stats(&self, filter: &JobFilter) -> Result<Vec<RunnerJobStat>> {
+        let mut query_builder = QueryBuilder::new(
+            r#"
+            SELECT
+                j.runner_id,
+                MAX(j.runner_description) as description,
+                COUNT(*) as job_count,
+                SUM(CASE WHEN j.status = 'failed' THEN 1 ELSE 0 END) as failed_count,
+                COALESCE(SUM(j.duration), 0.0) as busy_time,
+                COALESCE(SUM(j.queued_duration), 0.0) as total_queued_duration,
+                COUNT(j.queued_duration) as queued_count,
+                COALESCE(MAX(j.queued_duration), 0.0) as max_queued_duration
+            FROM jobs j JOIN pipelines p ON p.id = j.pipeline_id
+            WHERE j.runner_id IS NOT NULL
+            "#
+        );
+        push_job_filters(&mut query_builder, filter);
+        query_builder.push(" GROUP BY j.runner_id");
+
+        Ok(DB::fetch_all::<RunnerJobStat>(query_builder, &self.pool).await?)
+    }
+
+    async fn flaky_stats(&self, filter: &PipelineFilter) -> Result<Vec<FlakyStat>> {
+        let mut query_builder = QueryBuilder::new(format!(
+            r#"
+            SELECT
+                project_id,
+                {} as project_name,
+                ref_name,
+                COUNT(*) as sha_count,
+                CAST(SUM(CASE WHEN failed > 0 AND succeeded > 0 THEN 1 ELSE 0 END) AS BIGINT) as flaky_sha_count,
+                CAST(SUM(CASE WHEN failed > 0 AND succeeded > 0 THEN failed ELSE 0 END) AS BIGINT) as flaky_failed_count,
+                CAST(SUM(CASE WHEN failed > 0 AND succeeded > 0 THEN 1 ELSE 0 END) * 100.0 / COUNT(*) AS DOUBLE PRECISION) as flaky_rate
+            FROM "#,
+            project_column_sql("full_path", "commits.project_id", "MAX(commits.project_full_path)")
+        ));
+        push_commit_outcomes(&mut query_builder, filter);
+        query_builder.push(
+            " commits GROUP BY project_id, ref_name \
+             HAVING SUM(CASE WHEN failed > 0 AND succeeded > 0 THEN 1 ELSE 0 END) > 0 \
+             ORDER BY flaky_rate DESC, flaky_sha_count DESC, project_name, ref_name",
+        );
+
+        Ok(DB::fetch_all::<FlakyStat>(query_builder, &self.pool).await?)
+    }
+
+    async fn flaky_pipelines(&self, filter: &PipelineFilter) -> Result<Vec<Pipeline>> {
+        let mut query_builder = QueryBuilder::new("SELECT * FROM pipelines WHERE status IN ('failed', 'success')");
+        push_pipeline_filter(&mut query_builder, filter);
+        push_ts_range(&mut query_builder, "created_at", filter.from_ts, filter.to_ts);
+        query_builder.push(" AND (project_id, ref_name, sha) IN (SELECT project_id, ref_name, sha FROM ");
+        push_commit_outcomes(&mut query_builder, filter);
+        query_builder.push(" commits WHERE failed > 0 AND succeeded > 0) ORDER BY created_at DESC, id DESC");
+
+        Ok(DB::fetch_all::<Pipeline>(query_builder, &self.pool).await?)
+    }
+
+    async fn pipeline_outcomes(&self, filter: &PipelineFilter, refs: &[String]) -> Result<Vec<Pipeline>> {
+        if refs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut query_builder = QueryBuilder::new("SELECT * FROM pipelines WHERE status IN ('failed', 'success')");
+        push_pipeline_filter(&mut query_builder, filter);
+        push_ts_range(&mut query_builder, "created_at", filter.from_ts, filter.to_ts);
+        query_builder.push(" AND ref_name IN (");
+        let mut separated = query_builder.separated(", ");
+        for r in refs {
+            separated.push_bind(r.clone());
+        }
+        separated.push_unseparated(") ORDER BY created_at, id");
+
+        Ok(DB::fetch_all::<Pipeline>(query_builder, &self.pool).await?)
+    }
+
+    async fn list_deployments(&self, filter: &PipelineFilter) -> Result<Vec<Deployment>> {
+        let mut query_builder = QueryBuilder::new(
+            format!(
+                "SELECT d.id, d.project_id, {} as project_full_path, d.environment_id, d.environment_name, d.ref_name, d.sha, \
+                 d.status, d.created_at, d.finished_at, d.commit_created_at, d.pipeline_id, e.tier as environment_tier \
+                 FROM deployments d LEFT JOIN environments e ON e.id = d.environment_id WHERE 1=1",
+                project_column_sql("full_path", "d.project_id", "d.project_full_path")
+            ),
+        );
+        // Deployments have no pipeline source to filter on
+        push_instance_filter(&mut query_builder, "d.project_id", filter.instance.as_deref());
+        push_project_filter(&mut query_builder, "d.project_id", PROJECT_IDS, "d.project_full_path", filter.project_name.as_deref(), filter.exclude_projects.as_deref());
+        push_list_filter(&mut query_builder, "d.ref_name", filter.ref_name.as_deref());
+        push_ts_range(&mut query_builder, "d.created_at", filter.from_ts, filter.to_ts);
+        query_builder.push(" ORDER BY d.created_at, d.id");
+
+        Ok(DB::fetch_all::<Deployment>(query_builder, &self.pool).await?)
+    }
+
+    async fn merge_request_stats(&self, filter: &PipelineFilter) -> Result<Vec<MergeRequestStat>> {
+        let mut query_builder = QueryBuilder::new(format!(
+            r#"
+            SELECT
+                {} as project_name,
+                p.merge_request_iid as iid,
+                COALESCE(MAX(m.title), '') as title,
+                COALESCE(MAX(m.state), '') as state,
+                MAX(m.web_url) as web_url,
+                MAX(m.merged_at) as merged_at,
+                COUNT(*) as pipeline_count,
+                CAST(SUM(CASE WHEN m.merged_at IS NULL OR p.created_at <= m.merged_at THEN 1 ELSE 0 END) AS BIGINT) as runs_before_merge,
+                CAST(SUM(CASE WHEN p.status = 'failed' THEN 1 ELSE 0 END) AS BIGINT) as failed_count,
+                CAST(COALESCE(SUM(p.duration), 0) AS BIGINT) as total_duration,
+                MIN(p.created_at) as first_pipeline_at,
+                MIN(CASE WHEN p.status = 'success' THEN p.finished_at END) - MIN(p.created_at) as time_to_green
+            FROM pipelines p
+            LEFT JOIN merge_requests m ON m.project_id = p.project_id AND m.iid = p.merge_request_iid
+            WHERE p.merge_request_iid IS NOT NULL
+            "#,
+            project_column_sql("full_path", "p.project_id", "MAX(p.project_full_path)")
+        ));
+        push_pipeline_filter(&mut query_builder, filter);
+        push_ts_range(&mut query_builder, "p.created_at", filter.from_ts, filter.to_ts);
+        query_builder.push(" GROUP BY p.project_id, p.merge_request_iid ORDER BY MAX(p.created_at) DESC");
+
+        Ok(DB::fetch_all::<MergeRequestStat>(query_builder, &self.pool).await?)
+    }
+
+    async fn test_trend(&self, filter: &PipelineFilter) -> Result<Vec<TestTrendPoint>> {
+        let to = filter.to_ts.unwrap_or_else(|| chrono::Utc::now().timestamp());
+        let mut query_builder = QueryBuilder::new(format!(
+            r#"
+            SELECT
+                {} as date,
+                COUNT(DISTINCT t.pipeline_id) as pipeline_count,
+                CAST(SUM(t.total_count) AS BIGINT) as total_count,
+                CAST(SUM(t.success_count) AS BIGINT) as success_count,
+                CAST(SUM(t.failed_count) AS BIGINT) as failed_count,
+                CAST(SUM(t.skipped_count) AS BIGINT) as skipped_count,
+                CAST(SUM(t.error_count) AS BIGINT) as error_count
+            FROM test_suites t JOIN pipelines p ON p.id = t.pipeline_id
+            WHERE 1=1
+            "#,
+            DB::created_at_bucket(Granularity::Day, "p.created_at", self.tz, filter.from_ts.unwrap_or(0), to)
+        ));
+        push_pipeline_filter(&mut query_builder, filter);
+        push_ts_range(&mut query_builder, "p.created_at", filter.from_ts, filter.to_ts);
+        query_builder.push(" GROUP BY 1 ORDER BY 1 DESC");
+
+        Ok(DB::fetch_all::<TestTrendPoint>(query_builder, &self.pool).await?)
+    }
+
+    async fn test_suite_stats(&self, filter: &PipelineFilter) -> Result<Vec<TestSuiteStat>> {
+        let mut query_builder = QueryBuilder::new(format!(
+            r#"
+            SELECT
+                {} as project_name,
+                t.name,
+                COUNT(*) as run_count,
+                SUM(CASE WHEN t.failed_count + t.error_count > 0 THEN 1 ELSE 0 END) as failed_run_count,
+                CAST(SUM(CASE WHEN t.failed_count + t.error_count > 0 THEN 1 ELSE 0 END) * 100.0 / COUNT(*) AS DOUBLE PRECISION) as failure_rate,
+                CAST(SUM(t.failed_count) AS BIGINT) as failed_count,
+                CAST(SUM(t.error_count) AS BIGINT) as error_count,
+                CAST(AVG(t.total_count) AS DOUBLE PRECISION) as avg_total_count,
+                AVG(t.total_time) as avg_time,
+                MAX(t.total_time) as max_time
+            FROM test_suites t JOIN pipelines p ON p.id = t.pipeline_id
+            WHERE 1=1
+            "#,
+            project_column_sql("full_path", "p.project_id", "MAX(p.project_full_path)")
+        ));
+        push_pipeline_filter(&mut query_builder, filter);
+        push_ts_range(&mut query_builder, "p.created_at", filter.from_ts, filter.to_ts);
+        query_builder.push(" GROUP BY p.project_id, t.name ORDER BY failed_run_count DESC, failed_count DESC, project_name, t.name");
+
+        Ok(DB::fetch_all::<TestSuiteStat>(query_builder, &self.pool).await?)
+    }
+
+    async fn coverage_trend(&self, filter: &PipelineFilter) -> Result<Vec<CoverageTrendPoint>> {
+        let to = filter.to_ts.unwrap_or_else(|| chrono::Utc::now().timestamp());
+        let mut query_builder = QueryBuilder::new(format!(
+            r#"
+            SELECT
+                {} as date,
+                {} as project_name,
+                ref_name,
+                COUNT(*) as pipeline_count,
+                AVG(coverage) as avg_coverage,
+                MIN(coverage) as min_coverage,
+                MAX(coverage) as max_coverage
+            FROM pipelines
+            WHERE status = 'success' AND coverage IS NOT NULL
+            "#,
+            DB::created_at_bucket(Granularity::Day, "created_at", self.tz, filter.from_ts.unwrap_or(0), to),
+            project_column_sql("full_path", "pipelines.project_id", "MAX(project_full_path)")
+        ));
+        push_pipeline_filter(&mut query_builder, filter);
+        push_ts_range(&mut query_builder, "created_at", filter.from_ts, filter.to_ts);
+        query_builder.push(" GROUP BY 1, project_id, 3 ORDER BY 2, 3, 1 DESC");
+
+        Ok(DB::fetch_all::<CoverageTrendPoint>(query_builder, &self.pool).await?)
+    }
+
+    async fn metric_pipeline_counts(&self, columns: &[&str]) -> Result<Vec<LabeledCount>> {
+        let mut qb = QueryBuilder::new(format!(
+            "SELECT {}CAST(COALESCE(SUM(count), 0) AS BIGINT) FROM {}",
+            select_columns(columns),
+            metric_totals_sql()
+        ));
+        push_group_by(&mut qb, columns);
+
+        let rows = DB::fetch_rows(qb, &self.pool).await?;
+        rows.iter().map(|row| Ok(LabeledCount {
+            labels: label_values(row, columns.len())?,
+            count: row.try_get(columns.len())?,
+        })).collect()
+    }
+
+    async fn metric_duration_histograms(&self, columns: &[&str], buckets: &[f64]) -> Result<Vec<LabeledHistogram>> {
+        let mut qb = QueryBuilder::new(format!(
+            "SELECT {}CAST(COALESCE(SUM(duration_count), 0) AS BIGINT), CAST(COALESCE(SUM(duration_sum), 0) AS BIGINT)",
+            select_columns(columns)
+        ));
+        for b in buckets {
+            qb.push(", CAST(COALESCE(SUM(CASE WHEN bucket <= ");
+            qb.push_bind(*b);
+            qb.push(" THEN duration_count ELSE 0 END), 0) AS BIGINT)");
+        }
+        qb.push(format!(" FROM {} WHERE duration_count > 0", metric_totals_sql()));
+        push_group_by(&mut qb, columns);
+
+        let rows = DB::fetch_rows(qb, &self.pool).await?;
+        let n = columns.len();
+        rows.iter().map(|row| Ok(LabeledHistogram {
+            labels: label_values(row, n)?,
+            count: row.try_get(n)?,
+            sum: row.try_get(n + 1)?,
+            buckets: (0..buckets.len()).map(|i| row.try_get(n + 2 + i)).collect::<Result<_, _>>()?,
+        })).collect()
+    }
+
+    async fn metric_last_pipelines(&self, columns: &[&str]) -> Result<Vec<LabeledLastStatus>> {
+        // The newest pipeline of each group ranks first
+        let cols = select_columns(columns);
+        let partition = if columns.is_empty() { String::new() } else { format!("PARTITION BY {} ", columns.join(", ")) };
+        let sql = format!(
+            "SELECT {cols}status, created_at FROM (SELECT {cols}status, created_at, \
+             ROW_NUMBER() OVER ({partition}ORDER BY created_at DESC) AS rn \
+             FROM (SELECT {instance} AS instance, pipelines.* FROM pipelines) p) t WHERE rn = 1",
+            instance = instance_name_sql(&format!("pipelines.id / {}", 1i64 << NAMESPACE_SHIFT))
+        );
+
+        let rows = sqlx::query(&sql).fetch_all(&self.pool).await?;
+        let n = columns.len();
+        rows.iter().map(|row| Ok(LabeledLastStatus {
+            labels: label_values(row, n)?,
+            status: row.try_get(n)?,
+            created_at: row.try_get(n + 1)?,
+        })).collect()
+    }
+}
+
+/// `pipeline_totals` with the `instance` name of each row, for the metrics to group by.
+fn metric_totals_sql() -> String {
+    format!("(SELECT {} AS instance, pipeline_totals.* FROM pipeline_totals) t", instance_name_sql("pipeline_totals.namespace"))
+}
+
+/// Columns and placeholders of a `pipelines` row, bound by `bind_pipeline`.
+const PIPELINE_VALUES: &str = "(id, project_id, project_name, project_full_path, ref_name, user_name, sha, status, created_at, started_at, finished_at, web_url, duration, queued_duration, source, merge_request_iid, coverage) \
+     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)";
+
+fn bind_pipeline<'q, DB>(query: Query<'q, DB, <DB as HasArguments<'q>>::Arguments>, p: &'q Pipeline) -> Query<'q, DB, <DB as HasArguments<'q>>::Arguments>
+where
+    DB: Database,
+    i64: Encode<'q, DB> + Type<DB>,
+    String: Encode<'q, DB> + Type<DB>,
+    Option<i64>: Encode<'q, DB> + Type<DB>,
+    Option<f64>: Encode<'q, DB> + Type<DB>,
+    Option<String>: Encode<'q, DB> + Type<DB>,
+{
+    query
+        .bind(p.id)
+        .bind(p.project_id)
+        .bind(&p.project_name)
+        .bind(&p.project_full_path)
+        .bind(&p.ref_name)
+        .bind(&p.user_name)
+        .bind(&p.sha)
+        .bind(&p.status)
+        .bind(p.created_at)
+        .bind(p.started_at)
+        .bind(p.finished_at)
+        .bind(&p.web_url)
+        .bind(p.duration)
+        .bind(p.queued_duration)
+        .bind(&p.source)
+        .bind(p.merge_request_iid)
+        .bind(p.coverage)
+}
+
+fn select_columns(columns: &[&str]) -> String {
+    columns.iter().map(|c| format!("{}, ", c)).collect()
+}
+
+fn push_ids<'args, DB>(qb: &mut QueryBuilder<'args, DB>, ids: &[i64])
+where
+    DB: Database,
+    i64: Encode<'args, DB> + Type<DB>,
+{
+    let mut separated = qb.separated(", ");
+    for id in ids {
+        separated.push_bind(*id);
+    }
+}
+
+fn push_group_by<DB: Database>(qb: &mut QueryBuilder<'_, DB>, columns: &[&str]) {
+    if !columns.is_empty() {
+        qb.push(" GROUP BY ");
+        qb.push(columns.join(", "));
+    }
+}
+
+fn label_values<R>(row: &R, n: usize) -> Result<Vec<String>>
+where
+    R: Row,
+    for<'r> String: Decode<'r, R::Database> + Type<R::Database>,
+    usize: ColumnIndex<R>,
+{
+    let mut values = Vec::with_capacity(n);
+    for i in 0..n {
+        let v: Option<String> = row.try_get(i)?;
+        values.push(v.unwrap_or_default());
+    }
+    Ok(values)
+}
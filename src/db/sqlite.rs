@@ -0,0 +1,64 @@
+use super::sql::{Dialect, SqlStorage};
+use super::{local_epoch_sql, migrations};
+use crate::models::Granularity;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono_tz::Tz;
+use sqlx::{FromRow, Pool, QueryBuilder, Sqlite};
+
+pub type SqliteStorage = SqlStorage<Sqlite>;
+
+#[async_trait]
+impl Dialect for Sqlite {
+    // A write transaction holds the lock on the whole database
+    const FOR_UPDATE: &'static str = "";
+
+    async fn migrate(pool: &Pool<Self>) -> Result<()> {
+        migrations::run_sqlite(pool).await
+    }
+
+    fn date_bucket(granularity: Granularity) -> &'static str {
+        match granularity {
+            Granularity::Week => "date(date, 'weekday 0', '-6 days')",
+            Granularity::Month => "substr(date, 1, 7)",
+            Granularity::Hour | Granularity::Day => "date",
+        }
+    }
+
+    fn created_at_bucket(granularity: Granularity, column: &str, tz: Tz, from: i64, to: i64) -> String {
+        // SQLite has no timezone database, so the UTC offsets over the range are inlined
+        let local = local_epoch_sql(column, tz, from, to);
+        match granularity {
+            Granularity::Hour => format!("strftime('%Y-%m-%d %H:00', {}, 'unixepoch')", local),
+            Granularity::Day => format!("date({}, 'unixepoch')", local),
+            Granularity::Week => format!("date({}, 'unixepoch', 'weekday 0', '-6 days')", local),
+            Granularity::Month => format!("strftime('%Y-%m', {}, 'unixepoch')", local),
+        }
+    }
+
+    async fn fetch_rows(mut qb: QueryBuilder<'_, Self>, pool: &Pool<Self>) -> sqlx::Result<Vec<Self::Row>> {
+        qb.build().fetch_all(pool).await
+    }
+
+    async fn fetch_all<T>(mut qb: QueryBuilder<'_, Self>, pool: &Pool<Self>) -> sqlx::Result<Vec<T>>
+    where
+        T: for<'r> FromRow<'r, Self::Row> + Send + Unpin,
+    {
+        qb.build_query_as::<T>().fetch_all(pool).await
+    }
+
+    async fn fetch_one<T>(mut qb: QueryBuilder<'_, Self>, pool: &Pool<Self>) -> sqlx::Result<T>
+    where
+        T: for<'r> FromRow<'r, Self::Row> + Send + Unpin,
+    {
+        qb.build_query_as::<T>().fetch_one(pool).await
+    }
+
+    fn rows_affected(result: &Self::QueryResult) -> u64 {
+        result.rows_affected()
+    }
+
+    async fn execute(mut qb: QueryBuilder<'_, Self>, conn: &mut Self::Connection) -> sqlx::Result<u64> {
+        Ok(qb.build().execute(conn).await?.rows_affected())
+    }
+}
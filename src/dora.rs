@@ -0,0 +1,235 @@
+use crate::db;
+use crate::gitlab_ops;
+use crate::instance::Instance;
+use crate::monitor;
+use crate::models::{Deployment, DoraReport, DoraStat};
+use crate::state::AppState;
+use chrono::{TimeZone, Utc};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration as StdDuration;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+/// Periodically store the environments and deployments of every monitored project.
+pub async fn start_deployment_loop(state: AppState) {
+    let Some(dora) = &state.config.dora else { return };
+    let interval = dora.interval_seconds.unwrap_or(3600);
+
+    loop {
+        for instance in state.instances.iter() {
+            match monitor::discover_instance_projects(instance).await {
+                Ok(projects) => {
+                    info!("Polling deployments of {} projects of instance {}", projects.len(), instance.name);
+                    for project in &projects {
+                        sync_deployments(&state, instance, project.id, &project.path_with_namespace).await;
+                        // small sleep to avoid hammering the API
+                        sleep(StdDuration::from_millis(200)).await;
+                    }
+                }
+                Err(e) => error!("Failed to discover projects of instance {} for deployment polling: {}", instance.name, e),
+            }
+        }
+        sleep(StdDuration::from_secs(interval)).await;
+    }
+}
+
+/// Fetch the environments and the deployments updated since the project's watermark,
+/// which only advances once both were stored.
+async fn sync_deployments(state: &AppState, instance: &Instance, project_id: u64, full_path: &str) {
+    let key = instance.id(project_id).to_string();
+    let poll_time = Utc::now();
+    let last_poll_ts = match state.db.get_watermark(db::SCOPE_DEPLOYMENTS, &key).await {
+        Ok(opt) => opt.unwrap_or(poll_time.timestamp() - state.config.poller.backfill_days * 86400),
+        Err(e) => {
+            error!("Failed to read deployment watermark for project {}: {}", full_path, e);
+            return;
+        }
+    };
+    let since = Utc.timestamp_opt(last_poll_ts, 0).single().unwrap_or(poll_time);
+
+    let environments = match gitlab_ops::fetch_environments(&instance.gitlab_client, project_id).await {
+        Ok(envs) => envs.iter().map(|e| instance.scope_environment(e.to_db_environment(project_id as i64))).collect::<Vec<_>>(),
+        Err(e) => {
+            error!("Failed to fetch environments for project {}: {}", full_path, e);
+            return;
+        }
+    };
+    if let Err(e) = state.db.upsert_environments(&environments).await {
+        error!("Failed to store environments for project {}: {}", full_path, e);
+        return;
+    }
+
+    let deployments = match gitlab_ops::fetch_deployments(&instance.gitlab_client, project_id, since).await {
+        Ok(deps) => deps.iter().map(|d| instance.scope_deployment(d.to_db_deployment(project_id as i64, full_path))).collect::<Vec<_>>(),
+        Err(e) => {
+            error!("Failed to fetch deployments for project {}: {}", full_path, e);
+            return;
+        }
+    };
+    if let Err(e) = state.db.upsert_deployments(&deployments).await {
+        error!("Failed to store deployments for project {}: {}", full_path, e);
+        return;
+    }
+    if let Err(e) = state.db.set_watermark(db::SCOPE_DEPLOYMENTS, &key, poll_time.timestamp()).await {
+        error!("Failed to update deployment watermark for project {}: {}", full_path, e);
+    }
+}
+
+/// Whether a deployment went to production: its environment name matches `environments`,
+/// or without a pattern, its environment is in the `production` tier.
+fn is_production(d: &Deployment, environments: Option<&Regex>) -> bool {
+    match environments {
+        Some(re) => re.is_match(&d.environment_name),
+        None => d.environment_tier.as_deref() == Some("production"),
+    }
+}
+
+/// DORA metrics of the production deployments in `deployments` (oldest first), per project
+/// and per monitored group, over the window from `from_ts` to `to_ts`.
+pub fn report(deployments: Vec<Deployment>, groups: &[String], environments: Option<&Regex>, from_ts: i64, to_ts: i64) -> DoraReport {
+    let days = ((to_ts - from_ts) as f64 / 86400.0).max(1.0 / 24.0);
+    let production: Vec<Deployment> = deployments.into_iter().filter(|d| is_production(d, environments)).collect();
+
+    // Keyed by project id so a renamed project stays one row, named by its latest path
+    let mut by_project: BTreeMap<i64, Vec<&Deployment>> = BTreeMap::new();
+    for d in &production {
+        by_project.entry(d.project_id).or_default().push(d);
+    }
+    let mut projects: Vec<DoraStat> = by_project.values()
+        .map(|deps| dora_stat(&deps[deps.len() - 1].project_full_path, deps, days))
+        .collect();
+    projects.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let groups = groups.iter().filter_map(|group| {
+        let prefix = format!("{}/", group);
+        let deps: Vec<&Deployment> = production.iter()
+            .filter(|d| d.project_full_path.starts_with(&prefix))
+            .collect();
+        (!deps.is_empty()).then(|| dora_stat(group, &deps, days))
+    }).collect();
+
+    DoraReport { from_ts, to_ts, groups, projects }
+}
+
+fn dora_stat(name: &str, deployments: &[&Deployment], days: f64) -> DoraStat {
+    let succeeded: Vec<&&Deployment> = deployments.iter().filter(|d| d.status == "success").collect();
+    let failed = deployments.iter().filter(|d| d.status == "failed").count() as i64;
+    let deployment_count = succeeded.len() as i64;
+
+    let lead_times: Vec<f64> = succeeded.iter()
+        .filter_map(|d| Some((d.finished_at? - d.commit_created_at?) as f64))
+        .filter(|t| *t >= 0.0)
+        .collect();
+
+    // An environment is down from its first failed deployment until the next successful one
+    let mut down_since: HashMap<i64, i64> = HashMap::new();
+    let mut restore_times = Vec::new();
+    for d in deployments {
+        let at = d.finished_at.unwrap_or(d.created_at);
+        match d.status.as_str() {
+            "failed" => {
+                down_since.entry(d.environment_id).or_insert(at);
+            }
+            "success" => {
+                if let Some(since) = down_since.remove(&d.environment_id) {
+                    restore_times.push((at - since).max(0) as f64);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let restore_count = restore_times.len() as i64;
+    DoraStat {
+        name: name.to_string(),
+        deployment_count,
+        deployment_frequency: deployment_count as f64 / days,
+        median_lead_time: median(lead_times),
+        failed_deployment_count: failed,
+        change_failure_rate: if deployment_count + failed > 0 { failed as f64 * 100.0 / (deployment_count + failed) as f64 } else { 0.0 },
+        median_time_to_restore: median(restore_times),
+        restore_count,
+    }
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deployment(project_id: i64, path: &str, environment_id: i64, environment: &str, status: &str, finished_at: i64, commit_created_at: i64) -> Deployment {
+        Deployment {
+            id: finished_at,
+            project_id,
+            project_full_path: path.to_string(),
+            environment_id,
+            environment_name: environment.to_string(),
+            ref_name: "main".to_string(),
+            sha: "abc".to_string(),
+            status: status.to_string(),
+            created_at: finished_at - 50,
+            finished_at: Some(finished_at),
+            commit_created_at: Some(commit_created_at),
+            pipeline_id: None,
+            environment_tier: Some(if environment == "staging" { "staging" } else { "production" }.to_string()),
+        }
+    }
+
+    fn deployments() -> Vec<Deployment> {
+        vec![
+            deployment(7, "grp/app", 1, "production", "success", 200, 100),
+            deployment(8, "other/svc", 3, "production", "success", 300, 0),
+            deployment(7, "grp/app", 1, "production", "failed", 1_000, 900),
+            deployment(7, "grp/app", 2, "staging", "success", 1_100, 900),
+            // Renamed since the earlier deployments
+            deployment(7, "grp/app-renamed", 1, "production", "success", 1_600, 1_000),
+        ]
+    }
+
+    #[test]
+    fn report_counts_production_deployments_per_project_and_group() {
+        let groups = vec!["grp".to_string(), "empty".to_string()];
+        let report = report(deployments(), &groups, None, 0, 2 * 86400);
+
+        let names: Vec<&str> = report.projects.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["grp/app-renamed", "other/svc"]);
+
+        let app = &report.projects[0];
+        assert_eq!((app.deployment_count, app.failed_deployment_count, app.restore_count), (2, 1, 1));
+        assert_eq!(app.deployment_frequency, 1.0);
+        assert_eq!(app.median_lead_time, Some(350.0));
+        assert!((app.change_failure_rate - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(app.median_time_to_restore, Some(600.0));
+
+        let svc = &report.projects[1];
+        assert_eq!((svc.deployment_count, svc.failed_deployment_count), (1, 0));
+        assert_eq!((svc.median_lead_time, svc.median_time_to_restore), (Some(300.0), None));
+
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!((report.groups[0].name.as_str(), report.groups[0].deployment_count), ("grp", 2));
+    }
+
+    #[test]
+    fn report_selects_environments_by_pattern() {
+        let staging = Regex::new("^staging$").unwrap();
+        let report = report(deployments(), &[], Some(&staging), 0, 86400);
+        assert_eq!(report.projects.len(), 1);
+        assert_eq!((report.projects[0].name.as_str(), report.projects[0].deployment_count), ("grp/app", 1));
+    }
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        assert_eq!(median(Vec::new()), None);
+        assert_eq!(median(vec![3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(vec![4.0, 1.0, 3.0, 2.0]), Some(2.5));
+    }
+}
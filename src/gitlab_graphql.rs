@@ -3,7 +3,11 @@ use chrono::{DateTime, Duration, Utc};
 use reqwest::Client;
 use serde::{Deserialize, de::DeserializeOwned, Serialize};
 use serde_json::json;
-use crate::gitlab_types::{ProjectPipelineInfo, ProjectConnection};
+use tracing::info;
+use crate::gitlab_types::{
+    MergeRequestInfo, PageInfo, PipelineConnection, PipelineInfo, ProjectConnection, ProjectNode, ProjectPipelineInfo,
+    RunnerInfo,
+};
 
 #[derive(Clone)]
 pub struct GitlabGraphqlClient {
@@ -38,6 +42,163 @@ struct GroupNode {
     projects: Option<ProjectConnection>,
 }
 
+#[derive(Deserialize)]
+struct ProjectQueryResponse {
+    data: Option<ProjectData>,
+}
+
+#[derive(Deserialize)]
+struct ProjectData {
+    project: Option<ProjectPipelinesNode>,
+}
+
+#[derive(Deserialize)]
+struct ProjectPipelinesNode {
+    pipelines: Option<PipelineConnection>,
+}
+
+#[derive(Deserialize)]
+struct ProjectActivityResponse {
+    data: Option<ProjectActivityData>,
+}
+
+#[derive(Deserialize)]
+struct ProjectActivityData {
+    project: Option<ProjectNode>,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestQueryResponse {
+    data: Option<MergeRequestData>,
+}
+
+/// Only the field of the polled kind, `group` or `project`, is queried.
+#[derive(Deserialize)]
+struct MergeRequestData {
+    group: Option<MergeRequestParentNode>,
+    project: Option<MergeRequestParentNode>,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestParentNode {
+    #[serde(rename = "mergeRequests")]
+    merge_requests: Option<MergeRequestConnection>,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestConnection {
+    #[serde(rename = "pageInfo")]
+    page_info: Option<PageInfo>,
+    nodes: Option<Vec<MergeRequestInfo>>,
+}
+
+#[derive(Deserialize)]
+struct RunnerQueryResponse {
+    data: Option<RunnerGroupData>,
+}
+
+#[derive(Deserialize)]
+struct RunnerGroupData {
+    group: Option<RunnerGroupNode>,
+}
+
+#[derive(Deserialize)]
+struct RunnerGroupNode {
+    runners: Option<RunnerConnection>,
+}
+
+#[derive(Deserialize)]
+struct RunnerConnection {
+    #[serde(rename = "pageInfo")]
+    page_info: Option<PageInfo>,
+    nodes: Option<Vec<RunnerInfo>>,
+}
+
+const PIPELINE_FIELDS_FRAGMENT: &str = r#"
+fragment PipelineFields on Pipeline {
+    id
+    sha
+    status
+    createdAt
+    startedAt
+    finishedAt
+    duration
+    queuedDuration
+    ref
+    source
+    coverage
+    user {
+        name
+    }
+    mergeRequest {
+        ...MergeRequestFields
+    }
+}
+"#;
+
+const MERGE_REQUEST_FIELDS_FRAGMENT: &str = r#"
+fragment MergeRequestFields on MergeRequest {
+    iid
+    projectId
+    title
+    state
+    webUrl
+    createdAt
+    mergedAt
+    sourceBranch
+    targetBranch
+    project {
+        fullPath
+    }
+}
+"#;
+
+/// A project with its pipelines updated after `$updatedAfter`, shared by the group and project polls.
+const PROJECT_ACTIVITY_FRAGMENT: &str = r#"
+fragment ProjectActivityFields on Project {
+    id
+    fullPath
+    name
+    webUrl
+    pipelines(updatedAfter: $updatedAfter, first: $pipelinesFirst) {
+        pageInfo {
+            endCursor
+            hasNextPage
+        }
+        nodes {
+            ...PipelineFields
+        }
+    }
+}
+"#;
+
+/// Page size of the per-project pipeline connection in the group query.
+const PIPELINES_PER_PROJECT: usize = 30;
+
+/// What one incremental poll covers: a group, with or without its subgroups, or a single project.
+#[derive(Clone, Copy, Debug)]
+pub enum PollTarget<'a> {
+    Group { full_path: &'a str, include_subgroups: bool },
+    Project(&'a str),
+}
+
+impl PollTarget<'_> {
+    pub fn full_path(&self) -> &str {
+        match self {
+            PollTarget::Group { full_path, .. } => full_path,
+            PollTarget::Project(full_path) => full_path,
+        }
+    }
+
+    /// "group" or "project", for logs and the watermark scope.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PollTarget::Group { .. } => crate::db::SCOPE_GROUP,
+            PollTarget::Project(_) => crate::db::SCOPE_PROJECT,
+        }
+    }
+}
+
 impl GitlabGraphqlClient {
     pub fn new(base_url: String, token: String, timeout: u64, skip_invalid_certs: bool) -> Self {
         let client = Client::builder()
@@ -109,48 +270,47 @@ impl GitlabGraphqlClient {
                         Ok(user_name)
                     }
 
+    /// Projects of `target` with pipelines updated since `since_time`, and those pipelines.
     pub async fn fetch_incremental_activity(
+        &self,
+        target: PollTarget<'_>,
+        since_time: DateTime<Utc>
+    ) -> Result<Vec<ProjectPipelineInfo>> {
+        match target {
+            PollTarget::Group { full_path, include_subgroups } => self.fetch_group_activity(full_path, include_subgroups, since_time).await,
+            PollTarget::Project(full_path) => self.fetch_project_activity(full_path, since_time).await,
+        }
+    }
+
+    async fn fetch_group_activity(
         &self, 
         group_full_path: &str, 
+        include_subgroups: bool,
         since_time: DateTime<Utc>
     ) -> Result<Vec<ProjectPipelineInfo>> {
         
         let query_time = since_time - Duration::seconds(60);
 
                 let query = r#"
-                query($fullPath: ID!, $cursor: String, $updatedAfter: Time!) {
+                query($fullPath: ID!, $cursor: String, $updatedAfter: Time!, $pipelinesFirst: Int!, $includeSubgroups: Boolean!) {
                     group(fullPath: $fullPath) {
-                        projects(includeSubgroups: true, first: 50, after: $cursor) {
+                        projects(includeSubgroups: $includeSubgroups, first: 50, after: $cursor) {
                             pageInfo {
                                 endCursor
                                 hasNextPage
                             }
                             nodes {
-                                id
-                                fullPath
-                                name
-                                webUrl
-                                pipelines(updatedAfter: $updatedAfter, first: 30) {
-                                    nodes {
-                                        id
-                                        sha
-                                        status
-                                        createdAt
-                                        finishedAt
-                                        duration
-                                        ref
-                                        user {
-                                            name
-                                        }
-                                    }
-                                }
+                                ...ProjectActivityFields
                             }
                         }
                     }
                 }
                 "#;
+        let query = format!("{}{}{}{}", query, PROJECT_ACTIVITY_FRAGMENT, PIPELINE_FIELDS_FRAGMENT, MERGE_REQUEST_FIELDS_FRAGMENT);
+        let updated_after = query_time.to_rfc3339();
 
         let mut active_projects = Vec::new();
+        let mut overflowed_projects = 0usize;
         let mut cursor: Option<String> = None;
         let mut has_next_page = true;
 
@@ -158,10 +318,12 @@ impl GitlabGraphqlClient {
             let variables = json!({
                 "fullPath": group_full_path,
                 "cursor": cursor,
-                "updatedAfter": query_time.to_rfc3339()
+                "updatedAfter": updated_after,
+                "pipelinesFirst": PIPELINES_PER_PROJECT,
+                "includeSubgroups": include_subgroups
             });
 
-            let response: GroupQueryResponse = self.post_graphql(query, variables).await?;
+            let response: GroupQueryResponse = self.post_graphql(&query, variables).await?;
 
             if let Some(group) = response.data.and_then(|d| d.group) {
                 if let Some(projects) = group.projects {
@@ -174,24 +336,11 @@ impl GitlabGraphqlClient {
 
                     if let Some(nodes) = projects.nodes {
                         for p in nodes {
-                            if let Some(pipe_conn) = p.pipelines {
-                                if let Some(mut pipe_nodes) = pipe_conn.nodes {
-                                    if !pipe_nodes.is_empty() {
-                                        for pipe in &mut pipe_nodes {
-                                            let base = p.web_url.as_deref().unwrap_or("");
-                                            pipe.web_url = Some(format!("{}/-/pipelines/{}", base, pipe.id));
-                                        }
-
-                                        active_projects.push(ProjectPipelineInfo {
-                                            id: p.id,
-                                            name: p.name,
-                                            full_path: p.full_path,
-                                            web_url: p.web_url,
-                                            pipelines: pipe_nodes,
-                                        });
-                                    }
-                                }
+                            let (active, overflowed) = self.project_activity(p, &updated_after, query_time).await?;
+                            if overflowed {
+                                overflowed_projects += 1;
                             }
+                            active_projects.extend(active);
                         }
                     }
                 } else {
@@ -202,9 +351,229 @@ impl GitlabGraphqlClient {
             }
         }
 
+        if overflowed_projects > 0 {
+            info!("Group {}: {} project(s) exceeded one page of pipelines", group_full_path, overflowed_projects);
+        }
+
         Ok(active_projects)
     }
 
+    async fn fetch_project_activity(&self, project_full_path: &str, since_time: DateTime<Utc>) -> Result<Vec<ProjectPipelineInfo>> {
+        let query_time = since_time - Duration::seconds(60);
+        let query = r#"
+        query($fullPath: ID!, $updatedAfter: Time!, $pipelinesFirst: Int!) {
+            project(fullPath: $fullPath) {
+                ...ProjectActivityFields
+            }
+        }
+        "#;
+        let query = format!("{}{}{}{}", query, PROJECT_ACTIVITY_FRAGMENT, PIPELINE_FIELDS_FRAGMENT, MERGE_REQUEST_FIELDS_FRAGMENT);
+        let updated_after = query_time.to_rfc3339();
+        let variables = json!({
+            "fullPath": project_full_path,
+            "updatedAfter": updated_after,
+            "pipelinesFirst": PIPELINES_PER_PROJECT
+        });
+
+        let response: ProjectActivityResponse = self.post_graphql(&query, variables).await?;
+        let Some(project) = response.data.and_then(|d| d.project) else {
+            bail!("Project not found: {}", project_full_path);
+        };
+        let (active, _) = self.project_activity(project, &updated_after, query_time).await?;
+        Ok(active.into_iter().collect())
+    }
+
+    /// The pipelines of a polled project, paging past the first page if needed; `None` for a
+    /// project without updated pipelines. Also reports whether a further page was fetched.
+    async fn project_activity(
+        &self,
+        p: ProjectNode,
+        updated_after: &str,
+        query_time: DateTime<Utc>,
+    ) -> Result<(Option<ProjectPipelineInfo>, bool)> {
+        let Some(pipe_conn) = p.pipelines else { return Ok((None, false)) };
+        let Some(mut pipe_nodes) = pipe_conn.nodes else { return Ok((None, false)) };
+
+        // A busy project can update more pipelines than fit in one page
+        let mut overflowed = false;
+        if let Some(page_info) = pipe_conn.page_info.filter(|pi| pi.has_next_page) {
+            overflowed = true;
+            let more = self.fetch_remaining_pipelines(&p.full_path, updated_after, page_info.end_cursor).await?;
+            info!(
+                "Project {} updated more than {} pipelines since {}; fetched {} more via pagination",
+                p.full_path, PIPELINES_PER_PROJECT, query_time, more.len()
+            );
+            pipe_nodes.extend(more);
+        }
+        if pipe_nodes.is_empty() {
+            return Ok((None, overflowed));
+        }
+        for pipe in &mut pipe_nodes {
+            let base = p.web_url.as_deref().unwrap_or("");
+            pipe.web_url = Some(format!("{}/-/pipelines/{}", base, pipe.id));
+        }
+
+        let active = ProjectPipelineInfo {
+            id: p.id,
+            name: p.name,
+            full_path: p.full_path,
+            web_url: p.web_url,
+            pipelines: pipe_nodes,
+        };
+        Ok((Some(active), overflowed))
+    }
+
+    /// All pipelines of a project updated after `since`, with their start and queue times.
+    pub async fn fetch_project_pipelines(&self, project_full_path: &str, since: DateTime<Utc>) -> Result<Vec<PipelineInfo>> {
+        self.fetch_remaining_pipelines(project_full_path, &since.to_rfc3339(), None).await
+    }
+
+    /// Follow a project's `pipelines` connection from `cursor` until the last page.
+    async fn fetch_remaining_pipelines(
+        &self,
+        project_full_path: &str,
+        updated_after: &str,
+        mut cursor: Option<String>,
+    ) -> Result<Vec<PipelineInfo>> {
+        let query = r#"
+        query($fullPath: ID!, $cursor: String, $updatedAfter: Time!) {
+            project(fullPath: $fullPath) {
+                pipelines(updatedAfter: $updatedAfter, first: 100, after: $cursor) {
+                    pageInfo {
+                        endCursor
+                        hasNextPage
+                    }
+                    nodes {
+                        ...PipelineFields
+                    }
+                }
+            }
+        }
+        "#;
+        let query = format!("{}{}{}", query, PIPELINE_FIELDS_FRAGMENT, MERGE_REQUEST_FIELDS_FRAGMENT);
+
+        let mut pipelines = Vec::new();
+        loop {
+            let variables = json!({
+                "fullPath": project_full_path,
+                "cursor": cursor,
+                "updatedAfter": updated_after
+            });
+
+            let response: ProjectQueryResponse = self.post_graphql(&query, variables).await?;
+            let Some(conn) = response.data.and_then(|d| d.project).and_then(|p| p.pipelines) else {
+                bail!("Project not found: {}", project_full_path);
+            };
+            pipelines.extend(conn.nodes.unwrap_or_default());
+
+            match conn.page_info {
+                Some(pi) if pi.has_next_page && pi.end_cursor.is_some() => cursor = pi.end_cursor,
+                _ => break,
+            }
+        }
+
+        Ok(pipelines)
+    }
+
+    /// Merge requests of `target` updated after `since`, so that merges without a later
+    /// pipeline still reach the stored merge requests.
+    pub async fn fetch_merge_requests(&self, target: PollTarget<'_>, since: DateTime<Utc>) -> Result<Vec<MergeRequestInfo>> {
+        let (parent, filter) = match target {
+            PollTarget::Group { include_subgroups, .. } => ("group", format!("includeSubgroups: {}, ", include_subgroups)),
+            PollTarget::Project(_) => ("project", String::new()),
+        };
+        let query = format!(r#"
+        query($fullPath: ID!, $cursor: String, $updatedAfter: Time!) {{
+            {parent}(fullPath: $fullPath) {{
+                mergeRequests({filter}updatedAfter: $updatedAfter, first: 100, after: $cursor) {{
+                    pageInfo {{
+                        endCursor
+                        hasNextPage
+                    }}
+                    nodes {{
+                        ...MergeRequestFields
+                    }}
+                }}
+            }}
+        }}
+        "#);
+        let query = format!("{}{}", query, MERGE_REQUEST_FIELDS_FRAGMENT);
+        let updated_after = (since - Duration::seconds(60)).to_rfc3339();
+
+        let mut merge_requests = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let variables = json!({
+                "fullPath": target.full_path(),
+                "cursor": cursor,
+                "updatedAfter": updated_after
+            });
+
+            let response: MergeRequestQueryResponse = self.post_graphql(&query, variables).await?;
+            let Some(parent) = response.data.and_then(|d| d.group.or(d.project)) else {
+                bail!("{} not found: {}", target.kind(), target.full_path());
+            };
+            let Some(conn) = parent.merge_requests else { break };
+            merge_requests.extend(conn.nodes.unwrap_or_default());
+
+            match conn.page_info {
+                Some(pi) if pi.has_next_page && pi.end_cursor.is_some() => cursor = pi.end_cursor,
+                _ => break,
+            }
+        }
+
+        Ok(merge_requests)
+    }
+
+    /// Runners of a group, its subgroups and their projects.
+    pub async fn fetch_runners(&self, group_full_path: &str) -> Result<Vec<RunnerInfo>> {
+        let query = r#"
+        query($fullPath: ID!, $cursor: String) {
+            group(fullPath: $fullPath) {
+                runners(membership: DESCENDANTS, first: 100, after: $cursor) {
+                    pageInfo {
+                        endCursor
+                        hasNextPage
+                    }
+                    nodes {
+                        id
+                        description
+                        runnerType
+                        status
+                        paused
+                        executorName
+                        tagList
+                        contactedAt
+                    }
+                }
+            }
+        }
+        "#;
+
+        let mut runners = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let variables = json!({
+                "fullPath": group_full_path,
+                "cursor": cursor
+            });
+
+            let response: RunnerQueryResponse = self.post_graphql(query, variables).await?;
+            let Some(group) = response.data.and_then(|d| d.group) else {
+                bail!("Group not found: {}", group_full_path);
+            };
+            let Some(conn) = group.runners else { break };
+            runners.extend(conn.nodes.unwrap_or_default());
+
+            match conn.page_info {
+                Some(pi) if pi.has_next_page && pi.end_cursor.is_some() => cursor = pi.end_cursor,
+                _ => break,
+            }
+        }
+
+        Ok(runners)
+    }
+
     async fn post_graphql<T: DeserializeOwned>(&self, query: &str, variables: serde_json::Value) -> Result<T> {
         let payload = json!({
             "query": query,
@@ -1,13 +1,23 @@
-use crate::gitlab_types::{GitlabPipeline, ProjectInfo};
+use crate::gitlab_graphql::GitlabGraphqlClient;
+use crate::gitlab_types::{
+    GitlabDeployment, GitlabEnvironment, GitlabJob, GitlabTestReportSummary, PipelineInfo, ProjectInfo,
+};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use gitlab::api::{groups, projects, AsyncQuery, Pagination, paged};
+use gitlab::api::common::NameOrId;
+use gitlab::api::{groups, projects, ApiError, AsyncQuery, Pagination, paged};
 use gitlab::AsyncGitlab;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
 
 pub async fn discover_projects(
     client: &AsyncGitlab,
     groups: &[String],
+    include_subgroups: bool,
     _min_activity_date: Option<DateTime<Utc>>,
 ) -> Result<Vec<ProjectInfo>> {
     // User request: Fetch ALL projects (except archived), then filter for pipelines later.
@@ -20,7 +30,7 @@ pub async fn discover_projects(
         // The endpoint `groups::projects::GroupProjects` takes a group ID or path.
         let mut builder = groups::projects::GroupProjects::builder();
         builder.group(group.as_str());
-        builder.include_subgroups(true);
+        builder.include_subgroups(include_subgroups);
         builder.archived(false);
 
         let endpoint = builder.build()?;
@@ -37,63 +47,59 @@ pub async fn discover_projects(
     Ok(all_projects)
 }
 
-pub async fn fetch_pipelines(
-    client: &AsyncGitlab,
-    project_id: u64,
-    updated_after: Option<DateTime<Utc>>,
-) -> Result<Vec<GitlabPipeline>> {
-    let mut builder = projects::pipelines::Pipelines::builder();
-    builder.project(project_id);
-    
-    if let Some(after) = updated_after {
-        builder.updated_after(after);
+/// A project by full path or id, or `None` once it was deleted (or is no longer visible to the token).
+pub async fn find_project<'a>(client: &AsyncGitlab, project: impl Into<NameOrId<'a>>) -> Result<Option<ProjectInfo>> {
+    let endpoint = projects::Project::builder().project(project).build()?;
+    match endpoint.query_async(client).await {
+        Ok(project) => Ok(Some(project)),
+        Err(e) if is_not_found(&e) => Ok(None),
+        Err(e) => Err(e.into()),
     }
-
-    let endpoint = builder.build()?;
-    let pipelines: Vec<GitlabPipeline> = paged(endpoint, Pagination::All)
-        .query_async(client)
-        .await?;
-    Ok(pipelines)
 }
 
-/// Fetch pipelines for multiple projects concurrently with a concurrency limit.
-pub async fn fetch_pipelines_concurrent(
-    client: &AsyncGitlab,
-    project_ids: Vec<u64>,
-    updated_after: Option<DateTime<Utc>>,
-    concurrency: usize,
-) -> Result<Vec<(u64, Vec<GitlabPipeline>)>> {
-    use tokio::sync::Semaphore;
-    use tokio::task::JoinSet;
+fn is_not_found<E: std::error::Error + Send + Sync + 'static>(e: &ApiError<E>) -> bool {
+    match e {
+        ApiError::GitlabWithStatus { status, .. }
+        | ApiError::GitlabObjectWithStatus { status, .. }
+        | ApiError::GitlabUnrecognizedWithStatus { status, .. }
+        | ApiError::GitlabService { status, .. } => status.as_u16() == 404,
+        _ => false,
+    }
+}
 
-    use std::sync::Arc;
+/// Run `fetch` for every key with at most `concurrency` requests in flight, retrying each
+/// failure up to three times with exponential backoff. Results come in completion order.
+async fn fetch_concurrent<K, T, F, Fut>(keys: Vec<K>, concurrency: usize, fetch: F) -> Vec<(K, Result<T>)>
+where
+    K: Copy + Send + 'static,
+    T: Send + 'static,
+    F: Fn(K) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T>> + Send,
+{
     let sem = Arc::new(Semaphore::new(concurrency));
-    let mut join_set: JoinSet<(u64, Result<Vec<GitlabPipeline>, anyhow::Error>)> = JoinSet::new();
+    let fetch = Arc::new(fetch);
+    let mut join_set: JoinSet<(K, Result<T>)> = JoinSet::new();
 
-    for pid in project_ids {
-        let client = client.clone();
-        let sem_clone = sem.clone();
-        let after = updated_after.clone();
+    for key in keys {
+        let sem = sem.clone();
+        let fetch = fetch.clone();
         join_set.spawn(async move {
-            // Acquire permit to limit concurrency
-            let permit = sem_clone.acquire_owned().await.unwrap();
-            let _permit = permit; // keep until the end of this async block
+            // Acquire permit to limit concurrency, kept until the end of this async block
+            let _permit = sem.acquire_owned().await.unwrap();
 
-            // Retry logic with exponential backoff
             let mut attempt: u32 = 0;
             let max_retries: u32 = 3;
             loop {
                 attempt += 1;
-                match fetch_pipelines(&client, pid, after).await {
-                    Ok(pipes) => return (pid, Ok(pipes)),
+                match fetch(key).await {
+                    Ok(value) => return (key, Ok(value)),
                     Err(e) => {
                         if attempt > max_retries {
-                            return (pid, Err(e.into()));
+                            return (key, Err(e));
                         }
                         // exponential backoff: 500ms * 2^(attempt-1)
                         let backoff_ms = 500u64.saturating_mul(1u64 << (attempt - 1));
                         tokio::time::sleep(std::time::Duration::from_millis(backoff_ms)).await;
-                        continue;
                     }
                 }
             }
@@ -103,16 +109,160 @@ pub async fn fetch_pipelines_concurrent(
     let mut results = Vec::new();
     while let Some(res) = join_set.join_next().await {
         match res {
-            Ok((pid, Ok(pipes))) => results.push((pid, pipes)),
-            Ok((pid, Err(e))) => {
-                tracing::error!("fetch_pipelines failed for {}: {}", pid, e);
-                results.push((pid, Vec::new()));
+            Ok(result) => results.push(result),
+            Err(e) => tracing::error!("task join error: {}", e),
+        }
+    }
+    results
+}
+
+/// Fetch the pipelines updated after `since` of multiple `(project_id, full_path)` projects
+/// concurrently with a concurrency limit, through GraphQL.
+pub async fn fetch_pipelines_concurrent(
+    client: &Arc<GitlabGraphqlClient>,
+    projects: Vec<(u64, String)>,
+    since: DateTime<Utc>,
+    concurrency: usize,
+) -> Result<Vec<(u64, Vec<PipelineInfo>)>> {
+    let project_ids = projects.iter().map(|(id, _)| *id).collect();
+    let paths: Arc<HashMap<u64, String>> = Arc::new(projects.into_iter().collect());
+    let client = client.clone();
+    let results = fetch_concurrent(project_ids, concurrency, move |pid| {
+        let client = client.clone();
+        let paths = paths.clone();
+        async move { client.fetch_project_pipelines(&paths[&pid], since).await }
+    }).await;
+
+    Ok(results.into_iter()
+        .map(|(pid, res)| match res {
+            Ok(pipes) => (pid, pipes),
+            Err(e) => {
+                tracing::error!("fetch_project_pipelines failed for {}: {}", pid, e);
+                (pid, Vec::new())
             }
+        })
+        .collect())
+}
+
+pub async fn fetch_pipeline_jobs(
+    client: &AsyncGitlab,
+    project_id: u64,
+    pipeline_id: u64,
+) -> Result<Vec<GitlabJob>> {
+    let endpoint = projects::pipelines::PipelineJobs::builder()
+        .project(project_id)
+        .pipeline(pipeline_id)
+        .include_retried(true)
+        .build()?;
+    let jobs: Vec<GitlabJob> = paged(endpoint, Pagination::All)
+        .query_async(client)
+        .await?;
+    Ok(jobs)
+}
+
+/// Fetch jobs for multiple `(project_id, pipeline_id)` pairs concurrently with a concurrency limit.
+pub async fn fetch_jobs_concurrent(
+    client: &AsyncGitlab,
+    pipelines: Vec<(u64, u64)>,
+    concurrency: usize,
+) -> Result<Vec<(u64, Vec<GitlabJob>)>> {
+    let client = client.clone();
+    let results = fetch_concurrent(pipelines, concurrency, move |(project_id, pipeline_id)| {
+        let client = client.clone();
+        async move { fetch_pipeline_jobs(&client, project_id, pipeline_id).await }
+    }).await;
+
+    Ok(results.into_iter()
+        .filter_map(|((_, pipeline_id), res)| match res {
+            Ok(value) => Some((pipeline_id, value)),
             Err(e) => {
-                tracing::error!("task join error: {}", e);
+                tracing::error!("fetch_pipeline_jobs failed for pipeline {}: {}", pipeline_id, e);
+                None
             }
-        }
+        })
+        .collect())
+}
+
+pub async fn fetch_test_report_summary(
+    client: &AsyncGitlab,
+    project_id: u64,
+    pipeline_id: u64,
+) -> Result<GitlabTestReportSummary> {
+    let endpoint = projects::pipelines::PipelineTestReportSummary::builder()
+        .project(project_id)
+        .pipeline(pipeline_id)
+        .build()?;
+    let summary: GitlabTestReportSummary = endpoint.query_async(client).await?;
+    Ok(summary)
+}
+
+/// Fetch test report summaries for multiple `(project_id, pipeline_id)` pairs concurrently with a concurrency limit.
+pub async fn fetch_test_reports_concurrent(
+    client: &AsyncGitlab,
+    pipelines: Vec<(u64, u64)>,
+    concurrency: usize,
+) -> Result<Vec<(u64, GitlabTestReportSummary)>> {
+    let client = client.clone();
+    let results = fetch_concurrent(pipelines, concurrency, move |(project_id, pipeline_id)| {
+        let client = client.clone();
+        async move { fetch_test_report_summary(&client, project_id, pipeline_id).await }
+    }).await;
+
+    Ok(results.into_iter()
+        .filter_map(|((_, pipeline_id), res)| match res {
+            Ok(value) => Some((pipeline_id, value)),
+            Err(e) => {
+                tracing::error!("fetch_test_report_summary failed for pipeline {}: {}", pipeline_id, e);
+                None
+            }
+        })
+        .collect())
+}
+
+pub async fn fetch_environments(client: &AsyncGitlab, project_id: u64) -> Result<Vec<GitlabEnvironment>> {
+    let endpoint = projects::environments::Environments::builder()
+        .project(project_id)
+        .build()?;
+    let environments: Vec<GitlabEnvironment> = paged(endpoint, Pagination::All)
+        .query_async(client)
+        .await?;
+    Ok(environments)
+}
+
+/// Deployments of a project updated after `updated_after`, oldest update first.
+pub async fn fetch_deployments(
+    client: &AsyncGitlab,
+    project_id: u64,
+    updated_after: DateTime<Utc>,
+) -> Result<Vec<GitlabDeployment>> {
+    // GitLab only accepts `updated_after` when ordering by `updated_at`
+    let endpoint = projects::deployments::Deployments::builder()
+        .project(project_id)
+        .updated_after(updated_after)
+        .order_by(projects::deployments::DeploymentOrderBy::UpdatedAt)
+        .sort(gitlab::api::common::SortOrder::Ascending)
+        .build()?;
+    let deployments: Vec<GitlabDeployment> = paged(PagedDeployments(endpoint), Pagination::All)
+        .query_async(client)
+        .await?;
+    Ok(deployments)
+}
+
+/// The deployments list is paginated by GitLab, but the crate does not mark it `Pageable`.
+struct PagedDeployments<'a>(projects::deployments::Deployments<'a>);
+
+impl gitlab::api::Endpoint for PagedDeployments<'_> {
+    fn method(&self) -> gitlab::api::endpoint_prelude::Method {
+        self.0.method()
     }
 
-    Ok(results)
+    fn endpoint(&self) -> std::borrow::Cow<'static, str> {
+        self.0.endpoint()
+    }
+
+    fn parameters(&self) -> gitlab::api::QueryParams<'_> {
+        self.0.parameters()
+    }
 }
+
+impl gitlab::api::Pageable for PagedDeployments<'_> {}
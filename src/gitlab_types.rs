@@ -6,6 +6,17 @@ pub struct ProjectInfo {
     pub name: String,
     pub path_with_namespace: String,
     pub last_activity_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Unset for projects without a repository.
+    #[serde(default)]
+    pub default_branch: Option<String>,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub visibility: Option<String>,
+    #[serde(default)]
+    pub topics: Vec<String>,
+    #[serde(default)]
+    pub web_url: Option<String>,
 }
 
 #[allow(dead_code)]
@@ -47,6 +58,8 @@ pub struct PageInfo {
 
 #[derive(Deserialize)]
 pub struct PipelineConnection {
+    #[serde(rename = "pageInfo")]
+    pub page_info: Option<PageInfo>,
     pub nodes: Option<Vec<PipelineInfo>>,
 }
 
@@ -71,13 +84,23 @@ pub struct PipelineInfo {
     pub status: String,
     #[serde(rename = "createdAt")]
     pub created_at: String,
+    #[serde(rename = "startedAt", default)]
+    pub started_at: Option<String>,
     #[serde(rename = "finishedAt")]
     pub finished_at: Option<String>,
     pub duration: Option<u64>,
+    #[serde(rename = "queuedDuration", default)]
+    pub queued_duration: Option<f64>,
     #[serde(rename = "ref")]
     pub ref_name: String,
     pub web_url: Option<String>,
     pub user: UserInfo,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(rename = "mergeRequest", default)]
+    pub merge_request: Option<MergeRequestInfo>,
+    #[serde(default)]
+    pub coverage: Option<f64>,
 }
 
 impl PipelineInfo {
@@ -85,15 +108,19 @@ impl PipelineInfo {
         let created_ts = chrono::DateTime::parse_from_rfc3339(&self.created_at)
             .map(|dt| dt.timestamp())
             .unwrap_or(0);
+        let started_ts = self.started_at.as_ref().and_then(|s| {
+            chrono::DateTime::parse_from_rfc3339(s).ok().map(|dt| dt.timestamp())
+        });
         let finished_ts = self.finished_at.as_ref().and_then(|s| {
             chrono::DateTime::parse_from_rfc3339(s).ok().map(|dt| dt.timestamp())
         });
+        let (started_at, queued_duration) = pipeline_timing(created_ts, started_ts, self.queued_duration);
         // If duration missing but finished timestamp present, compute duration
         let duration = match (self.duration, finished_ts) {
             (Some(d), _) => Some(d as i64),
             (None, Some(f_ts)) => {
                 let dur = f_ts - created_ts;
-                if dur > 0 { Some(dur as i64) } else { None }
+                if dur > 0 { Some(dur) } else { None }
             }
             _ => None,
         };
@@ -108,55 +135,568 @@ impl PipelineInfo {
             user_name: self.user.name.clone(),
             status: self.status.to_ascii_lowercase(),
             created_at: created_ts,
+            started_at,
             finished_at: finished_ts,
             duration,
+            queued_duration,
             web_url: self.web_url.clone(),
+            source: self.source.as_deref().map(str::to_ascii_lowercase),
+            merge_request_iid: self.merge_request.as_ref().map(|m| m.iid as i64).or_else(|| merge_request_iid(&self.ref_name)),
+            coverage: self.coverage,
         }
     }
 }
 
+/// A merge request from GraphQL: a pipeline's `mergeRequest` or a node of a group's `mergeRequests`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MergeRequestInfo {
+    #[serde(deserialize_with = "parse_gid")]
+    pub iid: u64,
+    #[serde(rename = "projectId")]
+    pub project_id: u64,
+    pub title: String,
+    pub state: Option<String>,
+    #[serde(rename = "webUrl")]
+    pub web_url: Option<String>,
+    #[serde(rename = "createdAt", default, deserialize_with = "gitlab_time_opt")]
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(rename = "mergedAt", default, deserialize_with = "gitlab_time_opt")]
+    pub merged_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(rename = "sourceBranch")]
+    pub source_branch: Option<String>,
+    #[serde(rename = "targetBranch")]
+    pub target_branch: Option<String>,
+    #[serde(default)]
+    pub project: Option<MergeRequestProject>,
+}
+
+/// The project of a GraphQL merge request, to skip those of excluded projects.
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct GitlabPipeline {
+pub struct MergeRequestProject {
+    #[serde(rename = "fullPath")]
+    pub full_path: String,
+}
+
+impl MergeRequestInfo {
+    pub fn to_db_merge_request(&self) -> crate::models::MergeRequest {
+        crate::models::MergeRequest {
+            project_id: self.project_id as i64,
+            iid: self.iid as i64,
+            title: self.title.clone(),
+            state: self.state.as_deref().map(str::to_ascii_lowercase),
+            source_branch: self.source_branch.clone(),
+            target_branch: self.target_branch.clone(),
+            web_url: self.web_url.clone(),
+            created_at: self.created_at.map(|d| d.timestamp()),
+            merged_at: self.merged_at.map(|d| d.timestamp()),
+        }
+    }
+}
+
+/// A runner from GraphQL, as listed by a group's `runners`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RunnerInfo {
+    #[serde(deserialize_with = "parse_gid")]
+    pub id: u64,
+    pub description: Option<String>,
+    #[serde(rename = "runnerType", default)]
+    pub runner_type: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub paused: Option<bool>,
+    #[serde(rename = "executorName", default)]
+    pub executor_name: Option<String>,
+    #[serde(rename = "tagList", default)]
+    pub tag_list: Option<Vec<String>>,
+    #[serde(rename = "contactedAt", default, deserialize_with = "gitlab_time_opt")]
+    pub contacted_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl RunnerInfo {
+    pub fn to_db_runner(&self) -> crate::models::Runner {
+        crate::models::Runner {
+            id: self.id as i64,
+            description: self.description.clone(),
+            runner_type: self.runner_type.as_deref().map(str::to_ascii_lowercase),
+            status: self.status.as_deref().map(str::to_ascii_lowercase),
+            paused: self.paused.unwrap_or(false),
+            executor: self.executor_name.clone(),
+            tags: self.tag_list.as_deref().unwrap_or_default().join(","),
+            contacted_at: self.contacted_at.map(|d| d.timestamp()),
+        }
+    }
+}
+
+/// IID of the merge request whose pipeline ref is `refs/merge-requests/<iid>/head` (or `/merge`, `/train`).
+pub fn merge_request_iid(ref_name: &str) -> Option<i64> {
+    ref_name.strip_prefix("refs/merge-requests/")?.split('/').next()?.parse().ok()
+}
+
+/// Start time and queue time of a pipeline, each derived from the other when GitLab
+/// reports only one of them (webhooks carry `queued_duration` but no start time).
+pub fn pipeline_timing(created_at: i64, started_at: Option<i64>, queued_duration: Option<f64>) -> (Option<i64>, Option<i64>) {
+    let queued = queued_duration.map(|q| q.round() as i64);
+    let started_at = started_at.or(queued.map(|q| created_at + q));
+    let queued = queued.or(started_at.map(|s| s - created_at).filter(|q| *q >= 0));
+    (started_at, queued)
+}
+
+/// Parse a GitLab timestamp: RFC 3339 from the API, `2016-08-12 15:23:28 UTC` from webhooks.
+pub fn parse_gitlab_time(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Some(dt.with_timezone(&chrono::Utc));
+    }
+    if let Ok(dt) = chrono::DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S %z") {
+        return Some(dt.with_timezone(&chrono::Utc));
+    }
+    chrono::NaiveDateTime::parse_from_str(s.trim_end_matches(" UTC"), "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|dt| dt.and_utc())
+}
+
+fn gitlab_time<'de, D>(deserializer: D) -> Result<chrono::DateTime<chrono::Utc>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    parse_gitlab_time(&s).ok_or_else(|| serde::de::Error::custom(format!("invalid timestamp: {}", s)))
+}
+
+fn gitlab_time_opt<'de, D>(deserializer: D) -> Result<Option<chrono::DateTime<chrono::Utc>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s: Option<String> = Deserialize::deserialize(deserializer)?;
+    match s {
+        Some(s) => parse_gitlab_time(&s)
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid timestamp: {}", s))),
+        None => Ok(None),
+    }
+}
+
+/// A job from the REST API; also matches the `builds` entries of a Pipeline Hook.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GitlabJob {
+    pub id: u64,
+    pub name: String,
+    pub stage: String,
+    pub status: String,
+    #[serde(deserialize_with = "gitlab_time")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub duration: Option<f64>,
+    pub queued_duration: Option<f64>,
+    #[serde(default)]
+    pub allow_failure: bool,
+    #[serde(default)]
+    pub web_url: Option<String>,
+    pub runner: Option<JobRunner>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JobRunner {
+    pub id: u64,
+    pub description: Option<String>,
+}
+
+impl GitlabJob {
+    /// `retried` is not part of the REST payload; callers derive it from sibling jobs.
+    pub fn to_db_job(&self, pipeline_id: i64, project_id: i64, retried: bool) -> crate::models::Job {
+        crate::models::Job {
+            id: self.id as i64,
+            pipeline_id,
+            project_id,
+            name: self.name.clone(),
+            stage: self.stage.clone(),
+            status: self.status.to_ascii_lowercase(),
+            runner_id: self.runner.as_ref().map(|r| r.id as i64),
+            runner_description: self.runner.as_ref().and_then(|r| r.description.clone()),
+            created_at: self.created_at.timestamp(),
+            started_at: self.started_at.map(|d| d.timestamp()),
+            finished_at: self.finished_at.map(|d| d.timestamp()),
+            duration: self.duration,
+            queued_duration: self.queued_duration,
+            retried,
+            allow_failure: self.allow_failure,
+            web_url: self.web_url.clone(),
+        }
+    }
+}
+
+/// Convert all jobs of one pipeline, flagging every attempt except the latest of each name as retried.
+pub fn jobs_to_db(jobs: &[GitlabJob], pipeline_id: i64, project_id: i64) -> Vec<crate::models::Job> {
+    let mut latest: std::collections::HashMap<&str, u64> = std::collections::HashMap::new();
+    for j in jobs {
+        let e = latest.entry(j.name.as_str()).or_insert(j.id);
+        if j.id > *e {
+            *e = j.id;
+        }
+    }
+    jobs.iter()
+        .map(|j| j.to_db_job(pipeline_id, project_id, latest.get(j.name.as_str()) != Some(&j.id)))
+        .collect()
+}
+
+/// The test report summary of a pipeline; a suite per job that uploaded JUnit reports.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitlabTestReportSummary {
+    #[serde(default)]
+    pub test_suites: Vec<GitlabTestSuite>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitlabTestSuite {
+    pub name: String,
+    #[serde(default)]
+    pub total_time: f64,
+    #[serde(default)]
+    pub total_count: i64,
+    #[serde(default)]
+    pub success_count: i64,
+    #[serde(default)]
+    pub failed_count: i64,
+    #[serde(default)]
+    pub skipped_count: i64,
+    #[serde(default)]
+    pub error_count: i64,
+}
+
+impl GitlabTestReportSummary {
+    pub fn to_db_test_suites(&self, pipeline_id: i64, project_id: i64) -> Vec<crate::models::TestSuite> {
+        self.test_suites.iter().map(|t| crate::models::TestSuite {
+            pipeline_id,
+            project_id,
+            name: t.name.clone(),
+            total_time: t.total_time,
+            total_count: t.total_count,
+            success_count: t.success_count,
+            failed_count: t.failed_count,
+            skipped_count: t.skipped_count,
+            error_count: t.error_count,
+        }).collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitlabEnvironment {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub tier: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+}
+
+impl GitlabEnvironment {
+    pub fn to_db_environment(&self, project_id: i64) -> crate::models::Environment {
+        crate::models::Environment {
+            id: self.id as i64,
+            project_id,
+            name: self.name.clone(),
+            tier: self.tier.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// A deployment from the REST API, with the job that ran it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GitlabDeployment {
     pub id: u64,
     pub r#ref: String,
     pub sha: String,
     pub status: String,
-    #[serde(rename = "created_at")]
+    #[serde(deserialize_with = "gitlab_time")]
     pub created_at: chrono::DateTime<chrono::Utc>,
-    #[serde(rename = "updated_at")]
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub environment: DeploymentEnvironment,
+    #[serde(default)]
+    pub deployable: Option<DeploymentJob>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeploymentEnvironment {
+    pub id: u64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeploymentJob {
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub commit: Option<DeploymentCommit>,
+    #[serde(default)]
+    pub pipeline: Option<DeploymentPipeline>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeploymentCommit {
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeploymentPipeline {
+    pub id: u64,
+}
+
+impl GitlabDeployment {
+    pub fn to_db_deployment(&self, project_id: i64, project_full_path: &str) -> crate::models::Deployment {
+        let status = self.status.to_ascii_lowercase();
+        let job = self.deployable.as_ref();
+        // Older GitLab releases have no deployment finish time; fall back to the job's,
+        // then to the last update of a deployment that already finished
+        let finished_at = self.finished_at
+            .or(job.and_then(|j| j.finished_at))
+            .or(if matches!(status.as_str(), "success" | "failed" | "canceled") { self.updated_at } else { None });
+        crate::models::Deployment {
+            id: self.id as i64,
+            project_id,
+            project_full_path: project_full_path.to_string(),
+            environment_id: self.environment.id as i64,
+            environment_name: self.environment.name.clone(),
+            ref_name: self.r#ref.clone(),
+            sha: self.sha.clone(),
+            status,
+            created_at: self.created_at.timestamp(),
+            finished_at: finished_at.map(|d| d.timestamp()),
+            commit_created_at: job.and_then(|j| j.commit.as_ref()).and_then(|c| c.created_at).map(|d| d.timestamp()),
+            pipeline_id: job.and_then(|j| j.pipeline.as_ref()).map(|p| p.id as i64),
+            environment_tier: None,
+        }
+    }
+}
+
+/// Payload of a GitLab Pipeline Hook (`object_kind = "pipeline"`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct PipelineHook {
+    pub object_attributes: PipelineHookAttributes,
+    pub project: HookProject,
+    pub user: Option<HookUser>,
+    /// Set for merge request pipelines.
+    #[serde(default)]
+    pub merge_request: Option<HookMergeRequest>,
+    #[serde(default)]
+    pub builds: Vec<GitlabJob>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PipelineHookAttributes {
+    pub id: u64,
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+    pub sha: String,
+    pub status: String,
+    #[serde(deserialize_with = "gitlab_time")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
     pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
-    pub web_url: Option<String>,
     pub duration: Option<u64>,
+    pub queued_duration: Option<f64>,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub source: Option<String>,
 }
 
-impl GitlabPipeline {
-    pub fn to_db_pipeline(&self, project_id: i64, project_name: &str, project_full_path: &str) -> crate::models::Pipeline {
-        // compute duration if missing but finished_at is present
-        let created_ts = self.created_at.timestamp();
-        let finished_ts = self.finished_at.map(|d| d.timestamp());
-        let duration = match (self.duration, finished_ts) {
+#[derive(Debug, Clone, Deserialize)]
+pub struct HookProject {
+    pub id: u64,
+    pub name: String,
+    pub path_with_namespace: String,
+    pub web_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HookUser {
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HookMergeRequest {
+    pub iid: u64,
+    pub title: String,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub source_branch: Option<String>,
+    #[serde(default)]
+    pub target_branch: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+/// Payload of a GitLab Merge Request Hook (`object_kind = "merge_request"`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeRequestHook {
+    pub object_attributes: MergeRequestHookAttributes,
+    pub project: HookProject,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeRequestHookAttributes {
+    pub iid: u64,
+    pub title: String,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default)]
+    pub source_branch: Option<String>,
+    #[serde(default)]
+    pub target_branch: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// `open`, `update`, `merge`, `close`, ...
+    #[serde(default)]
+    pub action: Option<String>,
+}
+
+impl MergeRequestHook {
+    pub fn to_db_merge_request(&self) -> crate::models::MergeRequest {
+        let attrs = &self.object_attributes;
+        crate::models::MergeRequest {
+            project_id: self.project.id as i64,
+            iid: attrs.iid as i64,
+            title: attrs.title.clone(),
+            state: attrs.state.clone(),
+            source_branch: attrs.source_branch.clone(),
+            target_branch: attrs.target_branch.clone(),
+            web_url: attrs.url.clone(),
+            created_at: attrs.created_at.map(|d| d.timestamp()),
+            // The payload has no merge time; the merge event is the last update
+            merged_at: if attrs.action.as_deref() == Some("merge") { attrs.updated_at.map(|d| d.timestamp()) } else { None },
+        }
+    }
+}
+
+impl PipelineHook {
+    pub fn to_db_pipeline(&self) -> crate::models::Pipeline {
+        let attrs = &self.object_attributes;
+        let created_ts = attrs.created_at.timestamp();
+        let finished_ts = attrs.finished_at.map(|d| d.timestamp());
+        let (started_at, queued_duration) = pipeline_timing(created_ts, None, attrs.queued_duration);
+        let duration = match (attrs.duration, finished_ts) {
             (Some(d), _) => Some(d as i64),
             (None, Some(f_ts)) => {
                 let dur = f_ts - created_ts;
-                if dur > 0 { Some(dur as i64) } else { None }
+                if dur > 0 { Some(dur) } else { None }
             }
             _ => None,
         };
 
         crate::models::Pipeline {
-            id: self.id as i64,
-            project_id,
-            project_name: project_name.to_string(),
-            project_full_path: project_full_path.to_string(),
-            ref_name: self.r#ref.clone(),
-            sha: self.sha.clone(),
-             // GitLab REST API /api/v4/projects/:ID/pipelines does not provide user info in pipeline object
-            user_name: "".to_string(),
-            status: self.status.to_ascii_lowercase(),
+            id: attrs.id as i64,
+            project_id: self.project.id as i64,
+            project_name: self.project.name.clone(),
+            project_full_path: self.project.path_with_namespace.clone(),
+            ref_name: attrs.ref_name.clone(),
+            sha: attrs.sha.clone(),
+            user_name: self.user.as_ref().and_then(|u| u.name.clone()).unwrap_or_default(),
+            status: attrs.status.to_ascii_lowercase(),
             created_at: created_ts,
+            started_at,
             finished_at: finished_ts,
             duration,
-            web_url: self.web_url.clone(),
+            queued_duration,
+            web_url: attrs.url.clone(),
+            source: attrs.source.clone(),
+            merge_request_iid: self.merge_request.as_ref().map(|m| m.iid as i64).or_else(|| merge_request_iid(&attrs.ref_name)),
+            // Hooks carry no coverage; polling fills it in
+            coverage: None,
+        }
+    }
+
+    /// The merge request of a merge request pipeline; the hook carries no creation or merge time.
+    pub fn to_db_merge_request(&self) -> Option<crate::models::MergeRequest> {
+        let mr = self.merge_request.as_ref()?;
+        Some(crate::models::MergeRequest {
+            project_id: self.project.id as i64,
+            iid: mr.iid as i64,
+            title: mr.title.clone(),
+            state: mr.state.clone(),
+            source_branch: mr.source_branch.clone(),
+            target_branch: mr.target_branch.clone(),
+            web_url: mr.url.clone(),
+            created_at: None,
+            merged_at: None,
+        })
+    }
+
+    /// Jobs embedded in the hook; their URLs are derived from the project URL.
+    pub fn to_db_jobs(&self) -> Vec<crate::models::Job> {
+        let base = self.project.web_url.as_deref().unwrap_or("");
+        let builds: Vec<GitlabJob> = self.builds.iter().cloned().map(|mut b| {
+            if b.web_url.is_none() && !base.is_empty() {
+                b.web_url = Some(format!("{}/-/jobs/{}", base, b.id));
+            }
+            b
+        }).collect();
+        jobs_to_db(&builds, self.object_attributes.id as i64, self.project.id as i64)
+    }
+}
+
+/// Payload of a GitLab Job Hook (`object_kind = "build"`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobHook {
+    pub build_id: u64,
+    pub build_name: String,
+    pub build_stage: String,
+    pub build_status: String,
+    #[serde(deserialize_with = "gitlab_time")]
+    pub build_created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
+    pub build_started_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default, deserialize_with = "gitlab_time_opt")]
+    pub build_finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub build_duration: Option<f64>,
+    pub build_queued_duration: Option<f64>,
+    #[serde(default)]
+    pub build_allow_failure: bool,
+    pub pipeline_id: u64,
+    pub project_id: u64,
+    pub runner: Option<JobRunner>,
+    pub repository: Option<HookRepository>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HookRepository {
+    pub homepage: Option<String>,
+}
+
+impl JobHook {
+    /// Retries are detected when a newer attempt with the same name is stored.
+    pub fn to_db_job(&self) -> crate::models::Job {
+        let web_url = self.repository.as_ref()
+            .and_then(|r| r.homepage.as_ref())
+            .map(|h| format!("{}/-/jobs/{}", h, self.build_id));
+        crate::models::Job {
+            id: self.build_id as i64,
+            pipeline_id: self.pipeline_id as i64,
+            project_id: self.project_id as i64,
+            name: self.build_name.clone(),
+            stage: self.build_stage.clone(),
+            status: self.build_status.to_ascii_lowercase(),
+            runner_id: self.runner.as_ref().map(|r| r.id as i64),
+            runner_description: self.runner.as_ref().and_then(|r| r.description.clone()),
+            created_at: self.build_created_at.timestamp(),
+            started_at: self.build_started_at.map(|d| d.timestamp()),
+            finished_at: self.build_finished_at.map(|d| d.timestamp()),
+            duration: self.build_duration,
+            queued_duration: self.build_queued_duration,
+            retried: false,
+            allow_failure: self.build_allow_failure,
+            web_url,
         }
     }
 }
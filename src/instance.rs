@@ -0,0 +1,278 @@
+//! GitLab instances and the id namespaces that keep their rows apart in storage.
+//!
+//! Every GitLab id (pipeline, job, project, runner, ...) is stored as
+//! `namespace << NAMESPACE_SHIFT | id`. The first registered instance has namespace 0, so
+//! its ids are GitLab's own and databases written before instances existed stay valid.
+
+use crate::config::GitLabConfig;
+use crate::gitlab_graphql::{GitlabGraphqlClient, PollTarget};
+use crate::models::{Deployment, Environment, Job, MergeRequest, Pipeline, Runner};
+use gitlab::AsyncGitlab;
+use regex::Regex;
+use std::sync::Arc;
+
+/// Bits left to the GitLab id; 2^40 leaves ample room above GitLab.com's job ids.
+pub const NAMESPACE_SHIFT: u32 = 40;
+
+#[derive(Clone)]
+pub struct Instance {
+    pub name: String,
+    pub namespace: i64,
+    pub config: GitLabConfig,
+    pub projects: ProjectSelector,
+    pub gitlab_client: Arc<AsyncGitlab>,
+    pub graphql_client: Arc<GitlabGraphqlClient>,
+}
+
+impl Instance {
+    /// Stored id of a GitLab id of this instance.
+    pub fn id(&self, gitlab_id: u64) -> i64 {
+        (self.namespace << NAMESPACE_SHIFT) | gitlab_id as i64
+    }
+
+    /// Stored ids of this instance lie in `[first, end)`.
+    pub fn id_range(&self) -> (i64, i64) {
+        (self.namespace << NAMESPACE_SHIFT, (self.namespace + 1) << NAMESPACE_SHIFT)
+    }
+
+    /// Watermark key of a monitored group or project; the first instance keeps the bare
+    /// path it used before instances existed.
+    pub fn watermark_key(&self, full_path: &str) -> String {
+        if self.namespace == 0 {
+            full_path.to_string()
+        } else {
+            format!("{}:{}", self.name, full_path)
+        }
+    }
+
+    pub fn scope_pipeline(&self, mut p: Pipeline) -> Pipeline {
+        p.id = self.id(p.id as u64);
+        p.project_id = self.id(p.project_id as u64);
+        p
+    }
+
+    pub fn scope_jobs(&self, jobs: Vec<Job>) -> Vec<Job> {
+        jobs.into_iter()
+            .map(|mut j| {
+                j.id = self.id(j.id as u64);
+                j.pipeline_id = self.id(j.pipeline_id as u64);
+                j.project_id = self.id(j.project_id as u64);
+                j.runner_id = j.runner_id.map(|r| self.id(r as u64));
+                j
+            })
+            .collect()
+    }
+
+    pub fn scope_merge_request(&self, mut mr: MergeRequest) -> MergeRequest {
+        mr.project_id = self.id(mr.project_id as u64);
+        mr
+    }
+
+    pub fn scope_environment(&self, mut e: Environment) -> Environment {
+        e.id = self.id(e.id as u64);
+        e.project_id = self.id(e.project_id as u64);
+        e
+    }
+
+    pub fn scope_deployment(&self, mut d: Deployment) -> Deployment {
+        d.id = self.id(d.id as u64);
+        d.project_id = self.id(d.project_id as u64);
+        d.environment_id = self.id(d.environment_id as u64);
+        d.pipeline_id = d.pipeline_id.map(|p| self.id(p as u64));
+        d
+    }
+
+    pub fn scope_runner(&self, mut r: Runner) -> Runner {
+        r.id = self.id(r.id as u64);
+        r
+    }
+}
+
+/// Which projects of an instance are monitored: those of `monitor_groups` (with their
+/// subgroups unless `include_subgroups = false`) and `monitor_projects`, minus the excluded ones.
+#[derive(Clone)]
+pub struct ProjectSelector {
+    groups: Vec<String>,
+    include_subgroups: bool,
+    projects: Vec<String>,
+    exclude: Vec<Regex>,
+}
+
+impl ProjectSelector {
+    pub fn new(config: &GitLabConfig) -> Self {
+        let mut exclude: Vec<Regex> = config.exclude_projects.iter()
+            .filter_map(|glob| Regex::new(&glob_regex(glob)).ok())
+            .collect();
+        // Validated when the config was loaded
+        exclude.extend(config.exclude_projects_regex.as_deref().and_then(|re| Regex::new(re).ok()));
+        ProjectSelector {
+            groups: config.monitor_groups.clone(),
+            include_subgroups: config.include_subgroups.unwrap_or(true),
+            projects: config.monitor_projects.clone(),
+            exclude,
+        }
+    }
+
+    /// The groups, then the projects, polled for incremental activity.
+    pub fn poll_targets(&self) -> Vec<PollTarget<'_>> {
+        self.groups.iter()
+            .map(|g| PollTarget::Group { full_path: g, include_subgroups: self.include_subgroups })
+            .chain(self.projects.iter().map(|p| PollTarget::Project(p)))
+            .collect()
+    }
+
+    pub fn is_excluded(&self, full_path: &str) -> bool {
+        self.exclude.iter().any(|re| re.is_match(full_path))
+    }
+
+    /// Whether a project, known only by its path (e.g. from a webhook), is monitored.
+    pub fn is_monitored(&self, full_path: &str) -> bool {
+        if self.is_excluded(full_path) {
+            return false;
+        }
+        self.projects.iter().any(|p| p == full_path)
+            || self.groups.iter().any(|g| {
+                full_path.strip_prefix(g.as_str())
+                    .and_then(|rest| rest.strip_prefix('/'))
+                    .is_some_and(|rest| self.include_subgroups || !rest.contains('/'))
+            })
+    }
+}
+
+/// Anchored regex of a project path glob.
+fn glob_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                re.push_str(".*");
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+/// GitLab id of a stored id.
+pub fn gitlab_id(id: i64) -> u64 {
+    (id & ((1 << NAMESPACE_SHIFT) - 1)) as u64
+}
+
+/// The configured instance a stored id belongs to; `None` once its instance was removed.
+pub fn instance_of(instances: &[Instance], id: i64) -> Option<&Instance> {
+    instances.iter().find(|i| i.namespace == id >> NAMESPACE_SHIFT)
+}
+
+/// Whether a stored id belongs to one of the instances named in a comma separated list;
+/// "All" or empty matches everything.
+pub fn matches(instances: &[Instance], id: i64, names: Option<&str>) -> bool {
+    match names {
+        None | Some("") | Some("All") => true,
+        Some(names) => instance_of(instances, id)
+            .is_some_and(|i| names.split(',').any(|n| n.trim() == i.name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gitlab::GitlabBuilder;
+
+    fn config(name: &str) -> GitLabConfig {
+        GitLabConfig {
+            name: Some(name.to_string()),
+            url: format!("https://{}.example.com", name),
+            token: String::new(),
+            monitor_groups: vec!["grp".to_string()],
+            monitor_projects: vec!["other/svc".to_string()],
+            exclude_projects: vec!["grp/sandbox-*".to_string(), "grp/**/archive".to_string()],
+            exclude_projects_regex: Some("^grp/tmp-".to_string()),
+            include_subgroups: None,
+            branch_filter_regex: None,
+            timeout_seconds: None,
+            skip_invalid_certs: None,
+            webhook_secret: None,
+        }
+    }
+
+    async fn instance(name: &str, namespace: i64) -> Instance {
+        let config = config(name);
+        let host = format!("{}.example.com", name);
+        // Unauthenticated clients are built without a request to the instance
+        let gitlab_client = GitlabBuilder::new_unauthenticated(&host).build_async().await.unwrap();
+        let graphql_client = GitlabGraphqlClient::new(config.url.clone(), String::new(), 30, false);
+        Instance {
+            name: name.to_string(),
+            namespace,
+            projects: ProjectSelector::new(&config),
+            config,
+            gitlab_client: Arc::new(gitlab_client),
+            graphql_client: Arc::new(graphql_client),
+        }
+    }
+
+    #[test]
+    fn glob_regex_stays_within_segments() {
+        let re = |glob: &str| Regex::new(&glob_regex(glob)).unwrap();
+        assert!(re("grp/sandbox-*").is_match("grp/sandbox-x"));
+        assert!(!re("grp/sandbox-*").is_match("grp/sandbox-x/app"));
+        assert!(re("grp/**/archive").is_match("grp/a/b/archive"));
+        assert!(!re("grp/**/archive").is_match("grp/archive-old"));
+        assert!(re("grp/app?").is_match("grp/app2"));
+        assert!(!re("grp/app?").is_match("grp/app/"));
+        // Regex metacharacters are literal and the pattern is anchored
+        assert!(!re("grp/a.b").is_match("grp/axb"));
+        assert!(!re("grp/app").is_match("x/grp/app"));
+    }
+
+    #[test]
+    fn project_selector_monitors_groups_and_projects_minus_exclusions() {
+        let selector = ProjectSelector::new(&config("default"));
+        assert!(selector.is_monitored("grp/app"));
+        assert!(selector.is_monitored("grp/sub/app"));
+        assert!(selector.is_monitored("other/svc"));
+        assert!(!selector.is_monitored("other/app"));
+        assert!(!selector.is_monitored("grpx/app"));
+        assert!(!selector.is_monitored("grp"));
+        assert!(!selector.is_monitored("grp/sandbox-x"));
+        assert!(!selector.is_monitored("grp/sub/archive"));
+        assert!(!selector.is_monitored("grp/tmp-build"));
+    }
+
+    #[test]
+    fn project_selector_without_subgroups_takes_direct_children_only() {
+        let selector = ProjectSelector::new(&GitLabConfig { include_subgroups: Some(false), ..config("default") });
+        assert!(selector.is_monitored("grp/app"));
+        assert!(!selector.is_monitored("grp/sub/app"));
+    }
+
+    #[tokio::test]
+    async fn ids_keep_their_namespace() {
+        let first = instance("default", 0).await;
+        let second = instance("com", 1).await;
+        assert_eq!(first.id(42), 42);
+        assert_eq!(second.id(42), (1 << NAMESPACE_SHIFT) | 42);
+        assert_eq!(gitlab_id(first.id(42)), 42);
+        assert_eq!(gitlab_id(second.id(42)), 42);
+        assert_eq!(second.id_range(), (1 << NAMESPACE_SHIFT, 2 << NAMESPACE_SHIFT));
+    }
+
+    #[tokio::test]
+    async fn instance_of_finds_the_namespace() {
+        let instances = vec![instance("default", 0).await, instance("com", 2).await];
+        assert_eq!(instance_of(&instances, 42).map(|i| i.name.as_str()), Some("default"));
+        assert_eq!(instance_of(&instances, instances[1].id(42)).map(|i| i.name.as_str()), Some("com"));
+        // Namespace 1 belonged to an instance that is no longer configured
+        assert!(instance_of(&instances, (1 << NAMESPACE_SHIFT) | 42).is_none());
+
+        assert!(matches(&instances, 42, None));
+        assert!(matches(&instances, 42, Some("All")));
+        assert!(matches(&instances, instances[1].id(42), Some("default, com")));
+        assert!(!matches(&instances, 42, Some("com")));
+    }
+}
@@ -1,14 +1,22 @@
 mod api;
 mod config;
 mod db;
+mod dora;
 mod gitlab_ops;
 mod gitlab_graphql;
 mod models;
 mod gitlab_types;
+mod instance;
+mod metrics;
 mod monitor;
+mod projects;
+mod retention;
+mod runners;
 mod state;
+mod webhook;
 
 use crate::config::Config;
+use crate::instance::{Instance, ProjectSelector};
 use crate::state::AppState;
 use anyhow::Result;
 use gitlab::GitlabBuilder;
@@ -31,42 +39,58 @@ async fn main() -> Result<()> {
     let config = Arc::new(config);
 
     // Initialize DB
-    let db = db::init_db().await.expect("Failed to initialize database");
-
-    // Record service start time as initial poll watermark
-    if let Err(e) = crate::db::set_last_poll(&db, chrono::Utc::now().timestamp()).await {
-        tracing::warn!("Failed to set initial poll watermark: {}", e);
+    let db = db::init_db(&config.database, config.timezone()).await.expect("Failed to initialize database");
+
+    // Each instance keeps the id namespace it was first registered with
+    let namespaces = db.register_instances(&config.gitlab).await.expect("Failed to register GitLab instances");
+
+    let mut instances = Vec::new();
+    for (gitlab, namespace) in config.gitlab.iter().zip(namespaces) {
+        let host = gitlab.url
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .trim_end_matches('/');
+
+        let gitlab_client = GitlabBuilder::new(host, gitlab.token.clone())
+            .build_async()
+            .await
+            .expect("Failed to create GitLab client");
+
+        // Initialize GraphQL Client
+        let graphql_client = crate::gitlab_graphql::GitlabGraphqlClient::new(
+            gitlab.url.clone(),
+            gitlab.token.clone(),
+            gitlab.timeout_seconds.unwrap_or(30),
+            gitlab.skip_invalid_certs.unwrap_or(false),
+        );
+
+        instances.push(Instance {
+            name: gitlab.name().to_string(),
+            namespace,
+            config: gitlab.clone(),
+            projects: ProjectSelector::new(gitlab),
+            gitlab_client: Arc::new(gitlab_client),
+            graphql_client: Arc::new(graphql_client),
+        });
     }
 
-    // Check if this is a fresh install (no pipelines)
-    let pipeline_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pipelines")
-        .fetch_one(&db)
-        .await
-        .unwrap_or(0);
-    let is_fresh_install = pipeline_count == 0;
-    if is_fresh_install {
-        info!("Fresh install detected. Will perform initial backfill for all projects.");
+    // Carry the legacy global poll watermark over to groups that have none yet; it was
+    // written by the instance that kept namespace 0
+    if let Some(first) = instances.iter().find(|i| i.namespace == 0) {
+        if let Err(e) = db.migrate_poll_state(&first.config.monitor_groups).await {
+            tracing::warn!("Failed to migrate poll watermarks: {}", e);
+        }
     }
 
-    let host = config.gitlab.url
-        .trim_start_matches("https://")
-        .trim_start_matches("http://")
-        .trim_end_matches('/');
-        
-    let gitlab_client = GitlabBuilder::new(host, config.gitlab.token.clone())
-        .build_async()
-        .await
-        .expect("Failed to create GitLab client");
-    let gitlab_client = Arc::new(gitlab_client);
-
-    // Initialize GraphQL Client
-    let graphql_client = crate::gitlab_graphql::GitlabGraphqlClient::new(
-        config.gitlab.url.clone(),
-        config.gitlab.token.clone(),
-        config.gitlab.timeout_seconds.unwrap_or(30),
-        config.gitlab.skip_invalid_certs.unwrap_or(false),
-    );
-    let graphql_client = Arc::new(graphql_client);
+    // Check which instances are freshly added (no pipelines)
+    let mut fresh = Vec::new();
+    for instance in &instances {
+        if db.pipeline_count(instance.id_range()).await.unwrap_or(0) == 0 {
+            info!("No pipelines stored for GitLab instance {}. Will perform initial backfill for all its projects.", instance.name);
+            fresh.push(instance.clone());
+        }
+    }
+    let is_fresh_install = fresh.len() == instances.len();
 
     let ttl = config.poller.ttl_seconds.unwrap_or(600) as u64;
     let capacity = config.poller.capacity.unwrap_or(10_000) as u64;
@@ -74,8 +98,7 @@ async fn main() -> Result<()> {
     // Create AppState
     let state = AppState {
         db,
-        gitlab_client,
-        graphql_client,
+        instances: Arc::new(instances),
         config: config.clone(),
         monitored_projects: Arc::new(RwLock::new(Vec::new())),
         refresh_notify: Arc::new(tokio::sync::Notify::new()),
@@ -87,34 +110,79 @@ async fn main() -> Result<()> {
     };
 
     // Perform initial backfill if needed (BLOCKING)
-    if is_fresh_install {
-        monitor::perform_initial_backfill(state.clone()).await;
-        // After initial backfill, asynchronously backfill usernames (do not block server start)
-        let username_state = state.clone();
-        tokio::spawn(async move {
-            monitor::backfill_usernames(username_state).await;
-        });
+    for instance in &fresh {
+        monitor::perform_initial_backfill(state.clone(), instance).await;
     }
 
-    // If not fresh install, start username backfill in background as well
-    if !is_fresh_install {
-        let username_state = state.clone();
-        tokio::spawn(async move {
-            monitor::backfill_usernames(username_state).await;
-        });
-    }
+    // Asynchronously backfill usernames (do not block server start)
+    let username_state = state.clone();
+    tokio::spawn(async move {
+        monitor::backfill_usernames(username_state).await;
+    });
+
+    // Fill in job-level data for already stored pipelines in the background
+    let jobs_state = state.clone();
+    tokio::spawn(async move {
+        monitor::backfill_jobs(jobs_state).await;
+    });
 
-    // Ensure daily_stats is populated on startup; if empty, run backfill
-    let daily_stats_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM daily_stats")
-        .fetch_one(&state.db)
-        .await
-        .unwrap_or(0);
-    if daily_stats_count == 0 {
-        info!("daily_stats empty — running backfill_daily_stats on startup");
-        if let Err(e) = crate::db::backfill_daily_stats(&state.db).await {
-            tracing::error!("daily_stats backfill on startup failed: {}", e);
-        } else {
-            info!("daily_stats backfill completed");
+    // Ensure daily_stats is populated on startup and bucketed in the reporting timezone;
+    // rollups written before the timezone was recorded were bucketed in UTC
+    let tz = config.timezone().name();
+    let rollup_tz = state.db.get_setting(db::SETTING_ROLLUP_TIMEZONE).await.ok().flatten();
+    let previous_tz = rollup_tz.as_deref().unwrap_or("UTC");
+    let daily_stats_count = state.db.daily_stats_count().await.unwrap_or(0);
+    let tz_changed = daily_stats_count > 0 && previous_tz != tz;
+    // Retention pruned whole days of the previous timezone; the rebuild can only start at one
+    // of its midnights, and days before it keep the rollups bucketed in that timezone
+    let previous = if tz_changed && retention::pipelines_floor(&config) > 0 {
+        previous_tz.parse::<chrono_tz::Tz>().ok()
+    } else {
+        None
+    };
+    let since = match previous {
+        Some(previous) => retention::pipelines_floor_in(&config, previous),
+        None => retention::pipelines_floor(&config),
+    };
+    let rebuild_reason = if daily_stats_count == 0 {
+        Some("daily_stats empty".to_string())
+    } else if tz_changed {
+        Some(format!("reporting timezone changed from {} to {}", previous_tz, tz))
+    } else {
+        None
+    };
+    let rollups_current = match rebuild_reason {
+        Some(reason) => {
+            info!("{} — rebuilding rollups on startup", reason);
+            match state.db.rebuild_rollups(since, previous).await {
+                Ok(()) => {
+                    info!("rollup rebuild completed");
+                    true
+                }
+                Err(e) => {
+                    tracing::error!("rollup rebuild on startup failed: {}", e);
+                    false
+                }
+            }
+        }
+        None => true,
+    };
+    // Only record the timezone once the rollups match it, so a failed rebuild is retried
+    if rollups_current && tz_changed {
+        let from = db::local_date(db::next_local_midnight(since, config.timezone()), config.timezone());
+        if previous.is_some() {
+            tracing::warn!(
+                "daily rollups before {} have no raw pipelines left to rebuild from and stay bucketed in {}",
+                from, previous_tz
+            );
+        }
+        if let Err(e) = state.db.set_setting(db::SETTING_ROLLUP_TIMEZONE_FROM, &from).await {
+            tracing::error!("Failed to record rollup timezone start: {}", e);
+        }
+    }
+    if rollups_current && rollup_tz.as_deref() != Some(tz) {
+        if let Err(e) = state.db.set_setting(db::SETTING_ROLLUP_TIMEZONE, tz).await {
+            tracing::error!("Failed to record rollup timezone: {}", e);
         }
     }
 
@@ -124,6 +192,30 @@ async fn main() -> Result<()> {
         monitor::start_monitor_loop(monitor_state).await;
     });
 
+    // Prune expired pipelines and rollups in background
+    let retention_state = state.clone();
+    tokio::spawn(async move {
+        retention::start_retention_loop(retention_state).await;
+    });
+
+    // Re-discover the monitored projects, backfilling new ones, in background
+    let projects_state = state.clone();
+    tokio::spawn(async move {
+        projects::start_discovery_loop(projects_state).await;
+    });
+
+    // Poll environments and deployments for the DORA metrics in background
+    let dora_state = state.clone();
+    tokio::spawn(async move {
+        dora::start_deployment_loop(dora_state).await;
+    });
+
+    // Poll the runners of the monitored groups for /api/runners in background
+    let runners_state = state.clone();
+    tokio::spawn(async move {
+        runners::start_runner_loop(runners_state).await;
+    });
+
     // Start Web Server
     let app = api::app_router(state);
     let addr = format!("{}:{}", config.server.host, config.server.port);
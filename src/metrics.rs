@@ -0,0 +1,152 @@
+use crate::state::AppState;
+use anyhow::Result;
+use std::fmt::Write;
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// `ref` is opt-in: merge request and feature branches leave its cardinality unbounded
+const DEFAULT_LABELS: &[&str] = &["project", "status"];
+const DEFAULT_BUCKETS: &[f64] = &[30.0, 60.0, 120.0, 300.0, 600.0, 900.0, 1800.0, 3600.0, 7200.0];
+
+// Every status GitLab reports, emitted as 0/1 on the last-status gauge.
+const PIPELINE_STATUSES: &[&str] = &[
+    "created", "waiting_for_resource", "preparing", "pending", "running",
+    "success", "failed", "canceled", "skipped", "manual", "scheduled",
+];
+
+/// A label exposed on the series and the column it is read from.
+#[derive(Clone, Copy)]
+struct Label {
+    name: &'static str,
+    column: &'static str,
+}
+
+fn label_for(name: &str) -> Option<Label> {
+    match name {
+        "instance" => Some(Label { name: "instance", column: "instance" }),
+        "project" => Some(Label { name: "project", column: "project_full_path" }),
+        "ref" => Some(Label { name: "ref", column: "ref_name" }),
+        "status" => Some(Label { name: "status", column: "status" }),
+        _ => None,
+    }
+}
+
+fn configured_labels(state: &AppState) -> Vec<Label> {
+    let names: Vec<String> = match &state.config.metrics.labels {
+        Some(l) => l.clone(),
+        // Several instances may have projects with the same path
+        None if state.instances.len() > 1 => std::iter::once("instance").chain(DEFAULT_LABELS.iter().copied()).map(String::from).collect(),
+        None => DEFAULT_LABELS.iter().map(|s| s.to_string()).collect(),
+    };
+    let mut labels: Vec<Label> = Vec::new();
+    for n in names {
+        match label_for(&n) {
+            Some(l) if !labels.iter().any(|x| x.name == l.name) => labels.push(l),
+            Some(_) => {}
+            None => tracing::warn!("Ignoring unknown metrics label '{}'", n),
+        }
+    }
+    labels
+}
+
+fn configured_buckets(state: &AppState) -> Vec<f64> {
+    let mut buckets = state.config.metrics.duration_buckets.clone()
+        .unwrap_or_else(|| DEFAULT_BUCKETS.to_vec());
+    buckets.retain(|b| b.is_finite());
+    buckets.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    buckets.dedup();
+    buckets
+}
+
+/// Render all pipeline metrics in OpenMetrics text format.
+pub async fn render(state: &AppState) -> Result<String> {
+    let labels = configured_labels(state);
+    let buckets = configured_buckets(state);
+
+    let mut out = String::new();
+    write_pipeline_counters(state, &labels, &mut out).await?;
+    write_duration_histogram(state, &labels, &buckets, &mut out).await?;
+    write_last_status(state, &labels, &mut out).await?;
+    out.push_str("# EOF\n");
+    Ok(out)
+}
+
+async fn write_pipeline_counters(state: &AppState, labels: &[Label], out: &mut String) -> Result<()> {
+    // Read from pipeline_totals rather than daily_stats, which moves a retried pipeline to
+    // its new status and is pruned by retention
+    let rows = state.db.metric_pipeline_counts(&columns(labels)).await?;
+
+    out.push_str("# TYPE gitlab_ci_pipelines counter\n");
+    out.push_str("# HELP gitlab_ci_pipelines Number of finished pipelines.\n");
+    for row in rows {
+        writeln!(out, "gitlab_ci_pipelines_total{} {}", format_labels(labels, &row.labels, None), row.count)?;
+    }
+    Ok(())
+}
+
+async fn write_duration_histogram(state: &AppState, labels: &[Label], buckets: &[f64], out: &mut String) -> Result<()> {
+    let rows = state.db.metric_duration_histograms(&columns(labels), buckets).await?;
+
+    out.push_str("# TYPE gitlab_ci_pipeline_duration_seconds histogram\n");
+    out.push_str("# HELP gitlab_ci_pipeline_duration_seconds Duration of finished pipelines.\n");
+    for row in rows {
+        if row.count == 0 {
+            continue;
+        }
+        for (b, in_bucket) in buckets.iter().zip(&row.buckets) {
+            let le = format!("{:?}", b);
+            writeln!(out, "gitlab_ci_pipeline_duration_seconds_bucket{} {}",
+                format_labels(labels, &row.labels, Some(("le", &le))), in_bucket)?;
+        }
+        writeln!(out, "gitlab_ci_pipeline_duration_seconds_bucket{} {}",
+            format_labels(labels, &row.labels, Some(("le", "+Inf"))), row.count)?;
+        writeln!(out, "gitlab_ci_pipeline_duration_seconds_count{} {}", format_labels(labels, &row.labels, None), row.count)?;
+        writeln!(out, "gitlab_ci_pipeline_duration_seconds_sum{} {}", format_labels(labels, &row.labels, None), row.sum)?;
+    }
+    Ok(())
+}
+
+async fn write_last_status(state: &AppState, labels: &[Label], out: &mut String) -> Result<()> {
+    // The gauge always carries its own status label, so group by the remaining ones
+    let group: Vec<Label> = labels.iter().copied().filter(|l| l.name != "status").collect();
+    let rows = state.db.metric_last_pipelines(&columns(&group)).await?;
+
+    out.push_str("# TYPE gitlab_ci_pipeline_last_status gauge\n");
+    out.push_str("# HELP gitlab_ci_pipeline_last_status Status of the most recent pipeline (1 for the current status).\n");
+    for row in &rows {
+        for s in PIPELINE_STATUSES {
+            writeln!(out, "gitlab_ci_pipeline_last_status{} {}",
+                format_labels(&group, &row.labels, Some(("status", s))),
+                if *s == row.status { 1 } else { 0 })?;
+        }
+    }
+
+    out.push_str("# TYPE gitlab_ci_pipeline_last_run_timestamp_seconds gauge\n");
+    out.push_str("# HELP gitlab_ci_pipeline_last_run_timestamp_seconds Creation time of the most recent pipeline.\n");
+    for row in &rows {
+        writeln!(out, "gitlab_ci_pipeline_last_run_timestamp_seconds{} {}", format_labels(&group, &row.labels, None), row.created_at)?;
+    }
+    Ok(())
+}
+
+fn columns(labels: &[Label]) -> Vec<&'static str> {
+    labels.iter().map(|l| l.column).collect()
+}
+
+fn format_labels(labels: &[Label], values: &[String], extra: Option<(&str, &str)>) -> String {
+    let mut pairs: Vec<String> = labels.iter().zip(values)
+        .map(|(l, v)| format!("{}=\"{}\"", l.name, escape_label_value(v)))
+        .collect();
+    if let Some((name, value)) = extra {
+        pairs.push(format!("{}=\"{}\"", name, escape_label_value(value)));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape_label_value(v: &str) -> String {
+    v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
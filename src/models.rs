@@ -12,11 +12,136 @@ pub struct Pipeline {
     pub user_name: String,
     pub status: String,
     pub created_at: i64,
+    pub started_at: Option<i64>,
     pub finished_at: Option<i64>,
     pub duration: Option<i64>,
+    /// Seconds between creation and the start of the first job.
+    pub queued_duration: Option<i64>,
     pub web_url: Option<String>,
+    /// What triggered the pipeline: `push`, `schedule`, `merge_request_event`, `api`, `trigger`, ...
+    pub source: Option<String>,
+    /// IID of the merge request the pipeline ran for, within its project.
+    pub merge_request_iid: Option<i64>,
+    /// Test coverage percentage, when the project's jobs report one.
+    pub coverage: Option<f64>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Job {
+    pub id: i64,
+    pub pipeline_id: i64,
+    pub project_id: i64,
+    pub name: String,
+    pub stage: String,
+    pub status: String,
+    pub runner_id: Option<i64>,
+    pub runner_description: Option<String>,
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    pub duration: Option<f64>,
+    pub queued_duration: Option<f64>,
+    pub retried: bool,
+    pub allow_failure: bool,
+    pub web_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Environment {
+    pub id: i64,
+    pub project_id: i64,
+    pub name: String,
+    /// `production`, `staging`, `testing`, `development` or `other`.
+    pub tier: Option<String>,
+    pub state: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Deployment {
+    pub id: i64,
+    pub project_id: i64,
+    pub project_full_path: String,
+    pub environment_id: i64,
+    pub environment_name: String,
+    pub ref_name: String,
+    pub sha: String,
+    pub status: String,
+    pub created_at: i64,
+    pub finished_at: Option<i64>,
+    /// Creation time of the deployed commit, the start of its lead time.
+    pub commit_created_at: Option<i64>,
+    pub pipeline_id: Option<i64>,
+    /// Tier of the environment as last polled; not stored with the deployment.
+    #[sqlx(default)]
+    pub environment_tier: Option<String>,
+}
+
+/// A merge request pipelines ran for, as last reported by GitLab.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct MergeRequest {
+    pub project_id: i64,
+    pub iid: i64,
+    pub title: String,
+    /// `opened`, `merged`, `closed` or `locked`; unknown until GitLab reports it.
+    pub state: Option<String>,
+    pub source_branch: Option<String>,
+    pub target_branch: Option<String>,
+    pub web_url: Option<String>,
+    pub created_at: Option<i64>,
+    pub merged_at: Option<i64>,
+}
+
+/// One suite of a pipeline's test report, as GitLab summarizes it from the JUnit artifacts.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TestSuite {
+    pub pipeline_id: i64,
+    pub project_id: i64,
+    pub name: String,
+    /// Seconds spent running the suite.
+    pub total_time: f64,
+    pub total_count: i64,
+    pub success_count: i64,
+    pub failed_count: i64,
+    pub skipped_count: i64,
+    pub error_count: i64,
+}
+
+/// A monitored project as last discovered. `state` is `active`, `archived`, `deleted` or
+/// `unmonitored` (moved out of the monitored groups or excluded).
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Project {
+    pub id: i64,
+    pub name: String,
+    pub full_path: String,
+    pub default_branch: Option<String>,
+    /// `private`, `internal` or `public`.
+    pub visibility: Option<String>,
+    pub archived: bool,
+    /// Comma-separated, like runner tags.
+    pub topics: String,
+    pub state: String,
+    /// Path before the last rename.
+    pub previous_full_path: Option<String>,
+    pub first_seen_at: i64,
+    pub last_seen_at: i64,
+    pub state_changed_at: i64,
+}
+
+/// A runner of the monitored groups, as last polled.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Runner {
+    pub id: i64,
+    pub description: Option<String>,
+    /// `instance_type`, `group_type` or `project_type`.
+    pub runner_type: Option<String>,
+    /// `online`, `offline`, `stale` or `never_contacted`.
+    pub status: Option<String>,
+    pub paused: bool,
+    pub executor: Option<String>,
+    /// Comma-separated; GitLab tags cannot contain commas.
+    pub tags: String,
+    pub contacted_at: Option<i64>,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct DailyStat {
@@ -24,3 +149,462 @@ pub struct DailyStat {
     pub status: String,
     pub count: i64,
 }
+
+/// Time bucket of `/api/stats/trend`; `DailyStat::date` holds the bucket label
+/// (`2024-05-01 13:00`, `2024-05-01`, the Monday of the week, or `2024-05`).
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Hour,
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PipelineFilter {
+    pub instance: Option<String>,
+    pub project_name: Option<String>,
+    pub ref_name: Option<String>,
+    pub exclude_projects: Option<String>,
+    pub status: Option<String>,
+    pub source: Option<String>,
+    pub from_ts: Option<i64>,
+    pub to_ts: Option<i64>,
+}
+
+/// Sort key of `/api/pipelines`; ties are broken by pipeline id in the same direction.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineSort {
+    #[default]
+    CreatedAt,
+    FinishedAt,
+    Duration,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Keyset position after the last row of a page: its sort value and id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineCursor {
+    pub value: i64,
+    pub id: i64,
+}
+
+impl std::fmt::Display for PipelineCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.value, self.id)
+    }
+}
+
+impl std::str::FromStr for PipelineCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, id) = s.rsplit_once('_').ok_or_else(|| format!("invalid cursor {:?}", s))?;
+        Ok(PipelineCursor {
+            value: value.parse().map_err(|_| format!("invalid cursor {:?}", s))?,
+            id: id.parse().map_err(|_| format!("invalid cursor {:?}", s))?,
+        })
+    }
+}
+
+/// One page of `list_pipelines`.
+#[derive(Debug, Clone, Copy)]
+pub struct PipelinePage {
+    pub sort: PipelineSort,
+    pub order: SortOrder,
+    pub limit: i64,
+    pub after: Option<PipelineCursor>,
+}
+
+impl PipelinePage {
+    /// Stand-in for a missing `finished_at` / `duration`, so those pipelines sort last either way.
+    fn null_value(&self) -> i64 {
+        match self.order {
+            SortOrder::Asc => i64::MAX,
+            SortOrder::Desc => -1,
+        }
+    }
+
+    /// SQL expression the page is ordered by.
+    pub fn sort_expr(&self) -> String {
+        match self.sort {
+            PipelineSort::CreatedAt => "created_at".to_string(),
+            PipelineSort::FinishedAt => format!("COALESCE(finished_at, {})", self.null_value()),
+            PipelineSort::Duration => format!("COALESCE(duration, {})", self.null_value()),
+        }
+    }
+
+    /// Cursor pointing just past `p`.
+    pub fn cursor_after(&self, p: &Pipeline) -> PipelineCursor {
+        let value = match self.sort {
+            PipelineSort::CreatedAt => p.created_at,
+            PipelineSort::FinishedAt => p.finished_at.unwrap_or(self.null_value()),
+            PipelineSort::Duration => p.duration.unwrap_or(self.null_value()),
+        };
+        PipelineCursor { value, id: p.id }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct JobFilter {
+    pub instance: Option<String>,
+    pub project_name: Option<String>,
+    pub ref_name: Option<String>,
+    pub exclude_projects: Option<String>,
+    pub pipeline_id: Option<i64>,
+    pub name: Option<String>,
+    pub stage: Option<String>,
+    pub status: Option<String>,
+    pub from_ts: Option<i64>,
+    pub to_ts: Option<i64>,
+}
+
+/// Duration percentiles are filled in from `duration_buckets` after the query.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ProjectStat {
+    pub project_name: String,
+    pub project_full_path: String,
+    pub count: i64,
+    pub avg_duration: f64,
+    pub avg_queued_duration: f64,
+    pub last_status: String,
+    #[sqlx(default)]
+    pub p50_duration: f64,
+    #[sqlx(default)]
+    pub p90_duration: f64,
+    #[sqlx(default)]
+    pub p95_duration: f64,
+    #[sqlx(default)]
+    pub p99_duration: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SummaryStat {
+    pub total_count: i64,
+    pub avg_duration: f64,
+    pub avg_queued_duration: f64,
+    pub success_rate: f64,
+    #[sqlx(default)]
+    pub p50_duration: f64,
+    #[sqlx(default)]
+    pub p90_duration: f64,
+    #[sqlx(default)]
+    pub p95_duration: f64,
+    #[sqlx(default)]
+    pub p99_duration: f64,
+}
+
+/// Pipelines of one group whose duration falls in the bucket ending at `bucket` seconds.
+#[derive(Debug, Clone, FromRow)]
+pub struct DurationBucketCount {
+    pub key: String,
+    pub bucket: i64,
+    pub count: i64,
+}
+
+/// Estimated duration percentiles in seconds.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DurationPercentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+/// One day of `/api/stats/duration_trend`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DurationTrendPoint {
+    pub date: String,
+    pub count: i64,
+    pub p50_duration: f64,
+    pub p90_duration: f64,
+    pub p95_duration: f64,
+    pub p99_duration: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct JobRow {
+    pub id: i64,
+    pub pipeline_id: i64,
+    pub project_id: i64,
+    pub project_full_path: String,
+    pub ref_name: String,
+    pub name: String,
+    pub stage: String,
+    pub status: String,
+    pub runner_id: Option<i64>,
+    pub runner_description: Option<String>,
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    pub duration: Option<f64>,
+    pub queued_duration: Option<f64>,
+    pub retried: bool,
+    pub allow_failure: bool,
+    pub web_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct JobStat {
+    pub project_name: String,
+    pub stage: String,
+    pub name: String,
+    pub count: i64,
+    pub failed_count: i64,
+    pub retried_count: i64,
+    pub failure_rate: f64,
+    pub avg_duration: f64,
+    pub max_duration: f64,
+    pub avg_queued_duration: f64,
+}
+
+/// Flakiness of one project and ref: commits whose pipelines both failed and succeeded.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct FlakyStat {
+    pub project_id: i64,
+    /// Current path of the project.
+    pub project_name: String,
+    pub ref_name: String,
+    /// Commits with at least one failed or successful pipeline.
+    pub sha_count: i64,
+    pub flaky_sha_count: i64,
+    /// Failed pipelines of the flaky commits.
+    pub flaky_failed_count: i64,
+    /// `flaky_sha_count` as a percentage of `sha_count`.
+    pub flaky_rate: f64,
+    #[sqlx(skip)]
+    pub examples: Vec<FlakyExample>,
+}
+
+/// One flaky commit with its latest failed and latest successful pipeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlakyExample {
+    pub sha: String,
+    pub failed_web_url: Option<String>,
+    pub success_web_url: Option<String>,
+}
+
+/// How long the default branch of a project stayed red: each red interval runs from its
+/// first failed pipeline to the next successful one. Times are in seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryStat {
+    pub project_name: String,
+    pub default_branch: String,
+    /// Red intervals that ended within the window.
+    pub recovery_count: i64,
+    /// Mean length of those intervals.
+    pub mttr: Option<f64>,
+    /// Longest red interval, including one still open.
+    pub longest_outage: Option<i64>,
+    /// Start of the open red interval, when the branch is red now.
+    pub red_since: Option<i64>,
+    /// Length of the open red interval so far; 0 when green.
+    pub current_red_streak: i64,
+    /// Failed pipelines in the open red interval.
+    pub current_failed_count: i64,
+}
+
+/// CI spent on one merge request by its pipelines in the window. Times are in seconds.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct MergeRequestStat {
+    pub project_name: String,
+    pub iid: i64,
+    pub title: String,
+    pub state: String,
+    pub web_url: Option<String>,
+    pub merged_at: Option<i64>,
+    pub pipeline_count: i64,
+    /// Pipelines created up to the merge; all of them while the merge request is not merged.
+    pub runs_before_merge: i64,
+    pub failed_count: i64,
+    /// Sum of the pipeline durations.
+    pub total_duration: i64,
+    pub first_pipeline_at: i64,
+    /// From the first pipeline's creation to the first successful pipeline finishing.
+    pub time_to_green: Option<i64>,
+}
+
+/// Test counts of the pipelines created on one local date that have a test report.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TestTrendPoint {
+    pub date: String,
+    pub pipeline_count: i64,
+    pub total_count: i64,
+    pub success_count: i64,
+    pub failed_count: i64,
+    pub skipped_count: i64,
+    pub error_count: i64,
+}
+
+/// How one test suite of a project fared across the pipelines in the window.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TestSuiteStat {
+    pub project_name: String,
+    pub name: String,
+    /// Pipelines that reported the suite.
+    pub run_count: i64,
+    /// Runs with at least one failed or erroring test.
+    pub failed_run_count: i64,
+    /// `failed_run_count` as a percentage of `run_count`.
+    pub failure_rate: f64,
+    /// Failed tests summed over all runs.
+    pub failed_count: i64,
+    pub error_count: i64,
+    pub avg_total_count: f64,
+    /// Seconds per run.
+    pub avg_time: f64,
+    pub max_time: f64,
+}
+
+/// Coverage reported by the successful pipelines of one project and ref on one local date.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct CoverageTrendPoint {
+    pub date: String,
+    pub project_name: String,
+    pub ref_name: String,
+    pub pipeline_count: i64,
+    pub avg_coverage: f64,
+    pub min_coverage: f64,
+    pub max_coverage: f64,
+}
+
+/// Jobs one runner picked up in a time window. Times are in seconds.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct RunnerJobStat {
+    pub runner_id: i64,
+    /// As reported by the jobs; the only name known for runners that were never polled.
+    pub description: Option<String>,
+    pub job_count: i64,
+    pub failed_count: i64,
+    /// Sum of the job durations.
+    pub busy_time: f64,
+    pub total_queued_duration: f64,
+    /// Jobs with a known queue time.
+    pub queued_count: i64,
+    pub max_queued_duration: f64,
+}
+
+/// Load and health of one runner over a time window. Times are in seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunnerStat {
+    pub id: i64,
+    pub instance: Option<String>,
+    pub description: Option<String>,
+    pub runner_type: Option<String>,
+    pub status: Option<String>,
+    pub paused: bool,
+    pub executor: Option<String>,
+    pub tags: Vec<String>,
+    pub contacted_at: Option<i64>,
+    pub job_count: i64,
+    pub failed_count: i64,
+    /// `failed_count` as a percentage of `job_count`.
+    pub failure_rate: f64,
+    pub busy_time: f64,
+    /// `busy_time` as a percentage of the window; above 100 when the runner runs jobs concurrently.
+    pub utilization: f64,
+    pub avg_queued_duration: Option<f64>,
+    pub max_queued_duration: Option<f64>,
+}
+
+/// Queue pressure on the runners carrying one tag, from the jobs they picked up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunnerTagStat {
+    pub tag: String,
+    pub runner_count: i64,
+    /// Runners online and not paused.
+    pub available_count: i64,
+    pub job_count: i64,
+    pub busy_time: f64,
+    pub avg_queued_duration: Option<f64>,
+    pub max_queued_duration: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunnerReport {
+    pub from_ts: i64,
+    pub to_ts: i64,
+    pub runners: Vec<RunnerStat>,
+    pub tags: Vec<RunnerTagStat>,
+}
+
+/// The four DORA metrics of a project or group over a time window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DoraStat {
+    /// Project path, or group path for group totals.
+    pub name: String,
+    pub deployment_count: i64,
+    /// Successful deployments per day of the window.
+    pub deployment_frequency: f64,
+    /// Median seconds from commit creation to its successful deployment.
+    pub median_lead_time: Option<f64>,
+    pub failed_deployment_count: i64,
+    /// Failed deployments as a percentage of successful and failed ones.
+    pub change_failure_rate: f64,
+    /// Median seconds from a failed deployment to the next successful one in the same environment.
+    pub median_time_to_restore: Option<f64>,
+    pub restore_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DoraReport {
+    pub from_ts: i64,
+    pub to_ts: i64,
+    pub groups: Vec<DoraStat>,
+    pub projects: Vec<DoraStat>,
+}
+
+/// A pipeline count for one combination of metric label values.
+#[derive(Debug, Clone)]
+pub struct LabeledCount {
+    pub labels: Vec<String>,
+    pub count: i64,
+}
+
+/// Duration histogram for one combination of metric label values;
+/// `buckets[i]` counts durations at or below the i-th upper bound.
+#[derive(Debug, Clone)]
+pub struct LabeledHistogram {
+    pub labels: Vec<String>,
+    pub count: i64,
+    pub sum: i64,
+    pub buckets: Vec<i64>,
+}
+
+/// The most recent pipeline for one combination of metric label values.
+#[derive(Debug, Clone)]
+pub struct LabeledLastStatus {
+    pub labels: Vec<String>,
+    pub status: String,
+    pub created_at: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pipeline_cursor_round_trips() {
+        for cursor in [PipelineCursor { value: 1_714_564_800, id: 42 }, PipelineCursor { value: -1, id: 7 }, PipelineCursor { value: i64::MAX, id: 1 << 40 }] {
+            assert_eq!(cursor.to_string().parse::<PipelineCursor>(), Ok(cursor));
+        }
+        assert_eq!(PipelineCursor { value: -1, id: 7 }.to_string(), "-1_7");
+    }
+
+    #[test]
+    fn pipeline_cursor_rejects_malformed_input() {
+        for input in ["", "42", "_", "1_", "_1", "a_1", "1_b", "1.5_2", "1_2_3"] {
+            assert!(input.parse::<PipelineCursor>().is_err(), "{:?} parsed", input);
+        }
+    }
+}
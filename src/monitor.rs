@@ -1,17 +1,19 @@
+use crate::gitlab_graphql::PollTarget;
 use crate::gitlab_ops;
+use crate::gitlab_types::ProjectInfo;
+use crate::instance::{gitlab_id, Instance};
+use crate::retention;
 use crate::state::AppState;
 use chrono::Utc;
 use regex::Regex;
 use std::time::Duration as StdDuration;
 use tokio::time::sleep;
-use tracing::{error, info};
-use crate::db;
+use tracing::{error, info, warn};
 use chrono::TimeZone;
 
-pub async fn perform_initial_backfill(state: AppState) {
-    info!("Starting initial backfill via REST API...");
-    
-    let branch_filter = if let Some(re) = &state.config.gitlab.branch_filter_regex {
+/// Only sync pipelines whose ref matches the instance's `branch_filter_regex`.
+fn branch_filter(instance: &Instance) -> Option<Regex> {
+    if let Some(re) = &instance.config.branch_filter_regex {
         match Regex::new(re) {
             Ok(r) => Some(r),
             Err(e) => {
@@ -21,10 +23,37 @@ pub async fn perform_initial_backfill(state: AppState) {
         }
     } else {
         None
-    };
+    }
+}
+
+/// The projects an instance monitors: those of its groups and its listed projects, minus
+/// the excluded ones.
+pub async fn discover_instance_projects(instance: &Instance) -> anyhow::Result<Vec<ProjectInfo>> {
+    let config = &instance.config;
+    let mut projects = gitlab_ops::discover_projects(
+        &instance.gitlab_client,
+        &config.monitor_groups,
+        config.include_subgroups.unwrap_or(true),
+        None,
+    ).await?;
+    for path in &config.monitor_projects {
+        let project = gitlab_ops::find_project(&instance.gitlab_client, path.as_str()).await
+            .map_err(|e| anyhow::anyhow!("project {}: {}", path, e))?;
+        match project {
+            Some(project) if !project.archived && !projects.iter().any(|p| p.id == project.id) => projects.push(project),
+            Some(_) => {}
+            None => warn!("Monitored project {} of instance {} not found", path, instance.name),
+        }
+    }
+    projects.retain(|p| !instance.projects.is_excluded(&p.path_with_namespace));
+    Ok(projects)
+}
+
+pub async fn perform_initial_backfill(state: AppState, instance: &Instance) {
+    info!("Starting initial backfill of instance {}...", instance.name);
 
     info!("Discovering all projects for backfill...");
-    let projects = match gitlab_ops::discover_projects(&state.gitlab_client, &state.config.gitlab.monitor_groups, None).await {
+    let projects = match discover_instance_projects(instance).await {
         Ok(p) => p,
         Err(e) => {
             error!("Failed to discover projects: {}", e);
@@ -33,29 +62,42 @@ pub async fn perform_initial_backfill(state: AppState) {
     };
     info!("Discovered {} projects for backfill", projects.len());
 
-    // store monitored projects in state for API listing
-    {
-        let mut mp = state.monitored_projects.write().unwrap();
-        mp.clear();
-        for pr in &projects {
-            mp.push(pr.clone());
-        }
+    backfill_projects(&state, instance, &projects).await;
+    if let Err(e) = crate::projects::record_backfilled(&state, instance, &projects).await {
+        error!("Failed to store projects of instance {}: {}", instance.name, e);
+    }
+
+    // Merges usually happen after the last pipeline of a merge request
+    let since = backfill_start(&state);
+    for target in instance.projects.poll_targets() {
+        sync_merge_requests(&state, instance, target, since).await;
     }
+    
+    info!("Initial backfill complete.");
+}
 
+/// Start of the backfill window.
+pub fn backfill_start(state: &AppState) -> chrono::DateTime<Utc> {
     let backfill_cutoff = chrono::Utc::now().timestamp() - (state.config.poller.backfill_days * 86400);
-    let updated_after = Some(chrono::DateTime::from_timestamp(backfill_cutoff, 0).unwrap_or_default());
+    chrono::DateTime::from_timestamp(backfill_cutoff, 0).unwrap_or_default()
+}
+
+/// Store the pipelines of `projects` (with GitLab's own ids) from the backfill window.
+pub async fn backfill_projects(state: &AppState, instance: &Instance, projects: &[ProjectInfo]) {
+    let branch_filter = branch_filter(instance);
+    let since = backfill_start(state);
 
     // Concurrently fetch pipelines for projects in batches
     let concurrency: usize = 10;
     let mut id_to_project = std::collections::HashMap::new();
-    let mut project_ids = Vec::new();
+    let mut project_paths = Vec::new();
     for project in projects.iter() {
         id_to_project.insert(project.id, project.clone());
-        project_ids.push(project.id);
+        project_paths.push((project.id, project.path_with_namespace.clone()));
     }
 
-    info!("Fetching pipelines for {} projects concurrently (concurrency={})", project_ids.len(), concurrency);
-    match gitlab_ops::fetch_pipelines_concurrent(&state.gitlab_client, project_ids, updated_after, concurrency).await {
+    info!("Fetching pipelines for {} projects concurrently (concurrency={})", project_paths.len(), concurrency);
+    match gitlab_ops::fetch_pipelines_concurrent(&instance.graphql_client, project_paths, since, concurrency).await {
         Ok(results) => {
             for (pid, pipelines) in results {
                 let project = match id_to_project.get(&pid) {
@@ -63,19 +105,33 @@ pub async fn perform_initial_backfill(state: AppState) {
                     None => continue,
                 };
                 info!("Fetched {} pipelines for project {}", pipelines.len(), project.name);
-                for p in pipelines {
-                    if let Some(re) = &branch_filter {
-                        if !re.is_match(&p.r#ref) { continue; }
+
+                let mut new_finished = Vec::new();
+                let mut merge_requests = Vec::new();
+                for mut p in pipelines {
+                    if branch_filter.as_ref().is_some_and(|re| !re.is_match(&p.ref_name)) {
+                        continue;
+                    }
+                    let base = project.web_url.as_deref().unwrap_or("");
+                    p.web_url = Some(format!("{}/-/pipelines/{}", base, p.id));
+                    if let Some(mr) = &p.merge_request {
+                        merge_requests.push(instance.scope_merge_request(mr.to_db_merge_request()));
                     }
-                    let db_p = p.to_db_pipeline(project.id as i64, &project.name, &project.path_with_namespace);
-                    insert_pipeline(&state, db_p).await;
+                    let db_p = instance.scope_pipeline(p.to_db_pipeline(project.id as i64, &project.name, &project.path_with_namespace));
+                    // Pipelines stored before already had their test reports fetched by the monitor loop
+                    if is_finished_status(&db_p.status) && !state.db.pipeline_exists(db_p.id).await.unwrap_or(true) {
+                        new_finished.push((pid, p.id));
+                    }
+                    let _ = insert_pipeline(state, db_p).await;
+                }
+                let _ = insert_merge_requests(state, merge_requests).await;
+                if state.config.poller.fetch_test_reports.unwrap_or(true) && !new_finished.is_empty() {
+                    sync_test_reports(state, instance, new_finished).await;
                 }
             }
         }
         Err(e) => error!("Concurrent fetch_pipelines failed: {}", e),
     }
-    
-    info!("Initial backfill complete.");
 }
 
 pub async fn backfill_usernames(state: AppState) {
@@ -83,309 +139,276 @@ pub async fn backfill_usernames(state: AppState) {
 
     tracing::info!("Starting username backfill for pipelines with missing user_name");
 
-    // loop until no more missing user_name
-    loop {
-        // fetch a batch of pipeline ids with missing user_name and their project_id
-        let rows: Vec<(i64, i64)> = match sqlx::query_as("SELECT id, project_id FROM pipelines WHERE user_name IS NULL OR user_name = '' LIMIT 500")
-            .fetch_all(&state.db).await {
-            Ok(r) => r,
-            Err(e) => {
-                tracing::error!("Failed to query pipelines for username backfill: {}", e);
-                return;
+    for instance in state.instances.iter() {
+        // loop until no more missing user_name
+        loop {
+            // fetch a batch of pipeline ids with missing user_name and their project_id
+            let rows = match state.db.pipelines_missing_user(instance.id_range(), 500).await {
+                Ok(r) => r,
+                Err(e) => {
+                    tracing::error!("Failed to query pipelines for username backfill: {}", e);
+                    return;
+                }
+            };
+
+            if rows.is_empty() {
+                tracing::info!("No pipelines found with missing user_name; username backfill of instance {} complete", instance.name);
+                break;
             }
-        };
 
-        if rows.is_empty() {
-            tracing::info!("No pipelines found with missing user_name; username backfill complete");
-            break;
-        }
+            // process in chunks with limited concurrency
+            let ids: Vec<(i64,i64)> = rows;
+            let concurrency: usize = 10;
 
-        // process in chunks with limited concurrency
-        let ids: Vec<(i64,i64)> = rows.into_iter().map(|(id, pid)| (id, pid)).collect();
-        let concurrency: usize = 10;
-
-        for chunk in ids.chunks(50) {
-            let mut set: JoinSet<(i64, Option<String>)> = JoinSet::new();
-            for &(pid, proj_id) in chunk {
-                let gclient = state.graphql_client.clone();
-                set.spawn(async move {
-                    let gid = format!("gid://gitlab/Ci::Pipeline/{}", pid);
-                    // Try GraphQL first
-                    match gclient.fetch_pipeline_user_by_gid(&gid).await {
-                        Ok(Some(name)) => (pid, Some(name)),
-                        Ok(None) => {
-                            // GraphQL returned no user; try REST fallback
-                            match gclient.fetch_pipeline_user_via_rest(proj_id, pid).await {
-                                Ok(opt) => (pid, opt),
-                                Err(e) => {
-                                    tracing::error!("REST fetch for pipeline {} failed: {}", pid, e);
-                                    (pid, None)
+            for chunk in ids.chunks(50) {
+                let mut set: JoinSet<(i64, Option<String>)> = JoinSet::new();
+                for &(pid, proj_id) in chunk {
+                    let gclient = instance.graphql_client.clone();
+                    set.spawn(async move {
+                        let (gl_pid, gl_proj_id) = (gitlab_id(pid) as i64, gitlab_id(proj_id) as i64);
+                        let gid = format!("gid://gitlab/Ci::Pipeline/{}", gl_pid);
+                        // Try GraphQL first
+                        match gclient.fetch_pipeline_user_by_gid(&gid).await {
+                            Ok(Some(name)) => (pid, Some(name)),
+                            Ok(None) => {
+                                // GraphQL returned no user; try REST fallback
+                                match gclient.fetch_pipeline_user_via_rest(gl_proj_id, gl_pid).await {
+                                    Ok(opt) => (pid, opt),
+                                    Err(e) => {
+                                        tracing::error!("REST fetch for pipeline {} failed: {}", pid, e);
+                                        (pid, None)
+                                    }
                                 }
                             }
-                        }
-                        Err(_e) => {
-                            // GraphQL failed; try REST
-                            match gclient.fetch_pipeline_user_via_rest(proj_id, pid).await {
-                                Ok(opt) => (pid, opt),
-                                Err(e) => {
-                                    tracing::error!("Both GraphQL and REST fetch failed for pipeline {}: {}", pid, e);
-                                    (pid, None)
+                            Err(_e) => {
+                                // GraphQL failed; try REST
+                                match gclient.fetch_pipeline_user_via_rest(gl_proj_id, gl_pid).await {
+                                    Ok(opt) => (pid, opt),
+                                    Err(e) => {
+                                        tracing::error!("Both GraphQL and REST fetch failed for pipeline {}: {}", pid, e);
+                                        (pid, None)
+                                    }
                                 }
                             }
                         }
-                    }
-                });
+                    });
 
-                if set.len() >= concurrency { break; }
-            }
+                    if set.len() >= concurrency { break; }
+                }
 
-            while let Some(res) = set.join_next().await {
-                match res {
-                    Ok((pid, Some(name))) => {
-                        if let Err(e) = sqlx::query("UPDATE pipelines SET user_name = ? WHERE id = ? AND (user_name IS NULL OR user_name = '')")
-                            .bind(&name)
-                            .bind(pid)
-                            .execute(&state.db).await {
-                            tracing::error!("Failed to update user_name for pipeline {}: {}", pid, e);
-                        } else {
-                            tracing::info!("Backfilled pipeline {} -> user={} ", pid, name);
+                while let Some(res) = set.join_next().await {
+                    match res {
+                        Ok((pid, Some(name))) => {
+                            if let Err(e) = state.db.set_pipeline_user(pid, &name).await {
+                                tracing::error!("Failed to update user_name for pipeline {}: {}", pid, e);
+                            } else {
+                                tracing::info!("Backfilled pipeline {} -> user={} ", pid, name);
+                            }
                         }
+                        Ok((_pid, None)) => { /* nothing to update */ }
+                        Err(e) => { tracing::error!("Task join error during username backfill: {}", e); }
                     }
-                    Ok((_pid, None)) => { /* nothing to update */ }
-                    Err(e) => { tracing::error!("Task join error during username backfill: {}", e); }
                 }
-            }
 
-            // small sleep to avoid hammering the API
-            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                // small sleep to avoid hammering the API
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            }
         }
     }
 }
 
-pub async fn start_monitor_loop(state: AppState) {
-    let branch_filter = if let Some(re) = &state.config.gitlab.branch_filter_regex {
-        match Regex::new(re) {
-            Ok(r) => Some(r),
-            Err(e) => {
-                error!("Invalid branch filter regex: {}", e);
-                None
-            }
-        }
-    } else {
-        None
-    };
+/// Fetch jobs for finished pipelines inside the backfill window that have none stored yet.
+pub async fn backfill_jobs(state: AppState) {
+    if !state.config.poller.fetch_jobs.unwrap_or(true) {
+        return;
+    }
+    info!("Starting job backfill for finished pipelines without jobs");
 
-    loop {
-        let current_loop_start = Utc::now();
-        info!("Starting polling cycle at {}", current_loop_start);
-        for group_path in &state.config.gitlab.monitor_groups {
-            info!("Polling group: {}", group_path);
-            // per requirements: generate poll time, read last poll, write current poll time immediately,
-            // then use last poll as `updatedAfter` for GraphQL query to avoid gaps.
-            let poll_time = chrono::Utc::now();
-            let last_poll_ts = match db::get_last_poll(&state.db).await {
-                Ok(opt) => opt.unwrap_or(poll_time.timestamp()),
+    let cutoff = Utc::now().timestamp() - (state.config.poller.backfill_days * 86400);
+    for instance in state.instances.iter() {
+        // walk pipelines by id so pipelines that legitimately have no jobs are visited only once
+        let (mut last_id, end) = instance.id_range();
+        loop {
+            let rows = match state.db.pipelines_without_jobs(last_id, end, cutoff, 500).await {
+                Ok(r) => r,
                 Err(e) => {
-                    error!("Failed to read last poll watermark: {}", e);
-                    poll_time.timestamp()
+                    error!("Failed to query pipelines for job backfill: {}", e);
+                    return;
                 }
             };
 
-            let since_time = chrono::Utc.timestamp_opt(last_poll_ts, 0).single().unwrap_or(poll_time);
-            info!("Fetching activity since {}", since_time);
+            let Some(&(max_id, _)) = rows.last() else { break };
+            last_id = max_id;
 
-            match state.graphql_client.fetch_incremental_activity(group_path, since_time).await {
-                Ok(projects) => {
-                    // fetch succeeded — update watermark to current poll_time
-                    if let Err(e) = db::set_last_poll(&state.db, current_loop_start.timestamp()).await {
-                        error!("Failed to update poll watermark after successful fetch: {}", e);
-                    }
-                    for proj in projects {
-                        for pipeline in proj.pipelines {
-                            if let Some(re) = &branch_filter {
-                                if !re.is_match(&pipeline.ref_name) { continue; }
-                            }
-                            let db_p = pipeline.to_db_pipeline(proj.id as i64, &proj.name, &proj.full_path);
-                            insert_pipeline(&state, db_p).await;
-                            info!("Processed pipeline {} for project {}", pipeline.id, proj.name);
-                        }
-                    }
-                },
-                Err(e) => {
-                    error!("Failed to fetch activity for group {}: {}", group_path, e);
-                },
+            for chunk in rows.chunks(50) {
+                let pairs = chunk.iter().map(|&(id, pid)| (gitlab_id(pid), gitlab_id(id))).collect();
+                sync_jobs(&state, instance, pairs).await;
+                // small sleep to avoid hammering the API
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
             }
         }
+    }
+    info!("Job backfill complete");
+}
 
-        info!("Polling cycle complete. Next poll in {} seconds.", state.config.poller.interval_seconds);
-        
-        tokio::select! {
-            _ = sleep(StdDuration::from_secs(state.config.poller.interval_seconds)) => {}
-            _ = state.refresh_notify.notified() => {
-                info!("Received force refresh signal.");
+fn is_finished_status(status: &str) -> bool {
+    matches!(status, "success" | "failed" | "canceled" | "skipped")
+}
+
+/// Fetch and store jobs for the given `(project_id, pipeline_id)` pairs of GitLab ids.
+async fn sync_jobs(state: &AppState, instance: &Instance, pipelines: Vec<(u64, u64)>) {
+    let project_of: std::collections::HashMap<u64, u64> = pipelines.iter().map(|&(proj, pid)| (pid, proj)).collect();
+    match gitlab_ops::fetch_jobs_concurrent(&instance.gitlab_client, pipelines, 10).await {
+        Ok(results) => {
+            for (pipeline_id, jobs) in results {
+                let project_id = project_of.get(&pipeline_id).copied().unwrap_or(0);
+                let db_jobs = crate::gitlab_types::jobs_to_db(&jobs, pipeline_id as i64, project_id as i64);
+                let _ = insert_jobs(state, instance.scope_jobs(db_jobs)).await;
             }
         }
+        Err(e) => error!("Concurrent fetch_jobs failed: {}", e),
     }
 }
 
-async fn insert_pipeline(state: &AppState, p: crate::models::Pipeline) {
-    // Use a transaction to upsert pipeline and maintain daily_stats atomically
-    let mut tx = match state.db.begin().await {
-        Ok(t) => t,
-        Err(e) => { error!("Failed to begin transaction: {}", e); return; }
-    };
+/// Fetch and store the test report summaries of the given `(project_id, pipeline_id)` pairs of GitLab ids.
+async fn sync_test_reports(state: &AppState, instance: &Instance, pipelines: Vec<(u64, u64)>) {
+    let project_of: std::collections::HashMap<u64, u64> = pipelines.iter().map(|&(proj, pid)| (pid, proj)).collect();
+    match gitlab_ops::fetch_test_reports_concurrent(&instance.gitlab_client, pipelines, 10).await {
+        Ok(results) => {
+            for (pipeline_id, summary) in results {
+                let project_id = project_of.get(&pipeline_id).copied().unwrap_or(0);
+                let suites = summary.to_db_test_suites(instance.id(pipeline_id), instance.id(project_id));
+                if let Err(e) = state.db.replace_test_suites(instance.id(pipeline_id), &suites).await {
+                    error!("Failed to store test suites for pipeline {}: {}", pipeline_id, e);
+                }
+            }
+        }
+        Err(e) => error!("Concurrent fetch_test_reports failed: {}", e),
+    }
+}
 
-    // Fetch existing pipeline if any
-    let existing: Option<(String, Option<i64>, i64)> = match sqlx::query_as(
-        "SELECT status, duration, created_at FROM pipelines WHERE id = ?",
-    ).bind(p.id).fetch_optional(&mut *tx).await {
-        Ok(r) => r,
-        Err(e) => { error!("Failed to query existing pipeline {}: {}", p.id, e); let _ = tx.rollback().await; return; }
-    };
+/// Store jobs, logging and returning a storage failure.
+pub async fn insert_jobs(state: &AppState, jobs: Vec<crate::models::Job>) -> anyhow::Result<()> {
+    if jobs.is_empty() {
+        return Ok(());
+    }
+    state.db.upsert_jobs(&jobs).await
+        .inspect_err(|e| error!("Failed to store jobs for pipeline {}: {}", jobs[0].pipeline_id, e))
+}
 
-    // Upsert pipeline row
-    match sqlx::query(
-        r#"
-        INSERT INTO pipelines (id, project_id, project_name, project_full_path, ref_name, user_name, sha, status, created_at, finished_at, web_url, duration)
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-        ON CONFLICT(id) DO UPDATE SET
-            status = CASE
-                WHEN excluded.finished_at IS NULL AND pipelines.finished_at IS NOT NULL THEN pipelines.status
-                ELSE excluded.status
-            END,
-            finished_at = CASE
-                WHEN excluded.finished_at IS NOT NULL THEN excluded.finished_at
-                ELSE pipelines.finished_at
-            END,
-            sha = excluded.sha,
-            duration = CASE
-                WHEN excluded.duration IS NOT NULL THEN excluded.duration
-                ELSE pipelines.duration
-            END,
-            web_url = COALESCE(excluded.web_url, pipelines.web_url),
-            user_name = COALESCE(excluded.user_name, pipelines.user_name)
-        "#,
-    ).bind(p.id)
-    .bind(p.project_id)
-    .bind(&p.project_name)
-    .bind(&p.project_full_path)
-    .bind(&p.ref_name)
-    .bind(&p.user_name)
-    .bind(&p.sha)
-    .bind(&p.status)
-    .bind(p.created_at)
-    .bind(p.finished_at)
-    .bind(&p.web_url)
-    .bind(p.duration)
-    .execute(&mut *tx).await {
-        Err(e) => { error!("Failed to upsert pipeline {}: {}", p.id, e); let _ = tx.rollback().await; return; }
-        Ok(_) => {}
+/// Store the merge requests of a polled group or project updated since `since`.
+pub async fn sync_merge_requests(state: &AppState, instance: &Instance, target: PollTarget<'_>, since: chrono::DateTime<Utc>) {
+    match instance.graphql_client.fetch_merge_requests(target, since).await {
+        Ok(mrs) => {
+            // A group's merge requests include those of its excluded projects
+            let db_mrs = mrs.iter()
+                .filter(|m| m.project.as_ref().is_none_or(|p| !instance.projects.is_excluded(&p.full_path)))
+                .map(|m| instance.scope_merge_request(m.to_db_merge_request()))
+                .collect();
+            let _ = insert_merge_requests(state, db_mrs).await;
+        }
+        Err(e) => error!("Failed to fetch merge requests for {} {} of instance {}: {}", target.kind(), target.full_path(), instance.name, e),
     }
+}
 
-    // Prepare date string for aggregation (YYYY-MM-DD)
-    let p_date = chrono::Utc.timestamp_opt(p.created_at, 0).single()
-        .map(|dt| dt.format("%Y-%m-%d").to_string())
-        .unwrap_or_else(|| "1970-01-01".to_string());
-
-    // Helper: track duration presence
-    let new_has_dur = p.duration.is_some();
-    let new_dur = p.duration.unwrap_or(0);
-
-    if let Some((old_status, old_dur_opt, old_created_at)) = existing {
-        let old_has_dur = old_dur_opt.is_some();
-        let old_dur = old_dur_opt.unwrap_or(0);
-        let old_date = chrono::Utc.timestamp_opt(old_created_at, 0).single()
-            .map(|dt| dt.format("%Y-%m-%d").to_string())
-            .unwrap_or_else(|| p_date.clone());
-
-        if old_status == p.status {
-            // Same status: handle duration presence/changes
-            match (old_has_dur, new_has_dur) {
-                (true, true) => {
-                    // both have durations: adjust total_duration by delta
-                    let delta = new_dur - old_dur;
-                    if delta != 0 {
-                        if let Err(e) = sqlx::query("UPDATE daily_stats SET total_duration = total_duration + ? WHERE date = ? AND project_id = ? AND status = ?")
-                            .bind(delta)
-                            .bind(&p_date)
-                            .bind(p.project_id)
-                            .bind(&p.status)
-                            .execute(&mut *tx).await {
-                            error!("Failed to update daily_stats duration for pipeline {}: {}", p.id, e);
-                            let _ = tx.rollback().await; return;
-                        }
-                    }
-                }
-                (false, true) => {
-                    // previously no duration -> now has: increment count_with_duration and add duration
-                    if let Err(e) = sqlx::query("UPDATE daily_stats SET total_duration = total_duration + ?, count_with_duration = count_with_duration + 1 WHERE date = ? AND project_id = ? AND status = ?")
-                        .bind(new_dur)
-                        .bind(&p_date)
-                        .bind(p.project_id)
-                        .bind(&p.status)
-                        .execute(&mut *tx).await {
-                        error!("Failed to update daily_stats adding duration for pipeline {}: {}", p.id, e);
-                        let _ = tx.rollback().await; return;
-                    }
-                }
-                (true, false) => {
-                    // previously had duration -> now none: subtract old and decrement count_with_duration
-                    if let Err(e) = sqlx::query("UPDATE daily_stats SET total_duration = total_duration - ?, count_with_duration = count_with_duration - 1 WHERE date = ? AND project_id = ? AND status = ?")
-                        .bind(old_dur)
-                        .bind(&p_date)
-                        .bind(p.project_id)
-                        .bind(&p.status)
-                        .execute(&mut *tx).await {
-                        error!("Failed to update daily_stats removing duration for pipeline {}: {}", p.id, e);
-                        let _ = tx.rollback().await; return;
+/// Store merge requests, logging and returning a storage failure.
+pub async fn insert_merge_requests(state: &AppState, merge_requests: Vec<crate::models::MergeRequest>) -> anyhow::Result<()> {
+    if merge_requests.is_empty() {
+        return Ok(());
+    }
+    state.db.upsert_merge_requests(&merge_requests).await
+        .inspect_err(|e| error!("Failed to store {} merge requests: {}", merge_requests.len(), e))
+}
+
+pub async fn start_monitor_loop(state: AppState) {
+    let branch_filters: Vec<Option<Regex>> = state.instances.iter().map(branch_filter).collect();
+
+    loop {
+        let current_loop_start = Utc::now();
+        info!("Starting polling cycle at {}", current_loop_start);
+        for (instance, branch_filter) in state.instances.iter().zip(&branch_filters) {
+            for target in instance.projects.poll_targets() {
+                let path = target.full_path();
+                info!("Polling {} {} of instance {}", target.kind(), path, instance.name);
+                let key = instance.watermark_key(path);
+                // Each group and project keeps its own watermark, which only advances once its
+                // fetch succeeded, so a failing one retries its whole window next cycle.
+                let poll_time = chrono::Utc::now();
+                let last_poll_ts = match state.db.get_watermark(target.kind(), &key).await {
+                    Ok(opt) => opt.unwrap_or(poll_time.timestamp()),
+                    Err(e) => {
+                        error!("Failed to read poll watermark for {} {} of instance {}: {}", target.kind(), path, instance.name, e);
+                        continue;
                     }
+                };
+                // Never look further back than the backfill window after long outages
+                let oldest = poll_time.timestamp() - state.config.poller.backfill_days * 86400;
+                let last_poll_ts = last_poll_ts.max(oldest);
+
+                let since_time = chrono::Utc.timestamp_opt(last_poll_ts, 0).single().unwrap_or(poll_time);
+                info!("Fetching activity since {}", since_time);
+
+                match instance.graphql_client.fetch_incremental_activity(target, since_time).await {
+                    Ok(projects) => {
+                        let mut finished = Vec::new();
+                        let mut merge_requests = Vec::new();
+                        let mut stored = true;
+                        for proj in projects {
+                            if instance.projects.is_excluded(&proj.full_path) {
+                                continue;
+                            }
+                            for pipeline in proj.pipelines {
+                                if let Some(re) = branch_filter {
+                                    if !re.is_match(&pipeline.ref_name) { continue; }
+                                }
+                                if let Some(mr) = &pipeline.merge_request {
+                                    merge_requests.push(instance.scope_merge_request(mr.to_db_merge_request()));
+                                }
+                                let db_p = instance.scope_pipeline(pipeline.to_db_pipeline(proj.id as i64, &proj.name, &proj.full_path));
+                                if is_finished_status(&db_p.status) {
+                                    finished.push((proj.id, pipeline.id));
+                                }
+                                stored &= insert_pipeline(&state, db_p).await.is_ok();
+                                info!("Processed pipeline {} for project {}", pipeline.id, proj.name);
+                            }
+                        }
+                        if state.config.poller.fetch_jobs.unwrap_or(true) && !finished.is_empty() {
+                            sync_jobs(&state, instance, finished.clone()).await;
+                        }
+                        if state.config.poller.fetch_test_reports.unwrap_or(true) && !finished.is_empty() {
+                            sync_test_reports(&state, instance, finished).await;
+                        }
+                        stored &= insert_merge_requests(&state, merge_requests).await.is_ok();
+                        // Merges usually happen after the last pipeline of a merge request
+                        sync_merge_requests(&state, instance, target, since_time).await;
+                        if !stored {
+                            warn!("Keeping the poll watermark of {} {} of instance {} to retry its unstored pipelines", target.kind(), path, instance.name);
+                        } else if let Err(e) = state.db.set_watermark(target.kind(), &key, poll_time.timestamp()).await {
+                            error!("Failed to update poll watermark for {} {} of instance {}: {}", target.kind(), path, instance.name, e);
+                        }
+                    },
+                    Err(e) => {
+                        error!("Failed to fetch activity for {} {} of instance {}: {}", target.kind(), path, instance.name, e);
+                    },
                 }
-                (false, false) => { /* nothing to do */ }
-            }
-        } else {
-            // Status changed: decrement old status row, adjust its duration/count_with_duration
-            if let Err(e) = sqlx::query("UPDATE daily_stats SET count = count - 1, total_duration = total_duration - ?, count_with_duration = count_with_duration - ? WHERE date = ? AND project_id = ? AND status = ?")
-                .bind(old_dur)
-                .bind(if old_has_dur { 1 } else { 0 })
-                .bind(&old_date)
-                .bind(p.project_id)
-                .bind(&old_status)
-                .execute(&mut *tx).await {
-                error!("Failed to decrement old daily_stats for pipeline {}: {}", p.id, e);
-                let _ = tx.rollback().await; return;
             }
 
-            // increment new status (insert or update) with duration info
-            if let Err(e) = sqlx::query("INSERT INTO daily_stats(date, project_id, project_name, project_full_path, status, count, total_duration, count_with_duration) VALUES (?, ?, ?, ?, ?, 1, ?, ?) ON CONFLICT(date, project_id, status) DO UPDATE SET count = daily_stats.count + 1, total_duration = daily_stats.total_duration + excluded.total_duration, count_with_duration = daily_stats.count_with_duration + excluded.count_with_duration, project_full_path = excluded.project_full_path")
-                .bind(&p_date)
-                .bind(p.project_id)
-                .bind(&p.project_name)
-                .bind(&p.project_full_path)
-                .bind(&p.status)
-                .bind(new_dur)
-                .bind(if new_has_dur { 1 } else { 0 })
-                .execute(&mut *tx).await {
-                error!("Failed to increment new daily_stats for pipeline {}: {}", p.id, e);
-                let _ = tx.rollback().await; return;
-            }
         }
-    } else {
-        // New pipeline: increment count and total_duration/count_with_duration for its date/status
-        if let Err(e) = sqlx::query("INSERT INTO daily_stats(date, project_id, project_name,project_full_path, status, count, total_duration, count_with_duration) VALUES (?, ?, ?, ?, ?, 1, ?, ?) ON CONFLICT(date, project_id, status) DO UPDATE SET count = daily_stats.count + 1, total_duration = daily_stats.total_duration + excluded.total_duration, count_with_duration = daily_stats.count_with_duration + excluded.count_with_duration, project_full_path = excluded.project_full_path")
-            .bind(&p_date)
-            .bind(p.project_id)
-            .bind(&p.project_name)
-            .bind(&p.project_full_path)
-            .bind(&p.status)
-            .bind(new_dur)
-            .bind(if new_has_dur { 1 } else { 0 })
-            .execute(&mut *tx).await {
-            error!("Failed to insert daily_stats for new pipeline {}: {}", p.id, e);
-            let _ = tx.rollback().await; return;
+        let interval = state.config.poll_interval_seconds();
+        info!("Polling cycle complete. Next poll in {} seconds.", interval);
+        
+        tokio::select! {
+            _ = sleep(StdDuration::from_secs(interval)) => {}
+            _ = state.refresh_notify.notified() => {
+                info!("Received force refresh signal.");
+            }
         }
     }
+}
 
-    if let Err(e) = tx.commit().await {
-        error!("Failed to commit pipeline insert transaction for {}: {}", p.id, e);
-    }
-}
\ No newline at end of file
+/// Store a pipeline, logging and returning a storage failure.
+pub async fn insert_pipeline(state: &AppState, p: crate::models::Pipeline) -> anyhow::Result<()> {
+    // The storage upserts the pipeline and maintains daily_stats atomically
+    state.db.upsert_pipeline(&p, retention::pipelines_floor(&state.config)).await
+        .inspect_err(|e| error!("Failed to store pipeline {}: {}", p.id, e))
+}
@@ -0,0 +1,170 @@
+use crate::gitlab_graphql::PollTarget;
+use crate::gitlab_ops;
+use crate::gitlab_types::ProjectInfo;
+use crate::instance::{gitlab_id, Instance};
+use crate::models::Project;
+use crate::monitor;
+use crate::state::AppState;
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration as StdDuration;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+pub const ACTIVE: &str = "active";
+pub const ARCHIVED: &str = "archived";
+pub const DELETED: &str = "deleted";
+/// Still on GitLab but moved out of the monitored groups or excluded.
+pub const UNMONITORED: &str = "unmonitored";
+
+/// Periodically re-discover the monitored projects.
+pub async fn start_discovery_loop(state: AppState) {
+    let interval = state.config.poller.discovery_interval_seconds.unwrap_or(3600);
+
+    loop {
+        sync_projects(&state).await;
+        sleep(StdDuration::from_secs(interval)).await;
+    }
+}
+
+/// Refresh `monitored_projects` and the stored project lifecycle of every instance. An
+/// instance whose discovery fails keeps the projects it had.
+async fn sync_projects(state: &AppState) {
+    let previous = state.monitored_projects.read().unwrap().clone();
+    let mut monitored = Vec::new();
+    for instance in state.instances.iter() {
+        match monitor::discover_instance_projects(instance).await {
+            Ok(found) => {
+                if let Err(e) = sync_instance(state, instance, &found).await {
+                    error!("Failed to store projects of instance {}: {}", instance.name, e);
+                }
+                monitored.extend(found.into_iter().map(|mut p| {
+                    p.id = instance.id(p.id) as u64;
+                    p
+                }));
+            }
+            Err(e) => {
+                error!("Failed to discover projects of instance {}: {}", instance.name, e);
+                let (first, end) = instance.id_range();
+                monitored.extend(previous.iter().filter(|p| (first..end).contains(&(p.id as i64))).cloned());
+            }
+        }
+    }
+    info!("Monitoring {} projects", monitored.len());
+    *state.monitored_projects.write().unwrap() = monitored;
+}
+
+/// Diff the discovered projects (with GitLab's own ids) against the stored ones: record
+/// renames, mark projects that disappeared and backfill the ones not monitored before.
+async fn sync_instance(state: &AppState, instance: &Instance, found: &[ProjectInfo]) -> anyhow::Result<()> {
+    let now = Utc::now().timestamp();
+    let stored: HashMap<i64, Project> = state.db.list_projects(instance.id_range()).await?
+        .into_iter()
+        .map(|p| (p.id, p))
+        .collect();
+
+    let mut rows = Vec::new();
+    let mut added = Vec::new();
+    for p in found {
+        let id = instance.id(p.id);
+        let mut row = match stored.get(&id) {
+            Some(old) => {
+                let mut row = old.clone();
+                if old.state != ACTIVE {
+                    info!("Project {} of instance {} is monitored again (was {})", p.path_with_namespace, instance.name, old.state);
+                    row.state = ACTIVE.to_string();
+                    row.state_changed_at = now;
+                    added.push(p.clone());
+                }
+                row
+            }
+            None => {
+                // Projects monitored before the projects table existed already have their
+                // pipelines from the initial backfill or the polling so far
+                if !state.db.project_has_pipelines(id).await? {
+                    info!("Discovered project {} of instance {}", p.path_with_namespace, instance.name);
+                    added.push(p.clone());
+                }
+                new_row(p, instance, now)
+            }
+        };
+        refresh(&mut row, p, instance);
+        row.last_seen_at = now;
+        rows.push(row);
+    }
+
+    let found_ids: HashSet<i64> = found.iter().map(|p| instance.id(p.id)).collect();
+    for old in stored.into_values().filter(|p| p.state == ACTIVE && !found_ids.contains(&p.id)) {
+        let mut row = old.clone();
+        match gitlab_ops::find_project(&instance.gitlab_client, gitlab_id(old.id)).await {
+            Ok(None) => row.state = DELETED.to_string(),
+            Ok(Some(p)) => {
+                row.state = if p.archived { ARCHIVED } else { UNMONITORED }.to_string();
+                refresh(&mut row, &p, instance);
+            }
+            Err(e) => {
+                warn!("Failed to look up vanished project {} of instance {}: {}", old.full_path, instance.name, e);
+                continue;
+            }
+        }
+        info!("Project {} of instance {} is {}", row.full_path, instance.name, row.state);
+        row.state_changed_at = now;
+        rows.push(row);
+    }
+    state.db.upsert_projects(&rows).await?;
+
+    if !added.is_empty() {
+        info!("Backfilling {} newly monitored projects of instance {}", added.len(), instance.name);
+        monitor::backfill_projects(state, instance, &added).await;
+        let since = monitor::backfill_start(state);
+        for p in &added {
+            monitor::sync_merge_requests(state, instance, PollTarget::Project(&p.path_with_namespace), since).await;
+        }
+    }
+    Ok(())
+}
+
+/// Record the projects the initial backfill of `instance` covered, so that the first
+/// discovery does not backfill them again.
+pub async fn record_backfilled(state: &AppState, instance: &Instance, projects: &[ProjectInfo]) -> anyhow::Result<()> {
+    let now = Utc::now().timestamp();
+    let rows: Vec<Project> = projects.iter()
+        .map(|p| {
+            let mut row = new_row(p, instance, now);
+            refresh(&mut row, p, instance);
+            row
+        })
+        .collect();
+    state.db.upsert_projects(&rows).await
+}
+
+/// An active project first seen at `now`.
+fn new_row(p: &ProjectInfo, instance: &Instance, now: i64) -> Project {
+    Project {
+        id: instance.id(p.id),
+        name: p.name.clone(),
+        full_path: p.path_with_namespace.clone(),
+        default_branch: None,
+        visibility: None,
+        archived: false,
+        topics: String::new(),
+        state: ACTIVE.to_string(),
+        previous_full_path: None,
+        first_seen_at: now,
+        last_seen_at: now,
+        state_changed_at: now,
+    }
+}
+
+/// Take over the current name, path and metadata, remembering the old path on a rename.
+fn refresh(row: &mut Project, p: &ProjectInfo, instance: &Instance) {
+    if row.full_path != p.path_with_namespace {
+        info!("Project {} of instance {} was renamed to {}", row.full_path, instance.name, p.path_with_namespace);
+        row.previous_full_path = Some(std::mem::replace(&mut row.full_path, p.path_with_namespace.clone()));
+    }
+    row.name = p.name.clone();
+    row.default_branch = p.default_branch.clone();
+    row.visibility = p.visibility.clone();
+    row.archived = p.archived;
+    row.topics = p.topics.join(",");
+}
@@ -0,0 +1,108 @@
+use crate::config::Config;
+use crate::db::{local_date, local_hour, local_midnight};
+use crate::state::AppState;
+use chrono::Utc;
+use chrono_tz::Tz;
+use std::future::Future;
+use std::time::Duration as StdDuration;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+
+/// Pause between delete batches so poller and webhook writes get the database in between.
+const BATCH_PAUSE: StdDuration = StdDuration::from_millis(200);
+
+/// Start of the day, in the reporting timezone, `days` days before `now`. Pruning whole days
+/// keeps every day still present in `pipelines` complete, so rebuilding its rollup stays exact.
+fn day_cutoff(now: i64, days: i64, tz: Tz) -> i64 {
+    local_midnight(now - days * 86400, tz)
+}
+
+/// Oldest `created_at` still guaranteed to have its raw pipelines; 0 without retention.
+pub fn pipelines_floor(config: &Config) -> i64 {
+    pipelines_floor_in(config, config.timezone())
+}
+
+/// `pipelines_floor` of pipelines pruned by whole days of `tz`.
+pub fn pipelines_floor_in(config: &Config, tz: Tz) -> i64 {
+    config.pipeline_retention_days()
+        .map(|days| day_cutoff(Utc::now().timestamp(), days, tz))
+        .unwrap_or(0)
+}
+
+/// Periodically delete pipelines and rollup rows older than the configured TTLs.
+pub async fn start_retention_loop(state: AppState) {
+    let pipelines_days = state.config.pipeline_retention_days();
+    let daily_stats_days = state.config.daily_stats_retention_days();
+    if pipelines_days.is_none() && daily_stats_days.is_none() {
+        return;
+    }
+
+    if let (Some(configured), Some(effective)) = (state.config.retention.pipelines_days, pipelines_days) {
+        if configured != effective {
+            warn!("retention.pipelines_days = {} is shorter than poller.backfill_days; keeping {} days", configured, effective);
+        }
+    }
+    if let (Some(configured), Some(effective)) = (state.config.retention.daily_stats_days, daily_stats_days) {
+        if configured != effective {
+            warn!("retention.daily_stats_days = {} is shorter than the pipeline retention; keeping {} days", configured, effective);
+        }
+    }
+
+    let interval = state.config.retention.interval_seconds.unwrap_or(3600);
+    let batch_size = state.config.retention.batch_size.unwrap_or(1000).max(1);
+    let tz = state.config.timezone();
+
+    loop {
+        let now = Utc::now().timestamp();
+        if let Some(days) = pipelines_days {
+            // hourly_stats only serve intra-day charts and share the raw pipeline TTL
+            let before = day_cutoff(now, days, tz);
+            let n = prune_in_batches("pipelines", batch_size, || state.db.prune_pipelines(before, batch_size)).await;
+            if n > 0 {
+                info!("Pruned {} pipelines created before {}", n, local_date(before, tz));
+            }
+            let hour = local_hour(before, tz);
+            let n = prune_in_batches("hourly_stats", batch_size, || state.db.prune_hourly_stats(&hour, batch_size)).await;
+            if n > 0 {
+                info!("Pruned {} hourly_stats rows before {}", n, hour);
+            }
+        }
+        if let Some(days) = daily_stats_days {
+            let date = local_date(day_cutoff(now, days, tz), tz);
+            let n = prune_in_batches("daily_stats", batch_size, || state.db.prune_daily_stats(&date, batch_size)).await;
+            if n > 0 {
+                info!("Pruned {} daily_stats rows dated before {}", n, date);
+            }
+            let n = prune_in_batches("daily_duration_buckets", batch_size, || state.db.prune_duration_buckets(&date, batch_size)).await;
+            if n > 0 {
+                info!("Pruned {} daily_duration_buckets rows dated before {}", n, date);
+            }
+        }
+        sleep(StdDuration::from_secs(interval)).await;
+    }
+}
+
+/// Call `prune` until it deletes less than a full batch; returns the rows deleted.
+async fn prune_in_batches<F, Fut>(what: &str, batch_size: i64, prune: F) -> u64
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = anyhow::Result<u64>>,
+{
+    let mut total = 0;
+    loop {
+        match prune().await {
+            Ok(n) => {
+                total += n;
+                if n < batch_size as u64 {
+                    break;
+                }
+            }
+            Err(e) => {
+                error!("Failed to prune {}: {}", what, e);
+                break;
+            }
+        }
+        sleep(BATCH_PAUSE).await;
+    }
+    total
+}
@@ -0,0 +1,164 @@
+use crate::instance::{instance_of, Instance};
+use crate::models::{Runner, RunnerJobStat, RunnerReport, RunnerStat, RunnerTagStat};
+use crate::state::AppState;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration as StdDuration;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+/// Periodically store the runners of every monitored group.
+pub async fn start_runner_loop(state: AppState) {
+    let Some(runners) = &state.config.runners else { return };
+    let interval = runners.interval_seconds.unwrap_or(300);
+
+    loop {
+        sync_runners(&state).await;
+        sleep(StdDuration::from_secs(interval)).await;
+    }
+}
+
+/// Replace the stored runners once every group of every instance was fetched, so a failing
+/// group does not make its runners disappear until the next poll.
+async fn sync_runners(state: &AppState) {
+    let mut by_id: BTreeMap<i64, Runner> = BTreeMap::new();
+    for instance in state.instances.iter() {
+        for group_path in &instance.config.monitor_groups {
+            match instance.graphql_client.fetch_runners(group_path).await {
+                Ok(runners) => {
+                    for r in runners {
+                        let r = instance.scope_runner(r.to_db_runner());
+                        by_id.insert(r.id, r);
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to fetch runners for group {} of instance {}: {}", group_path, instance.name, e);
+                    return;
+                }
+            }
+        }
+    }
+
+    let runners: Vec<Runner> = by_id.into_values().collect();
+    info!("Polled {} runners", runners.len());
+    if let Err(e) = state.db.replace_runners(&runners).await {
+        error!("Failed to store runners: {}", e);
+    }
+}
+
+/// Load of every runner and queue pressure per runner tag over the window from `from_ts`
+/// to `to_ts`. Runners that picked up jobs but were never polled are listed by what their
+/// jobs reported.
+pub fn report(instances: &[Instance], runners: Vec<Runner>, job_stats: Vec<RunnerJobStat>, from_ts: i64, to_ts: i64) -> RunnerReport {
+    let window = (to_ts - from_ts).max(1) as f64;
+    let mut jobs: HashMap<i64, RunnerJobStat> = job_stats.into_iter().map(|s| (s.runner_id, s)).collect();
+
+    let mut pairs: Vec<(Runner, Option<RunnerJobStat>)> = runners.into_iter()
+        .map(|r| {
+            let j = jobs.remove(&r.id);
+            (r, j)
+        })
+        .collect();
+    pairs.extend(jobs.into_values().map(|j| {
+        let r = Runner {
+            id: j.runner_id,
+            description: j.description.clone(),
+            runner_type: None,
+            status: None,
+            paused: false,
+            executor: None,
+            tags: String::new(),
+            contacted_at: None,
+        };
+        (r, Some(j))
+    }));
+
+    let mut tags: BTreeMap<String, TagTotals> = BTreeMap::new();
+    for (r, j) in &pairs {
+        for tag in tag_list(&r.tags) {
+            let t = tags.entry(tag).or_default();
+            t.runners += 1;
+            if r.status.as_deref() == Some("online") && !r.paused {
+                t.available += 1;
+            }
+            if let Some(j) = j {
+                t.add(j);
+            }
+        }
+    }
+
+    let mut runners: Vec<RunnerStat> = pairs.into_iter()
+        .map(|(r, j)| {
+            let instance = instance_of(instances, r.id).map(|i| i.name.clone());
+            runner_stat(r, instance, j.as_ref(), window)
+        })
+        .collect();
+    runners.sort_by(|a, b| b.busy_time.total_cmp(&a.busy_time).then(a.id.cmp(&b.id)));
+
+    let mut tags: Vec<RunnerTagStat> = tags.into_iter().map(|(tag, t)| t.into_stat(tag)).collect();
+    tags.sort_by(|a, b| b.avg_queued_duration.unwrap_or(0.0).total_cmp(&a.avg_queued_duration.unwrap_or(0.0)));
+
+    RunnerReport { from_ts, to_ts, runners, tags }
+}
+
+fn tag_list(tags: &str) -> Vec<String> {
+    tags.split(',').map(str::trim).filter(|t| !t.is_empty()).map(str::to_string).collect()
+}
+
+fn runner_stat(r: Runner, instance: Option<String>, jobs: Option<&RunnerJobStat>, window: f64) -> RunnerStat {
+    let job_count = jobs.map_or(0, |j| j.job_count);
+    let failed_count = jobs.map_or(0, |j| j.failed_count);
+    let busy_time = jobs.map_or(0.0, |j| j.busy_time);
+    let queued = jobs.filter(|j| j.queued_count > 0);
+    RunnerStat {
+        id: r.id,
+        instance,
+        tags: tag_list(&r.tags),
+        description: r.description,
+        runner_type: r.runner_type,
+        status: r.status,
+        paused: r.paused,
+        executor: r.executor,
+        contacted_at: r.contacted_at,
+        job_count,
+        failed_count,
+        failure_rate: if job_count > 0 { failed_count as f64 * 100.0 / job_count as f64 } else { 0.0 },
+        busy_time,
+        utilization: busy_time * 100.0 / window,
+        avg_queued_duration: queued.map(|j| j.total_queued_duration / j.queued_count as f64),
+        max_queued_duration: queued.map(|j| j.max_queued_duration),
+    }
+}
+
+#[derive(Default)]
+struct TagTotals {
+    runners: i64,
+    available: i64,
+    jobs: i64,
+    busy_time: f64,
+    queued_duration: f64,
+    queued_count: i64,
+    max_queued_duration: f64,
+}
+
+impl TagTotals {
+    fn add(&mut self, j: &RunnerJobStat) {
+        self.jobs += j.job_count;
+        self.busy_time += j.busy_time;
+        self.queued_duration += j.total_queued_duration;
+        self.queued_count += j.queued_count;
+        self.max_queued_duration = self.max_queued_duration.max(j.max_queued_duration);
+    }
+
+    fn into_stat(self, tag: String) -> RunnerTagStat {
+        let queued = self.queued_count > 0;
+        RunnerTagStat {
+            tag,
+            runner_count: self.runners,
+            available_count: self.available,
+            job_count: self.jobs,
+            busy_time: self.busy_time,
+            avg_queued_duration: queued.then(|| self.queued_duration / self.queued_count as f64),
+            max_queued_duration: queued.then_some(self.max_queued_duration),
+        }
+    }
+}
@@ -1,8 +1,7 @@
 use crate::config::Config;
+use crate::db::Storage;
 use crate::gitlab_types::ProjectInfo;
-use crate::gitlab_graphql::GitlabGraphqlClient;
-use gitlab::AsyncGitlab;
-use sqlx::SqlitePool;
+use crate::instance::Instance;
 use moka::future::Cache;
 use serde_json::Value as JsonValue;
 use std::sync::{Arc, RwLock};
@@ -10,9 +9,8 @@ use tokio::sync::Notify;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db: SqlitePool,
-    pub gitlab_client: Arc<AsyncGitlab>,
-    pub graphql_client: Arc<GitlabGraphqlClient>,
+    pub db: Arc<dyn Storage>,
+    pub instances: Arc<Vec<Instance>>,
     pub config: Arc<Config>,
     pub monitored_projects: Arc<RwLock<Vec<ProjectInfo>>>,
     pub refresh_notify: Arc<Notify>,
@@ -20,3 +18,10 @@ pub struct AppState {
     pub is_fresh_install: bool,
     pub cache: Cache<String, JsonValue>,
 }
+
+impl AppState {
+    /// Name of the instance a stored id belongs to.
+    pub fn instance_name(&self, id: i64) -> Option<String> {
+        crate::instance::instance_of(&self.instances, id).map(|i| i.name.clone())
+    }
+}
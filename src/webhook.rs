@@ -0,0 +1,151 @@
+use crate::gitlab_types::{JobHook, MergeRequestHook, PipelineHook};
+use crate::instance::Instance;
+use crate::monitor;
+use crate::state::AppState;
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use regex::Regex;
+use tracing::{error, info, warn};
+
+/// Receive GitLab Pipeline Hook, Job Hook and Merge Request Hook events of the instance
+/// with namespace 0, whose ids are GitLab's own, and store them like polled data.
+pub async fn receive_gitlab_hook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, Json<&'static str>) {
+    let Some(instance) = state.instances.iter().find(|i| i.namespace == 0).cloned() else {
+        return (StatusCode::NOT_FOUND, Json("no instance with namespace 0; use /webhooks/gitlab/<name>"));
+    };
+    receive(&state, &instance, headers, body).await
+}
+
+/// Receive the webhook events of the instance named in the path.
+pub async fn receive_instance_hook(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, Json<&'static str>) {
+    let Some(instance) = state.instances.iter().find(|i| i.name == name).cloned() else {
+        return (StatusCode::NOT_FOUND, Json("unknown instance"));
+    };
+    receive(&state, &instance, headers, body).await
+}
+
+async fn receive(state: &AppState, instance: &Instance, headers: HeaderMap, body: Bytes) -> (StatusCode, Json<&'static str>) {
+    let Some(secret) = state.config.webhook_secret(&instance.config) else {
+        return (StatusCode::NOT_FOUND, Json("webhook receiver disabled"));
+    };
+
+    let token = headers.get("X-Gitlab-Token").and_then(|v| v.to_str().ok()).unwrap_or("");
+    if !constant_time_eq(token.as_bytes(), secret.as_bytes()) {
+        warn!("Rejected GitLab webhook for instance {} with invalid X-Gitlab-Token", instance.name);
+        return (StatusCode::UNAUTHORIZED, Json("invalid token"));
+    }
+
+    let payload: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Failed to parse GitLab webhook body: {}", e);
+            return (StatusCode::BAD_REQUEST, Json("invalid payload"));
+        }
+    };
+
+    match payload.get("object_kind").and_then(|k| k.as_str()) {
+        Some("pipeline") => match serde_json::from_value::<PipelineHook>(payload) {
+            Ok(hook) => handle_pipeline_hook(state, instance, hook).await,
+            Err(e) => {
+                warn!("Failed to decode Pipeline Hook: {}", e);
+                (StatusCode::BAD_REQUEST, Json("invalid pipeline payload"))
+            }
+        },
+        Some("build") => match serde_json::from_value::<JobHook>(payload) {
+            Ok(hook) => handle_job_hook(state, instance, hook).await,
+            Err(e) => {
+                warn!("Failed to decode Job Hook: {}", e);
+                (StatusCode::BAD_REQUEST, Json("invalid job payload"))
+            }
+        },
+        Some("merge_request") => match serde_json::from_value::<MergeRequestHook>(payload) {
+            Ok(hook) => handle_merge_request_hook(state, instance, hook).await,
+            Err(e) => {
+                warn!("Failed to decode Merge Request Hook: {}", e);
+                (StatusCode::BAD_REQUEST, Json("invalid merge request payload"))
+            }
+        },
+        _ => (StatusCode::OK, Json("ignored event")),
+    }
+}
+
+async fn handle_pipeline_hook(state: &AppState, instance: &Instance, hook: PipelineHook) -> (StatusCode, Json<&'static str>) {
+    let path = &hook.project.path_with_namespace;
+    if !instance.projects.is_monitored(path) {
+        return (StatusCode::OK, Json("project not monitored"));
+    }
+
+    if let Some(re) = &instance.config.branch_filter_regex {
+        match Regex::new(re) {
+            Ok(r) if !r.is_match(&hook.object_attributes.ref_name) => {
+                return (StatusCode::OK, Json("ref filtered"));
+            }
+            Ok(_) => {}
+            Err(e) => error!("Invalid branch filter regex: {}", e),
+        }
+    }
+
+    let db_p = instance.scope_pipeline(hook.to_db_pipeline());
+    info!("Webhook: pipeline {} for project {} of instance {} is {}", hook.object_attributes.id, path, instance.name, db_p.status);
+    let stored: anyhow::Result<()> = async {
+        monitor::insert_pipeline(state, db_p).await?;
+        monitor::insert_jobs(state, instance.scope_jobs(hook.to_db_jobs())).await?;
+        let merge_requests = hook.to_db_merge_request().into_iter().map(|mr| instance.scope_merge_request(mr)).collect();
+        monitor::insert_merge_requests(state, merge_requests).await
+    }.await;
+    stored_response(stored)
+}
+
+async fn handle_merge_request_hook(state: &AppState, instance: &Instance, hook: MergeRequestHook) -> (StatusCode, Json<&'static str>) {
+    if !instance.projects.is_monitored(&hook.project.path_with_namespace) {
+        return (StatusCode::OK, Json("project not monitored"));
+    }
+
+    let mr = instance.scope_merge_request(hook.to_db_merge_request());
+    info!("Webhook: merge request !{} of project {} is {}", mr.iid, hook.project.path_with_namespace, mr.state.as_deref().unwrap_or("unknown"));
+    stored_response(monitor::insert_merge_requests(state, vec![mr]).await)
+}
+
+async fn handle_job_hook(state: &AppState, instance: &Instance, hook: JobHook) -> (StatusCode, Json<&'static str>) {
+    // Jobs reference their pipeline row, which the Pipeline Hook (or the poller) provides
+    let known = match state.db.pipeline_exists(instance.id(hook.pipeline_id)).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed to look up pipeline {} for job hook: {}", hook.pipeline_id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json("storage error"));
+        }
+    };
+    if !known {
+        return (StatusCode::OK, Json("pipeline not tracked"));
+    }
+
+    stored_response(monitor::insert_jobs(state, instance.scope_jobs(vec![hook.to_db_job()])).await)
+}
+
+/// 200 once the event is stored; a 500 has GitLab deliver it again.
+fn stored_response(stored: anyhow::Result<()>) -> (StatusCode, Json<&'static str>) {
+    match stored {
+        Ok(()) => (StatusCode::OK, Json("ok")),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json("storage error")),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}